@@ -0,0 +1,14 @@
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // A `protoc` picked with `PROTOC` wins, otherwise the vendored one is used so no system install is needed
+    if std::env::var_os("PROTOC").is_none() {
+        // SAFETY: the build script is single threaded, nothing else reads the environment concurrently
+        unsafe { std::env::set_var("PROTOC", protoc_bin_vendored::protoc_bin_path()?) };
+    }
+
+    tonic_build::configure()
+        .build_server(true)
+        .build_client(true)
+        .compile_protos(&["proto/items.proto", "proto/datas.proto"], &["proto"])?;
+
+    Ok(())
+}
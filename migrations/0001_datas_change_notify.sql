@@ -0,0 +1,18 @@
+-- Publishes every change to items.datas on the `datas_events` channel,
+-- consumed by the /api/datas/events SSE stream.
+CREATE OR REPLACE FUNCTION items.notify_datas_change() RETURNS trigger AS $$
+BEGIN
+    PERFORM pg_notify('datas_events', json_build_object(
+        'kind', CASE TG_OP WHEN 'INSERT' THEN 'created' WHEN 'UPDATE' THEN 'updated' ELSE 'deleted' END,
+        'id',   CASE TG_OP WHEN 'DELETE' THEN OLD.id ELSE NEW.id END,
+        'data', CASE TG_OP WHEN 'DELETE' THEN NULL ELSE row_to_json(NEW) END
+    )::text);
+
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+DROP TRIGGER IF EXISTS datas_notify ON items.datas;
+CREATE TRIGGER datas_notify
+    AFTER INSERT OR UPDATE OR DELETE ON items.datas
+    FOR EACH ROW EXECUTE FUNCTION items.notify_datas_change();
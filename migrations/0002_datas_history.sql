@@ -0,0 +1,13 @@
+-- Audit trail of every change made to items.datas through the API, written in the same transaction as the change
+
+CREATE TABLE IF NOT EXISTS items.datas_history (
+    id BIGSERIAL PRIMARY KEY,
+    datas_id INTEGER NOT NULL,
+    operation TEXT NOT NULL CHECK (operation IN ('insert', 'update', 'delete', 'restore')),
+    before JSONB,
+    after JSONB,
+    actor TEXT NOT NULL,
+    changed_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX IF NOT EXISTS datas_history_datas_id_idx ON items.datas_history (datas_id, id DESC);
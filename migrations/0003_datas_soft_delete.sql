@@ -0,0 +1,44 @@
+-- Deleting a datas row only marks it, the purge job removes it for good once the retention window passed
+
+ALTER TABLE items.datas ADD COLUMN IF NOT EXISTS deleted_at TIMESTAMPTZ;
+
+CREATE INDEX IF NOT EXISTS datas_deleted_at_idx ON items.datas (deleted_at) WHERE deleted_at IS NOT NULL;
+
+ALTER TABLE items.datas_history DROP CONSTRAINT IF EXISTS datas_history_operation_check;
+ALTER TABLE items.datas_history ADD CONSTRAINT datas_history_operation_check
+    CHECK (operation IN ('insert', 'update', 'delete', 'restore', 'purge'));
+
+-- Moving a row to the trash reads as a delete to event subscribers, taking it back out as a create
+CREATE OR REPLACE FUNCTION items.notify_datas_change() RETURNS trigger AS $$
+DECLARE
+    kind TEXT;
+BEGIN
+    IF TG_OP = 'INSERT' THEN
+        kind := 'created';
+    ELSIF TG_OP = 'DELETE' THEN
+        kind := 'deleted';
+    ELSIF OLD.deleted_at IS NULL AND NEW.deleted_at IS NOT NULL THEN
+        kind := 'deleted';
+    ELSIF OLD.deleted_at IS NOT NULL AND NEW.deleted_at IS NULL THEN
+        kind := 'created';
+    ELSIF NEW.deleted_at IS NOT NULL THEN
+        -- Changes to rows sitting in the trash aren't visible to anyone
+        RETURN NULL;
+    ELSE
+        kind := 'updated';
+    END IF;
+
+    -- A purge of a trashed row was already announced when it was trashed
+    IF TG_OP = 'DELETE' AND OLD.deleted_at IS NOT NULL THEN
+        RETURN NULL;
+    END IF;
+
+    PERFORM pg_notify('datas_events', json_build_object(
+        'kind', kind,
+        'id',   CASE TG_OP WHEN 'DELETE' THEN OLD.id ELSE NEW.id END,
+        'data', CASE kind WHEN 'deleted' THEN NULL ELSE row_to_json(NEW) END
+    )::text);
+
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
@@ -0,0 +1,11 @@
+-- Full-text search over datas names and niceties info, used by GET /api/search
+
+ALTER TABLE items.datas ADD COLUMN IF NOT EXISTS search tsvector
+    GENERATED ALWAYS AS (to_tsvector('english'::regconfig, coalesce(name, ''))) STORED;
+
+CREATE INDEX IF NOT EXISTS datas_search_idx ON items.datas USING GIN (search);
+
+ALTER TABLE items.niceties ADD COLUMN IF NOT EXISTS search tsvector
+    GENERATED ALWAYS AS (to_tsvector('english'::regconfig, coalesce(info, ''))) STORED;
+
+CREATE INDEX IF NOT EXISTS niceties_search_idx ON items.niceties USING GIN (search);
@@ -0,0 +1,11 @@
+-- API keys for the Postgres modes, only a SHA-256 of each key is kept
+
+CREATE TABLE IF NOT EXISTS items.api_keys (
+    id BIGSERIAL PRIMARY KEY,
+    name TEXT NOT NULL,
+    prefix TEXT NOT NULL,
+    key_hash TEXT NOT NULL UNIQUE,
+    scopes TEXT[] NOT NULL CHECK (scopes <@ ARRAY['items:read', 'items:write', 'admin']),
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    revoked_at TIMESTAMPTZ
+);
@@ -0,0 +1,4 @@
+-- Subject that created each datas row, only they or an admin may change it afterwards.
+-- Rows created without authentication keep a NULL owner and stay open to every writer.
+
+ALTER TABLE items.datas ADD COLUMN IF NOT EXISTS owner TEXT;
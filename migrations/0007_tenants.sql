@@ -0,0 +1,78 @@
+-- Every datas row belongs to a tenant. Row level security keeps each request to the tenant named by
+-- the `app.tenant` setting, which the server sets with `set_config('app.tenant', ..., true)` at the start
+-- of every transaction. Background jobs that work across tenants set `app.all_tenants` to 'on' instead.
+--
+-- Superusers and roles with BYPASSRLS skip these policies, run the server as an ordinary role.
+
+ALTER TABLE items.datas ADD COLUMN IF NOT EXISTS tenant_id TEXT NOT NULL DEFAULT 'default';
+ALTER TABLE items.datas_history ADD COLUMN IF NOT EXISTS tenant_id TEXT NOT NULL DEFAULT 'default';
+
+-- Rows written without a tenant in the setting are rejected by the NOT NULL constraint
+ALTER TABLE items.datas ALTER COLUMN tenant_id SET DEFAULT NULLIF(current_setting('app.tenant', true), '');
+ALTER TABLE items.datas_history ALTER COLUMN tenant_id SET DEFAULT NULLIF(current_setting('app.tenant', true), '');
+
+CREATE INDEX IF NOT EXISTS datas_tenant_idx ON items.datas (tenant_id);
+CREATE INDEX IF NOT EXISTS datas_history_tenant_idx ON items.datas_history (tenant_id, datas_id);
+
+-- The owner of a table skips its policies unless they are forced
+ALTER TABLE items.datas ENABLE ROW LEVEL SECURITY;
+ALTER TABLE items.datas FORCE ROW LEVEL SECURITY;
+ALTER TABLE items.datas_history ENABLE ROW LEVEL SECURITY;
+ALTER TABLE items.datas_history FORCE ROW LEVEL SECURITY;
+ALTER TABLE items.niceties ENABLE ROW LEVEL SECURITY;
+ALTER TABLE items.niceties FORCE ROW LEVEL SECURITY;
+
+DROP POLICY IF EXISTS tenant_isolation ON items.datas;
+CREATE POLICY tenant_isolation ON items.datas
+    USING (tenant_id = current_setting('app.tenant', true) OR current_setting('app.all_tenants', true) = 'on')
+    WITH CHECK (tenant_id = current_setting('app.tenant', true) OR current_setting('app.all_tenants', true) = 'on');
+
+DROP POLICY IF EXISTS tenant_isolation ON items.datas_history;
+CREATE POLICY tenant_isolation ON items.datas_history
+    USING (tenant_id = current_setting('app.tenant', true) OR current_setting('app.all_tenants', true) = 'on')
+    WITH CHECK (tenant_id = current_setting('app.tenant', true) OR current_setting('app.all_tenants', true) = 'on');
+
+-- Niceties follow the datas row they hang off, whose own policy applies inside the subquery
+DROP POLICY IF EXISTS tenant_isolation ON items.niceties;
+CREATE POLICY tenant_isolation ON items.niceties
+    USING (EXISTS (SELECT 1 FROM items.datas d WHERE d.id = datas_id))
+    WITH CHECK (EXISTS (SELECT 1 FROM items.datas d WHERE d.id = datas_id));
+
+-- An API key may be bound to one tenant, NULL lets the request pick it
+ALTER TABLE items.api_keys ADD COLUMN IF NOT EXISTS tenant_id TEXT;
+
+-- Events carry the tenant so subscribers only see their own
+CREATE OR REPLACE FUNCTION items.notify_datas_change() RETURNS trigger AS $$
+DECLARE
+    kind TEXT;
+BEGIN
+    IF TG_OP = 'INSERT' THEN
+        kind := 'created';
+    ELSIF TG_OP = 'DELETE' THEN
+        kind := 'deleted';
+    ELSIF OLD.deleted_at IS NULL AND NEW.deleted_at IS NOT NULL THEN
+        kind := 'deleted';
+    ELSIF OLD.deleted_at IS NOT NULL AND NEW.deleted_at IS NULL THEN
+        kind := 'created';
+    ELSIF NEW.deleted_at IS NOT NULL THEN
+        -- Changes to rows sitting in the trash aren't visible to anyone
+        RETURN NULL;
+    ELSE
+        kind := 'updated';
+    END IF;
+
+    -- A purge of a trashed row was already announced when it was trashed
+    IF TG_OP = 'DELETE' AND OLD.deleted_at IS NOT NULL THEN
+        RETURN NULL;
+    END IF;
+
+    PERFORM pg_notify('datas_events', json_build_object(
+        'kind',   kind,
+        'id',     CASE TG_OP WHEN 'DELETE' THEN OLD.id ELSE NEW.id END,
+        'data',   CASE kind WHEN 'deleted' THEN NULL ELSE row_to_json(NEW) END,
+        'tenant', CASE TG_OP WHEN 'DELETE' THEN OLD.tenant_id ELSE NEW.tenant_id END
+    )::text);
+
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
@@ -0,0 +1,7 @@
+-- Names identify datas rows synced from external systems, PUT /api/datas/by-name/{name} upserts on them.
+-- They are unique within a tenant, trashed rows included until they are purged.
+--
+-- Fails while a tenant still holds duplicate names, rename or delete them first.
+
+ALTER TABLE items.datas DROP CONSTRAINT IF EXISTS datas_tenant_name_key;
+ALTER TABLE items.datas ADD CONSTRAINT datas_tenant_name_key UNIQUE (tenant_id, name);
@@ -0,0 +1,11 @@
+-- Idempotency-Key records of the Postgres modes when no Redis is configured for them.
+-- `response` stays NULL while the first request runs, rows past `expires_at` are free to claim again.
+
+CREATE TABLE IF NOT EXISTS items.idempotency_keys (
+    key TEXT PRIMARY KEY,
+    fingerprint TEXT NOT NULL,
+    response JSONB,
+    expires_at TIMESTAMPTZ NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS idempotency_keys_expires_at_idx ON items.idempotency_keys (expires_at);
@@ -0,0 +1,55 @@
+-- Change events carry only the columns the API returns, the search vector, owner and trash marker stay out.
+-- NOTIFY payloads must be shorter than 8000 bytes, so a row too large for one is announced by id with
+-- `refetch` set and listeners read it back.
+CREATE OR REPLACE FUNCTION items.notify_datas_change() RETURNS trigger AS $$
+DECLARE
+    kind TEXT;
+    row_id INTEGER;
+    row_tenant TEXT;
+    payload TEXT;
+BEGIN
+    IF TG_OP = 'INSERT' THEN
+        kind := 'created';
+    ELSIF TG_OP = 'DELETE' THEN
+        kind := 'deleted';
+    ELSIF OLD.deleted_at IS NULL AND NEW.deleted_at IS NOT NULL THEN
+        kind := 'deleted';
+    ELSIF OLD.deleted_at IS NOT NULL AND NEW.deleted_at IS NULL THEN
+        kind := 'created';
+    ELSIF NEW.deleted_at IS NOT NULL THEN
+        -- Changes to rows sitting in the trash aren't visible to anyone
+        RETURN NULL;
+    ELSE
+        kind := 'updated';
+    END IF;
+
+    -- A purge of a trashed row was already announced when it was trashed
+    IF TG_OP = 'DELETE' AND OLD.deleted_at IS NOT NULL THEN
+        RETURN NULL;
+    END IF;
+
+    IF TG_OP = 'DELETE' THEN
+        row_id := OLD.id;
+        row_tenant := OLD.tenant_id;
+    ELSE
+        row_id := NEW.id;
+        row_tenant := NEW.tenant_id;
+    END IF;
+
+    payload := json_build_object(
+        'kind',   kind,
+        'id',     row_id,
+        'data',   CASE kind WHEN 'deleted' THEN NULL
+                  ELSE json_build_object('id', NEW.id, 'name', NEW.name, 'flags', NEW.flags, 'sys', NEW.sys) END,
+        'tenant', row_tenant
+    )::text;
+
+    IF octet_length(payload) >= 8000 THEN
+        payload := json_build_object('kind', kind, 'id', row_id, 'refetch', true, 'tenant', row_tenant)::text;
+    END IF;
+
+    PERFORM pg_notify('datas_events', payload);
+
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
@@ -1,24 +1,545 @@
-use axum::{extract::{Path, State}, response::IntoResponse, http::StatusCode, Json};
-use redis::AsyncCommands;
+use std::{collections::{HashMap, HashSet}, sync::LazyLock, time::{Duration, SystemTime, UNIX_EPOCH}};
+use axum::{extract::{ws::WebSocketUpgrade, Path, Query, State}, response::{IntoResponse, Response}, http::{HeaderMap, HeaderValue, StatusCode}};
+use redis::{aio::ConnectionLike, streams::{StreamAutoClaimOptions, StreamAutoClaimReply, StreamId, StreamMaxlen, StreamRangeReply, StreamReadOptions, StreamReadReply}, AsyncCommands, Script, SetExpiry, SetOptions};
 use serde_json::{from_str, to_string};
-use crate::{error::redis::*, prelude::redis::*};
+use crate::{auth::Principal, codec::{Accept, Negotiated, Payload}, error::redis::*, events::{self, ChangeEvent, ChangeKind, Feed, ITEMS_CHANNEL, ITEMS_STREAM}, export::{self, ExportQuery}, idempotency::IdempotencyKey, openapi::ApiErrors, prelude::redis::*, tenant::Tenant, ws};
+
+/// Every tenant that has stored an item, so background jobs can visit each keyspace
+pub const TENANTS_KEY: &str = "tenants";
 
 const NEXT_ID_KEY: &str = "next_item_id";
 const ITEM_INDEX_KEY: &str = "items_index";
 
-fn item_key(id: usize) -> String {
-    format!("item:{}", id)
+/// Sorted sets of item keys scored by the matching field
+const COUNT_INDEX_KEY: &str = "items_by_count";
+const HEIGHT_INDEX_KEY: &str = "items_by_height";
+const WEIGHT_INDEX_KEY: &str = "items_by_weight";
+
+/// Lexicographic index of `{lowercased name}\0{item key}` members, all scored 0
+const NAME_INDEX_KEY: &str = "items_by_name";
+
+/// The `items_by_name` member of each item key, so a rename or delete can drop the old one
+const NAME_MEMBERS_KEY: &str = "items_name_members";
+
+/// Item id to the subject that created it, items created without authentication have no entry
+const OWNERS_KEY: &str = "items_owners";
+
+/// Sorted set of `{tenant}:trash:item:{id}` keys, scored by the unix time they were deleted at
+const TRASH_KEY: &str = "items_trash";
+const PURGE_BATCH: isize = 500;
+const EXPORT_BATCH: usize = 500;
+
+/// Approximate number of entries kept in the `items:events` stream
+const EVENT_LOG_MAXLEN: usize = 100_000;
+const EVENT_FIELD: &str = "event";
+const DEFAULT_EVENT_PAGE: usize = 100;
+const MAX_EVENT_PAGE: usize = 1000;
+const MAX_BLOCK_MS: usize = 30_000;
+
+/// How often a PUT retries when the item changes between its check and its write
+const MAX_WATCH_ATTEMPTS: usize = 3;
+
+/// Lists the keys that were skipped because they could not be decoded
+const UNDECODABLE_HEADER: &str = "x-undecodable-items";
+
+/// HSET the given fields only if the item still exists, so a PATCH never resurrects a deleted item
+static PATCH_HASH: LazyLock<Script> = LazyLock::new(|| Script::new(r"
+    if redis.call('EXISTS', KEYS[1]) == 0 then
+        return 0
+    end
+    redis.call('HSET', KEYS[1], unpack(ARGV))
+    return 1
+"));
+
+/// Moves `item:{id}` to the trash and out of the index, 0 when there is no such item
+static TRASH_ITEM: LazyLock<Script> = LazyLock::new(|| Script::new(r"
+    if redis.call('EXISTS', KEYS[1]) == 0 then
+        return 0
+    end
+    redis.call('RENAME', KEYS[1], KEYS[2])
+    redis.call('SREM', KEYS[3], KEYS[1])
+    redis.call('ZADD', KEYS[4], ARGV[1], KEYS[2])
+    return 1
+"));
+
+/// Moves a trashed item back, 0 when it isn't in the trash and -1 when its id is taken again
+static RESTORE_ITEM: LazyLock<Script> = LazyLock::new(|| Script::new(r"
+    if redis.call('EXISTS', KEYS[1]) == 0 then
+        redis.call('ZREM', KEYS[4], KEYS[1])
+        return 0
+    end
+    if redis.call('EXISTS', KEYS[2]) == 1 then
+        return -1
+    end
+    redis.call('RENAME', KEYS[1], KEYS[2])
+    redis.call('ZREM', KEYS[4], KEYS[1])
+    redis.call('SADD', KEYS[3], KEYS[2])
+    return 1
+"));
+
+/// The Redis keys of one tenant, each prefixed with `{tenant}:` so tenants never see each other's items
+#[derive(Debug, Clone)]
+pub struct Keyspace(String);
+
+impl Keyspace {
+    pub fn new(tenant: &str) -> Self {
+        Self(tenant.to_string())
+    }
+
+    pub fn tenant(&self) -> &str {
+        &self.0
+    }
+
+    /// The tenant's own copy of a key such as `items_index`
+    pub fn key(&self, name: &str) -> String {
+        format!("{}:{}", self.0, name)
+    }
+
+    pub fn item(&self, id: usize) -> String {
+        format!("{}:item:{}", self.0, id)
+    }
+
+    /// Where a deleted item is kept until it is restored or purged
+    pub fn trash_item(&self, id: usize) -> String {
+        format!("{}:trash:item:{}", self.0, id)
+    }
+}
+
+impl From<&Tenant> for Keyspace {
+    fn from(tenant: &Tenant) -> Self {
+        Self::new(&tenant.0)
+    }
+}
+
+/// The `items_by_name` member of an item, prefix searches match against the lowercased name
+pub fn name_member(space: &Keyspace, item: &Item) -> String {
+    format!("{}\0{}", item.name.to_lowercase(), space.item(item.id))
+}
+
+/// Queues the secondary index entries of an item, dropping its `previous` name entry if the name changed
+pub fn queue_index(pipe: &mut redis::Pipeline, space: &Keyspace, item: &Item, previous: Option<&str>) {
+    let key = space.item(item.id);
+    let member = name_member(space, item);
+
+    if let Some(previous) = previous.filter(|previous| *previous != member) {
+        pipe.zrem(space.key(NAME_INDEX_KEY), previous).ignore();
+    }
+
+    pipe.zadd(space.key(COUNT_INDEX_KEY), &key, item.count).ignore()
+        .zadd(space.key(HEIGHT_INDEX_KEY), &key, item.height).ignore()
+        .zadd(space.key(WEIGHT_INDEX_KEY), &key, item.weight).ignore()
+        .zadd(space.key(NAME_INDEX_KEY), &member, 0).ignore()
+        .hset(space.key(NAME_MEMBERS_KEY), &key, &member).ignore();
+}
+
+/// The name index member currently recorded for an item
+async fn indexed_name<C>(con: &mut C, space: &Keyspace, id: usize) -> Result<Option<String>>
+where
+    C: ConnectionLike + Send + Sync
+{
+    Ok(con.hget(space.key(NAME_MEMBERS_KEY), space.item(id)).await?)
+}
+
+/// Removes item keys from the id index and every secondary index
+async fn drop_from_indexes<C>(con: &mut C, space: &Keyspace, keys: &[String]) -> Result<()>
+where
+    C: ConnectionLike + Send + Sync
+{
+    if keys.is_empty() {
+        return Ok(());
+    }
+
+    let members: Vec<Option<String>> = redis::cmd("HMGET").arg(space.key(NAME_MEMBERS_KEY)).arg(keys).query_async(&mut *con).await?;
+    let members: Vec<String> = members.into_iter().flatten().collect();
+
+    let mut pipe = redis::pipe();
+    pipe.atomic()
+        .srem(space.key(ITEM_INDEX_KEY), keys).ignore()
+        .zrem(space.key(COUNT_INDEX_KEY), keys).ignore()
+        .zrem(space.key(HEIGHT_INDEX_KEY), keys).ignore()
+        .zrem(space.key(WEIGHT_INDEX_KEY), keys).ignore()
+        .hdel(space.key(NAME_MEMBERS_KEY), keys).ignore();
+
+    if !members.is_empty() {
+        pipe.zrem(space.key(NAME_INDEX_KEY), &members).ignore();
+    }
+
+    pipe.query_async::<()>(&mut *con).await?;
+
+    Ok(())
+}
+
+/// Answers a filter from the secondary indexes, intersecting the keys each range matches
+async fn filtered_keys<C>(con: &mut C, space: &Keyspace, filter: &ItemFilter) -> Result<Vec<String>>
+where
+    C: ConnectionLike + Send + Sync
+{
+    fn bound(value: Option<usize>, unbounded: &str) -> String {
+        value.map(|v| v.to_string()).unwrap_or_else(|| unbounded.to_string())
+    }
+
+    let ranges = [
+        (COUNT_INDEX_KEY, filter.min_count, filter.max_count),
+        (HEIGHT_INDEX_KEY, filter.min_height, filter.max_height),
+        (WEIGHT_INDEX_KEY, filter.min_weight, filter.max_weight),
+    ];
+
+    let mut pipe = redis::pipe();
+    for (index, min, max) in ranges {
+        if min.is_some() || max.is_some() {
+            pipe.zrangebyscore(space.key(index), bound(min, "-inf"), bound(max, "+inf"));
+        }
+    }
+
+    if let Some(prefix) = &filter.name_prefix {
+        // 0xff never occurs in UTF-8, so it sorts after every name sharing the prefix
+        let prefix = prefix.to_lowercase();
+        let mut max = format!("[{}", prefix).into_bytes();
+        max.push(0xff);
+
+        pipe.zrangebylex(space.key(NAME_INDEX_KEY), format!("[{}", prefix), max);
+    }
+
+    let mut matches: Vec<Vec<String>> = pipe.query_async(&mut *con).await?;
+
+    if filter.name_prefix.is_some()
+        && let Some(members) = matches.last_mut()
+    {
+        for member in members.iter_mut() {
+            if let Some((_, key)) = member.rsplit_once('\0') {
+                *member = key.to_string();
+            }
+        }
+    }
+
+    matches.sort_by_key(|keys| keys.len());
+    let mut matches = matches.into_iter();
+    let Some(smallest) = matches.next() else { return Ok(Vec::new()) };
+
+    let others: Vec<HashSet<String>> = matches.map(|keys| keys.into_iter().collect()).collect();
+
+    Ok(smallest.into_iter().filter(|key| others.iter().all(|keys| keys.contains(key))).collect())
+}
+
+fn unix_now() -> i64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs() as i64).unwrap_or_default()
+}
+
+pub fn item_fields(item: &Item) -> [(&'static str, String); 6] {
+    [
+        ("id", item.id.to_string()),
+        ("name", item.name.clone()),
+        ("description", item.description.clone()),
+        ("count", item.count.to_string()),
+        ("height", item.height.to_string()),
+        ("weight", item.weight.to_string()),
+    ]
+}
+
+/// Decodes an item hash, explaining which field is missing or malformed when it can't
+pub fn item_from_hash(fields: &HashMap<String, String>) -> std::result::Result<Item, String> {
+    fn field<'a>(fields: &'a HashMap<String, String>, name: &str) -> std::result::Result<&'a String, String> {
+        fields.get(name).ok_or_else(|| format!("missing field '{}'", name))
+    }
+
+    fn number(fields: &HashMap<String, String>, name: &str) -> std::result::Result<usize, String> {
+        field(fields, name)?.parse().map_err(|_| format!("field '{}' is not a number", name))
+    }
+
+    Ok(Item {
+        id: number(fields, "id")?,
+        name: field(fields, "name")?.clone(),
+        description: field(fields, "description")?.clone(),
+        count: number(fields, "count")?,
+        height: number(fields, "height")?,
+        weight: number(fields, "weight")?,
+        ttl_seconds: None,
+    })
+}
+
+/// Maps a `TTL` reply to the remaining seconds, `None` when the key doesn't expire
+fn remaining_ttl(ttl: i64) -> Option<i64> {
+    (ttl >= 0).then_some(ttl)
+}
+
+/// Queues the write of a whole item in the configured layout.
+///
+/// `Some(0)` clears the expiry, `Some(n)` expires the item after `n` seconds, `None` leaves the expiry alone.
+fn write_item(pipe: &mut redis::Pipeline, layout: Layout, space: &Keyspace, item: &Item, ttl: Option<u64>) -> Result<()> {
+    let key = space.item(item.id);
+
+    match layout {
+        Layout::Json => {
+            let item_json = to_string(&Item { ttl_seconds: None, ..item.clone() })?;
+
+            let expiry = match ttl {
+                Some(0) => None,
+                Some(secs) => Some(SetExpiry::EX(secs)),
+                None => Some(SetExpiry::KEEPTTL)
+            };
+
+            match expiry {
+                Some(expiry) => pipe.set_options(&key, item_json, SetOptions::default().with_expiration(expiry)).ignore(),
+                None => pipe.set(&key, item_json).ignore()
+            };
+        }
+        Layout::Hash => {
+            pipe.hset_multiple(&key, &item_fields(item)).ignore();
+            queue_expiry(pipe, &key, ttl);
+        }
+    }
+
+    Ok(())
+}
+
+/// Rejects an expiry Redis would refuse, it keeps deadlines as milliseconds since the epoch in an `i64`
+fn check_ttl(ttl: Option<u64>) -> Result<()> {
+    let now_ms = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_millis() as u64).unwrap_or(0);
+    let max_secs = (i64::MAX as u64 - now_ms) / 1000;
+
+    match ttl {
+        Some(secs) if secs > max_secs => Err(Error::BadRequest(format!("ttl_seconds must be at most {}", max_secs))),
+        _ => Ok(())
+    }
+}
+
+/// Queues the expiry change of `key`, `ttl` has been through [`check_ttl`]
+fn queue_expiry(pipe: &mut redis::Pipeline, key: &str, ttl: Option<u64>) {
+    match ttl {
+        Some(0) => {
+            pipe.persist(key).ignore();
+        }
+        Some(secs) => {
+            pipe.expire(key, secs as i64).ignore();
+        }
+        None => {}
+    }
+}
+
+/// What came back from loading a batch of item keys
+struct Loaded {
+    items: Vec<Item>,
+    /// Keys holding something that isn't a valid item, with the reason
+    undecodable: Vec<String>,
+    /// Keys that no longer exist, because they expired or were deleted in the meantime
+    missing: Vec<String>
+}
+
+/// Loads the items stored under `keys`, along with their remaining TTL.
+///
+/// Keys holding something that can't be decoded as an item are returned separately so callers
+/// can report them instead of dropping them.
+async fn read_items<C>(con: &mut C, layout: Layout, keys: &[String]) -> Result<Loaded>
+where
+    C: ConnectionLike + Send + Sync
+{
+    let mut loaded = Loaded {
+        items: Vec::with_capacity(keys.len()),
+        undecodable: Vec::new(),
+        missing: Vec::new()
+    };
+
+    if keys.is_empty() {
+        return Ok(loaded);
+    }
+
+    let Loaded { items, undecodable, missing } = &mut loaded;
+
+    // The key each loaded item came from, a trashed item's TTL lives on its trash key
+    let mut found: Vec<&String> = Vec::with_capacity(keys.len());
+
+    match layout {
+        Layout::Json => {
+            let items_json: Vec<Option<String>> = con.mget(keys).await?;
+
+            for (key, json_str) in keys.iter().zip(items_json) {
+                let Some(json_str) = json_str else {
+                    missing.push(key.clone());
+                    continue;
+                };
+
+                match from_str::<Item>(&json_str) {
+                    Ok(item) => {
+                        items.push(item);
+                        found.push(key);
+                    }
+                    Err(e) => undecodable.push(format!("{} ({})", key, e))
+                }
+            }
+        }
+        Layout::Hash => {
+            let mut pipe = redis::pipe();
+            for key in keys {
+                pipe.cmd("TYPE").arg(key);
+            }
+            let types: Vec<String> = pipe.query_async(&mut *con).await?;
+
+            let mut pipe = redis::pipe();
+            let mut hashed = Vec::with_capacity(keys.len());
+            for (key, kind) in keys.iter().zip(types) {
+                match kind.as_str() {
+                    "hash" => {
+                        pipe.hgetall(key);
+                        hashed.push(key);
+                    }
+                    "none" => missing.push(key.clone()),
+                    other => undecodable.push(format!("{} (stored as {}, run redis-migrate)", key, other))
+                }
+            }
+
+            if !hashed.is_empty() {
+                let hashes: Vec<HashMap<String, String>> = pipe.query_async(&mut *con).await?;
+
+                for (key, fields) in hashed.into_iter().zip(hashes) {
+                    match item_from_hash(&fields) {
+                        Ok(item) => {
+                            items.push(item);
+                            found.push(key);
+                        }
+                        Err(e) => undecodable.push(format!("{} ({})", key, e))
+                    }
+                }
+            }
+        }
+    }
+
+    if !items.is_empty() {
+        let mut pipe = redis::pipe();
+        for key in found {
+            pipe.ttl(key);
+        }
+
+        let ttls: Vec<i64> = pipe.query_async(&mut *con).await?;
+        for (item, ttl) in items.iter_mut().zip(ttls) {
+            item.ttl_seconds = remaining_ttl(ttl);
+        }
+    }
+
+    Ok(loaded)
+}
+
+async fn read_item<C>(con: &mut C, layout: Layout, space: &Keyspace, id: usize) -> Result<Item>
+where
+    C: ConnectionLike + Send + Sync
+{
+    let mut loaded = read_items(con, layout, &[space.item(id)]).await?;
+
+    if let Some(reason) = loaded.undecodable.into_iter().next() {
+        return Err(Error::Undecodable(reason));
+    }
+
+    loaded.items.pop().ok_or_else(|| Error::NotFound(format!("Item ID: {}", id)))
+}
+
+/// Records a change in the tenant's `items:events` stream and publishes it so the `/api/items/events`
+/// subscribers of every instance see it. The write already happened, so a failure is only logged.
+async fn publish_change<C>(con: &mut C, space: &Keyspace, kind: ChangeKind, id: usize, item: Option<&Item>)
+where
+    C: ConnectionLike + Send + Sync
+{
+    let event = ChangeEvent { kind, id: id as i64, data: item.cloned(), tenant: Some(space.tenant().to_string()) };
+
+    let published = match to_string(&event) {
+        Ok(event_json) => {
+            redis::pipe()
+                .xadd_maxlen(space.key(ITEMS_STREAM), StreamMaxlen::Approx(EVENT_LOG_MAXLEN), "*", &[(EVENT_FIELD, &event_json)]).ignore()
+                .publish(ITEMS_CHANNEL, &event_json).ignore()
+                .query_async::<()>(con)
+                .await
+                .map_err(Error::from)
+        }
+        Err(e) => Err(Error::from(e))
+    };
+
+    if let Err(e) = published {
+        tracing::error!("Failed to record {} event for item {}: {:?}", kind.as_str(), id, e);
+    }
+}
+
+/// Decodes stream entries, keeping the ids of those that can't be decoded so they can still be acknowledged
+fn event_page(entries: Vec<StreamId>) -> EventPage {
+    let mut page = EventPage { next: entries.last().map(|entry| entry.id.clone()), ..Default::default() };
+
+    for entry in entries {
+        let event = entry.get::<String>(EVENT_FIELD)
+            .ok_or_else(|| format!("missing '{}' field", EVENT_FIELD))
+            .and_then(|json| from_str::<ChangeEvent<Item>>(&json).map_err(|e| e.to_string()));
+
+        match event {
+            Ok(event) => page.events.push(StreamEvent { stream_id: entry.id, event }),
+            Err(reason) => {
+                tracing::warn!("Undecodable stream entry {}: {}", entry.id, reason);
+                page.undecodable.push(entry.id);
+            }
+        }
+    }
+
+    page
+}
+
+/// Stream ids are `<ms>` or `<ms>-<seq>`
+fn validate_stream_id(id: &str) -> Result<()> {
+    let mut parts = id.splitn(2, '-');
+    let valid = parts.all(|part| !part.is_empty() && part.bytes().all(|b| b.is_ascii_digit()));
+
+    if valid {
+        Ok(())
+    }
+    else {
+        Err(Error::BadRequest(format!("Invalid stream id: {}", id)))
+    }
+}
+
+/// Maps the error Redis gives for an unknown consumer group to a 404
+fn map_group_error(group: &str, e: redis::RedisError) -> Error {
+    if e.code() == Some("NOGROUP") {
+        Error::NotFound(format!("Consumer group: {}", group))
+    }
+    else {
+        Error::from(e)
+    }
+}
+
+/// Refuses the change unless `principal` created item `id` or is an admin
+async fn check_owner<C>(con: &mut C, space: &Keyspace, id: usize, principal: Option<&Principal>) -> Result<()>
+where
+    C: ConnectionLike + Send + Sync
+{
+    let Some(principal) = principal.filter(|p| !p.is_admin()) else {
+        return Ok(());
+    };
+
+    let owner: Option<String> = con.hget(space.key(OWNERS_KEY), id).await?;
+
+    if principal.may_modify(owner.as_deref()) {
+        Ok(())
+    }
+    else {
+        Err(Error::Forbidden(format!("Item {} belongs to another subject", id)))
+    }
 }
 
 /// POST /api/items - Create a new item
+#[utoipa::path(
+    post, path = "/api/items", tag = "items",
+    params(IdempotencyKey),
+    request_body = CreateItemPayload,
+    responses((status = CREATED, body = Item), ApiErrors)
+)]
 pub async fn create_item(
     State(state): State<AppState>,
-    Json(payload): Json<CreateItemPayload>,
-) -> Result<impl IntoResponse> {
+    tenant: Tenant,
+    principal: Option<Principal>,
+    Accept(format): Accept,
+    Payload(payload): Payload<CreateItemPayload>,
+) -> Result<(StatusCode, Negotiated<Item>)> {
+    check_ttl(payload.ttl_seconds)?;
+
     let mut con = state.redis_pool.get().await.map_err(map_pool_error)?;
+    let space = Keyspace::from(&tenant);
 
     // Get a new unique ID atomically
-    let new_id: usize = con.incr(NEXT_ID_KEY, 1).await?;
+    let new_id: usize = con.incr(space.key(NEXT_ID_KEY), 1).await?;
 
     // Create the full Item struct
     let new_item = Item {
@@ -28,75 +549,176 @@ pub async fn create_item(
         count: payload.count,
         height: payload.height,
         weight: payload.weight,
+        ttl_seconds: payload.ttl_seconds.filter(|&secs| secs > 0).map(|secs| secs as i64),
     };
 
-    // Serialize the item to JSON
-    let item_json = to_string(&new_item)?;
-    let key = item_key(new_id);
+    // Store the item and register it in the index together
+    let mut pipe = redis::pipe();
+    pipe.atomic();
+    write_item(&mut pipe, state.layout, &space, &new_item, payload.ttl_seconds)?;
+    pipe.sadd(space.key(ITEM_INDEX_KEY), space.item(new_id)).ignore()
+        .sadd(TENANTS_KEY, space.tenant()).ignore();
+    queue_index(&mut pipe, &space, &new_item, None);
+    if let Some(principal) = &principal {
+        pipe.hset(space.key(OWNERS_KEY), new_id, &principal.subject).ignore();
+    }
+    pipe.query_async::<()>(&mut *con).await?;
+
+    publish_change(&mut *con, &space, ChangeKind::Created, new_id, Some(&new_item)).await;
 
-    // Store the item in Redis
-    con.set::<_, _, ()>(&key[..], item_json).await?;
-    
-    Ok((StatusCode::CREATED, Json(new_item)))
+    Ok((StatusCode::CREATED, Negotiated(format, new_item)))
 }
 
-/// GET /api/items - List all items
-pub async fn get_items(State(state): State<AppState>) -> Result<Json<Vec<Item>>> {
+/// GET /api/items - List all items, or those matching `min_/max_` count, height, weight and `name_prefix`
+///
+/// Filters are answered from the secondary indexes instead of loading every item.
+/// Entries that can't be decoded are left out of the body and listed in `X-Undecodable-Items`.
+/// Index entries whose item has expired are pruned on the way.
+#[utoipa::path(
+    get, path = "/api/items", tag = "items",
+    params(ItemFilter),
+    responses(
+        (status = OK, body = Vec<Item>, headers(("x-undecodable-items" = String, description = "Keys of items that could not be decoded"))),
+        ApiErrors
+    )
+)]
+pub async fn get_items(
+    State(state): State<AppState>,
+    tenant: Tenant,
+    Query(filter): Query<ItemFilter>,
+    Accept(format): Accept,
+) -> Result<(HeaderMap, Negotiated<Vec<Item>>)> {
     let mut con = state.redis_pool.get().await.map_err(map_pool_error)?;
+    let space = Keyspace::from(&tenant);
 
-    // Get all item keys from the index set
-    let item_keys: Vec<String> = con.smembers(ITEM_INDEX_KEY).await?;
+    let item_keys: Vec<String> = if filter.is_empty() {
+        con.smembers(space.key(ITEM_INDEX_KEY)).await?
+    }
+    else {
+        filtered_keys(&mut *con, &space, &filter).await?
+    };
 
-    if item_keys.is_empty() {
-        return Ok(Json(Vec::new()));
+    let Loaded { mut items, undecodable, missing } = read_items(&mut *con, state.layout, &item_keys).await?;
+    drop_from_indexes(&mut *con, &space, &missing).await?;
+
+    // An index entry can lag behind a concurrent write, so the loaded items have the final say
+    items.retain(|item| filter.matches(item));
+
+    let mut headers = HeaderMap::new();
+    if !undecodable.is_empty() {
+        tracing::warn!("Skipped {} undecodable items: {:?}", undecodable.len(), undecodable);
+
+        if let Ok(value) = HeaderValue::from_str(&undecodable.join(", ")) {
+            headers.insert(UNDECODABLE_HEADER, value);
+        }
     }
 
-    // Fetch all items using MGET (efficiently gets multiple keys)
-    let items_json: Vec<Option<String>> = con.mget(item_keys).await?;
+    Ok((headers, Negotiated(format, items)))
+}
+
+/// GET /api/items/export - Stream every item as NDJSON, CSV or a JSON array
+///
+/// Walks the tenant's keys with SCAN and fetches each batch at once, so only one batch is held at a time.
+#[utoipa::path(
+    get, path = "/api/items/export", tag = "items",
+    params(ExportQuery),
+    responses(
+        (status = OK, content((Item = "application/x-ndjson"), (String = "text/csv"), (Vec<Item> = "application/json"))),
+        ApiErrors
+    )
+)]
+pub async fn export_items(
+    State(state): State<AppState>,
+    tenant: Tenant,
+    Query(query): Query<ExportQuery>,
+) -> Result<Response> {
+    let mut con = state.redis_pool.get_owned().await.map_err(map_pool_error)?;
+    let layout = state.layout;
+    let pattern = Keyspace::from(&tenant).key("item:*");
 
-    // Deserialize JSON strings into Item structs, filtering out None values
-    // (in case an item was deleted but MGET ran before SREM finished, though unlikely with atomic DEL)
-    let items: Vec<Item> = items_json
-        .into_iter()
-        .flatten()
-        .filter_map(|json_str| from_str(&json_str).ok())
-        .collect();
+    let rows = async_stream::stream! {
+        let mut cursor = 0u64;
+
+        loop {
+            let scanned: redis::RedisResult<(u64, Vec<String>)> = redis::cmd("SCAN")
+                .arg(cursor)
+                .arg("MATCH")
+                .arg(&pattern)
+                .arg("COUNT")
+                .arg(EXPORT_BATCH)
+                .query_async(&mut *con)
+                .await;
+
+            let (next, keys) = match scanned {
+                Ok(x) => x,
+                Err(e) => {
+                    yield Err(Error::from(e));
+                    return;
+                }
+            };
+
+            match read_items(&mut *con, layout, &keys).await {
+                Ok(Loaded { items, undecodable, .. }) => {
+                    if !undecodable.is_empty() {
+                        tracing::warn!("Export skipped {} undecodable items: {:?}", undecodable.len(), undecodable);
+                    }
+
+                    for item in items {
+                        yield Ok(item);
+                    }
+                }
+                Err(e) => {
+                    yield Err(e);
+                    return;
+                }
+            }
+
+            if next == 0 {
+                break;
+            }
+            cursor = next;
+        }
+    };
 
-    Ok(Json(items))
+    Ok(export::stream_response(query.format, rows))
 }
 
 /// GET /api/items/{id} - Get a specific item by ID
+#[utoipa::path(get, path = "/api/items/{id}", tag = "items", params(("id" = usize, Path, description = "Item id")), responses((status = OK, body = Item), ApiErrors))]
 pub async fn get_item(
     State(state): State<AppState>,
+    tenant: Tenant,
     Path(id): Path<usize>,
-) -> Result<Json<Item>> {
+    Accept(format): Accept,
+) -> Result<Negotiated<Item>> {
     let mut con = state.redis_pool.get().await.map_err(map_pool_error)?;
-    let key = item_key(id);
-
-    let item_json: Option<String> = con.get(&key[..]).await?;
+    let space = Keyspace::from(&tenant);
+    let item = read_item(&mut *con, state.layout, &space, id).await?;
 
-    match item_json {
-        Some(json_str) => {
-            Ok(Json(from_str(&json_str)?))
-        },
-        None => Err(Error::NotFound(format!("Item ID: {}", id)))
-    }
+    Ok(Negotiated(format, item))
 }
 
 /// PUT /api/items/{id} - Update an existing item
+#[utoipa::path(
+    put, path = "/api/items/{id}", tag = "items",
+    params(("id" = usize, Path, description = "Item id")),
+    request_body = CreateItemPayload,
+    responses((status = OK, body = Item), ApiErrors)
+)]
 pub async fn update_item(
     State(state): State<AppState>,
+    tenant: Tenant,
     Path(id): Path<usize>,
-    Json(payload): Json<CreateItemPayload>
-) -> Result<Json<Item>> {
-    let mut con = state.redis_pool.get().await.map_err(map_pool_error)?;
-    let key = item_key(id);
+    principal: Option<Principal>,
+    Accept(format): Accept,
+    Payload(payload): Payload<CreateItemPayload>
+) -> Result<Negotiated<Item>> {
+    check_ttl(payload.ttl_seconds)?;
 
-    // Check if item exists before updating (optional, depends on desired PUT semantics)
-    let exists: bool = con.exists(&key).await?;
-    if !exists {
-        return Err(Error::NotFound(format!("Item ID: {}", id)));
-    }
+    let mut con = state.redis_pool.get().await.map_err(map_pool_error)?;
+    let space = Keyspace::from(&tenant);
+    let key = space.item(id);
+    check_owner(&mut *con, &space, id, principal.as_ref()).await?;
 
     // Create the updated item struct (ensure ID remains the same)
     let updated_item = Item {
@@ -106,35 +728,455 @@ pub async fn update_item(
         count: payload.count,
         height: payload.height,
         weight: payload.weight,
+        ttl_seconds: None,
     };
 
-    // Serialize and overwrite the item in Redis
-    let item_json = to_string(&updated_item)?;
-    let x = &key[..];
-    con.set::<_, _, ()>(x, item_json).await?;
+    // The key is watched from the existence check to the write, so an item deleted in between isn't resurrected
+    for _ in 0..MAX_WATCH_ATTEMPTS {
+        redis::cmd("WATCH").arg(&key).query_async::<()>(&mut *con).await?;
+
+        let written = overwrite_item(&mut *con, state.layout, &space, &updated_item, payload.ttl_seconds).await;
+        if written.is_err() {
+            // The connection goes back to the pool, it mustn't keep watching
+            let _ = redis::cmd("UNWATCH").query_async::<()>(&mut *con).await;
+        }
+
+        if let Some(item) = written? {
+            publish_change(&mut *con, &space, ChangeKind::Updated, id, Some(&item)).await;
+            return Ok(Negotiated(format, item));
+        }
+    }
+
+    Err(Error::Conflict(format!("Item ID {} kept changing, try again", id)))
+}
+
+/// Applies a PATCH to a JSON blob under a `WATCH` of its key, `None` when the key changed and nothing was written
+async fn patch_blob<C>(con: &mut C, space: &Keyspace, id: usize, payload: &UpdateItemPayload) -> Result<Option<Item>>
+where
+    C: ConnectionLike + Send + Sync
+{
+    let mut item = read_item(&mut *con, Layout::Json, space, id).await?;
+
+    if let Some(name) = &payload.name { item.name = name.clone(); }
+    if let Some(description) = &payload.description { item.description = description.clone(); }
+    if let Some(count) = payload.count { item.count = count; }
+    if let Some(height) = payload.height { item.height = height; }
+    if let Some(weight) = payload.weight { item.weight = weight; }
 
-    Ok(Json(updated_item))
+    overwrite_item(con, Layout::Json, space, &item, payload.ttl_seconds).await
 }
 
-/// DELETE /api/items/:id - Delete an item by ID
+/// Overwrites an existing item under a `WATCH` of its key and reads back the expiry it ends up with.
+///
+/// `None` when the key changed since it was watched and nothing was written.
+async fn overwrite_item<C>(con: &mut C, layout: Layout, space: &Keyspace, item: &Item, ttl: Option<u64>) -> Result<Option<Item>>
+where
+    C: ConnectionLike + Send + Sync
+{
+    let key = space.item(item.id);
+
+    let exists: bool = redis::cmd("EXISTS").arg(&key).query_async(&mut *con).await?;
+    if !exists {
+        return Err(Error::NotFound(format!("Item ID: {}", item.id)));
+    }
+
+    let previous = indexed_name(&mut *con, space, item.id).await?;
+
+    let mut pipe = redis::pipe();
+    pipe.atomic();
+    write_item(&mut pipe, layout, space, item, ttl)?;
+    queue_index(&mut pipe, space, item, previous.as_deref());
+    let written: Option<(i64,)> = pipe.ttl(&key).query_async(&mut *con).await?;
+
+    Ok(written.map(|(ttl,)| Item { ttl_seconds: remaining_ttl(ttl), ..item.clone() }))
+}
+
+/// PATCH /api/items/{id} - Update only the given fields of an item
+///
+/// With the hash layout only the sent fields are written; with the JSON layout the blob is rewritten.
+#[utoipa::path(
+    patch, path = "/api/items/{id}", tag = "items",
+    params(("id" = usize, Path, description = "Item id")),
+    request_body = UpdateItemPayload,
+    responses((status = OK, body = Item), ApiErrors)
+)]
+pub async fn patch_item(
+    State(state): State<AppState>,
+    tenant: Tenant,
+    Path(id): Path<usize>,
+    principal: Option<Principal>,
+    Accept(format): Accept,
+    Payload(payload): Payload<UpdateItemPayload>
+) -> Result<Negotiated<Item>> {
+    check_ttl(payload.ttl_seconds)?;
+
+    let mut con = state.redis_pool.get().await.map_err(map_pool_error)?;
+    let space = Keyspace::from(&tenant);
+    check_owner(&mut *con, &space, id, principal.as_ref()).await?;
+
+    match state.layout {
+        Layout::Json => {
+            // Watched from the read to the write, so a concurrent delete or update makes it retry instead of being undone
+            for _ in 0..MAX_WATCH_ATTEMPTS {
+                redis::cmd("WATCH").arg(space.item(id)).query_async::<()>(&mut *con).await?;
+
+                let written = patch_blob(&mut *con, &space, id, &payload).await;
+                if written.is_err() {
+                    let _ = redis::cmd("UNWATCH").query_async::<()>(&mut *con).await;
+                }
+
+                if let Some(item) = written? {
+                    publish_change(&mut *con, &space, ChangeKind::Updated, id, Some(&item)).await;
+                    return Ok(Negotiated(format, item));
+                }
+            }
+
+            Err(Error::Conflict(format!("Item ID {} kept changing, try again", id)))
+        }
+        Layout::Hash => {
+            let mut fields: Vec<String> = Vec::new();
+            if let Some(name) = payload.name { fields.extend(["name".to_string(), name]); }
+            if let Some(description) = payload.description { fields.extend(["description".to_string(), description]); }
+            if let Some(count) = payload.count { fields.extend(["count".to_string(), count.to_string()]); }
+            if let Some(height) = payload.height { fields.extend(["height".to_string(), height.to_string()]); }
+            if let Some(weight) = payload.weight { fields.extend(["weight".to_string(), weight.to_string()]); }
+
+            let previous = indexed_name(&mut *con, &space, id).await?;
+
+            if !fields.is_empty() {
+                let updated: i32 = PATCH_HASH.key(space.item(id)).arg(fields).invoke_async(&mut *con).await?;
+
+                if updated == 0 {
+                    return Err(Error::NotFound(format!("Item ID: {}", id)));
+                }
+            }
+
+            if payload.ttl_seconds.is_some() {
+                let mut pipe = redis::pipe();
+                queue_expiry(&mut pipe, &space.item(id), payload.ttl_seconds);
+                pipe.query_async::<()>(&mut *con).await?;
+            }
+
+            let item = read_item(&mut *con, state.layout, &space, id).await?;
+
+            let mut pipe = redis::pipe();
+            queue_index(&mut pipe, &space, &item, previous.as_deref());
+            pipe.query_async::<()>(&mut *con).await?;
+
+            publish_change(&mut *con, &space, ChangeKind::Updated, id, Some(&item)).await;
+
+            Ok(Negotiated(format, item))
+        }
+    }
+}
+
+/// DELETE /api/items/:id - Move an item to the trash
+///
+/// The item keeps its expiry, so an item that expires while in the trash is gone for good.
+#[utoipa::path(delete, path = "/api/items/{id}", tag = "items", params(("id" = usize, Path, description = "Item id")), responses((status = NO_CONTENT), ApiErrors))]
 pub async fn delete_item(
     State(state): State<AppState>,
+    tenant: Tenant,
     Path(id): Path<usize>,
+    principal: Option<Principal>,
 ) -> Result<StatusCode> {
     let mut con = state.redis_pool.get().await.map_err(map_pool_error)?;
-    let key = item_key(id);
+    let space = Keyspace::from(&tenant);
+    check_owner(&mut *con, &space, id, principal.as_ref()).await?;
 
-    let (del_count, _): (isize, isize) = redis::pipe()
-        .atomic()
-        .del(&key)
-        .srem(ITEM_INDEX_KEY, &key)
-        .query_async(&mut *con)
+    let trashed: i64 = TRASH_ITEM
+        .key(space.item(id))
+        .key(space.trash_item(id))
+        .key(space.key(ITEM_INDEX_KEY))
+        .key(space.key(TRASH_KEY))
+        .arg(unix_now())
+        .invoke_async(&mut *con)
         .await?;
 
-    if del_count == 0 {
+    if trashed == 0 {
         Err(Error::NotFound(format!("Item ID: {}", id)))
     }
     else {
+        drop_from_indexes(&mut *con, &space, &[space.item(id)]).await?;
+        publish_change(&mut *con, &space, ChangeKind::Deleted, id, None).await;
+
         Ok(StatusCode::NO_CONTENT)
     }
 }
+
+/// GET /api/items/trash - Deleted items that can still be restored, most recent first
+#[utoipa::path(get, path = "/api/items/trash", tag = "items", responses((status = OK, body = Vec<TrashedItem>), ApiErrors))]
+pub async fn get_trash(
+    State(state): State<AppState>,
+    tenant: Tenant,
+    Accept(format): Accept,
+) -> Result<Negotiated<Vec<TrashedItem>>> {
+    let mut con = state.redis_pool.get().await.map_err(map_pool_error)?;
+    let space = Keyspace::from(&tenant);
+
+    let entries: Vec<(String, f64)> = con.zrevrange_withscores(space.key(TRASH_KEY), 0, -1).await?;
+    let keys: Vec<String> = entries.iter().map(|(key, _)| key.clone()).collect();
+    let Loaded { items, undecodable, missing } = read_items(&mut *con, state.layout, &keys).await?;
+
+    // Expired while in the trash
+    if !missing.is_empty() {
+        con.zrem::<_, _, ()>(space.key(TRASH_KEY), &missing).await?;
+    }
+
+    if !undecodable.is_empty() {
+        tracing::warn!("Skipped {} undecodable trashed items: {:?}", undecodable.len(), undecodable);
+    }
+
+    let deleted_at: HashMap<String, i64> = entries.into_iter().map(|(key, score)| (key, score as i64)).collect();
+    let trashed = items
+        .into_iter()
+        .map(|item| TrashedItem {
+            deleted_at: deleted_at.get(&space.trash_item(item.id)).copied().unwrap_or_default(),
+            item
+        })
+        .collect();
+
+    Ok(Negotiated(format, trashed))
+}
+
+/// POST /api/items/:id/restore - Take an item back out of the trash
+#[utoipa::path(post, path = "/api/items/{id}/restore", tag = "items", params(("id" = usize, Path, description = "Item id")), responses((status = OK, body = Item), ApiErrors))]
+pub async fn restore_item(
+    State(state): State<AppState>,
+    tenant: Tenant,
+    Path(id): Path<usize>,
+    principal: Option<Principal>,
+    Accept(format): Accept,
+) -> Result<Negotiated<Item>> {
+    let mut con = state.redis_pool.get().await.map_err(map_pool_error)?;
+    let space = Keyspace::from(&tenant);
+    check_owner(&mut *con, &space, id, principal.as_ref()).await?;
+
+    let restored: i64 = RESTORE_ITEM
+        .key(space.trash_item(id))
+        .key(space.item(id))
+        .key(space.key(ITEM_INDEX_KEY))
+        .key(space.key(TRASH_KEY))
+        .invoke_async(&mut *con)
+        .await?;
+
+    match restored {
+        0 => Err(Error::NotFound(format!("Item {} is not in the trash", id))),
+        -1 => Err(Error::Conflict(format!("Item ID {} is in use again", id))),
+        _ => {
+            let item = read_item(&mut *con, state.layout, &space, id).await?;
+
+            let mut pipe = redis::pipe();
+            queue_index(&mut pipe, &space, &item, None);
+            pipe.query_async::<()>(&mut *con).await?;
+
+            publish_change(&mut *con, &space, ChangeKind::Created, id, Some(&item)).await;
+
+            Ok(Negotiated(format, item))
+        }
+    }
+}
+
+/// Removes items that have been in the trash for longer than `retention`, in every tenant
+pub async fn purge_trash(pool: &RedisPool, retention: Duration) -> Result<u64> {
+    let mut con = pool.get().await.map_err(map_pool_error)?;
+    let cutoff = unix_now() - retention.as_secs() as i64;
+    let mut purged = 0u64;
+
+    let tenants: Vec<String> = con.smembers(TENANTS_KEY).await?;
+
+    for tenant in tenants {
+        let space = Keyspace::new(&tenant);
+        let trash_prefix = space.key("trash:item:");
+
+        loop {
+            let keys: Vec<String> = con.zrangebyscore_limit(space.key(TRASH_KEY), "-inf", cutoff, 0, PURGE_BATCH).await?;
+            if keys.is_empty() {
+                break;
+            }
+
+            let ids: Vec<&str> = keys.iter().filter_map(|key| key.strip_prefix(trash_prefix.as_str())).collect();
+
+            let mut pipe = redis::pipe();
+            pipe.atomic()
+                .del(&keys).ignore()
+                .zrem(space.key(TRASH_KEY), &keys).ignore();
+
+            if !ids.is_empty() {
+                pipe.hdel(space.key(OWNERS_KEY), &ids).ignore();
+            }
+            pipe.query_async::<()>(&mut *con).await?;
+
+            purged += keys.len() as u64;
+        }
+    }
+
+    Ok(purged)
+}
+
+/// GET /api/items/events - Live item changes as Server-Sent Events, see [`events::sse`]
+#[utoipa::path(
+    get, path = "/api/items/events", tag = "events",
+    responses((status = OK, body = ChangeEvent<Item>, content_type = "text/event-stream"), ApiErrors)
+)]
+pub async fn item_events(feed: State<Feed<Item>>, tenant: Tenant) -> impl IntoResponse {
+    events::sse(feed, tenant).await
+}
+
+/// GET /ws - Live item change subscriptions over a WebSocket, see [`ws::handler`]
+#[utoipa::path(
+    get, path = "/ws", tag = "events",
+    responses((status = SWITCHING_PROTOCOLS, description = "Upgraded, `ChangeEvent` messages follow"), ApiErrors)
+)]
+pub async fn item_socket(socket: WebSocketUpgrade, feed: State<Feed<Item>>, tenant: Tenant) -> Response {
+    ws::handler(socket, feed, tenant).await
+}
+
+/// GET /api/items/events/history?since=<stream-id>&limit=<n> - Replay changes recorded after `since`
+#[utoipa::path(
+    get, path = "/api/items/events/history", tag = "events",
+    params(HistoryQuery),
+    responses((status = OK, body = EventPage), ApiErrors)
+)]
+pub async fn get_event_history(
+    State(state): State<AppState>,
+    tenant: Tenant,
+    Accept(format): Accept,
+    Query(query): Query<HistoryQuery>,
+) -> Result<Negotiated<EventPage>> {
+    let mut con = state.redis_pool.get().await.map_err(map_pool_error)?;
+    let space = Keyspace::from(&tenant);
+    let limit = query.limit.unwrap_or(DEFAULT_EVENT_PAGE).clamp(1, MAX_EVENT_PAGE);
+
+    // An exclusive start, so passing back the last seen id never repeats it
+    let start = match &query.since {
+        Some(since) => {
+            validate_stream_id(since)?;
+            format!("({}", since)
+        }
+        None => "-".to_string()
+    };
+
+    let reply: StreamRangeReply = con.xrange_count(space.key(ITEMS_STREAM), start, "+", limit).await?;
+    let mut page = event_page(reply.ids);
+
+    // Nothing new yet, so the client should ask again from the same place
+    if page.next.is_none() {
+        page.next = query.since;
+    }
+
+    Ok(Negotiated(format, page))
+}
+
+/// POST /api/items/events/groups - Create a consumer group on the event stream
+#[utoipa::path(
+    post, path = "/api/items/events/groups", tag = "events",
+    request_body = CreateGroupPayload,
+    responses((status = CREATED), (status = CONFLICT, description = "The group already exists"), ApiErrors)
+)]
+pub async fn create_event_group(
+    State(state): State<AppState>,
+    tenant: Tenant,
+    Payload(payload): Payload<CreateGroupPayload>,
+) -> Result<StatusCode> {
+    let mut con = state.redis_pool.get().await.map_err(map_pool_error)?;
+    let space = Keyspace::from(&tenant);
+
+    let start = payload.start.unwrap_or_else(|| "$".to_string());
+    if start != "$" {
+        validate_stream_id(&start)?;
+    }
+
+    match con.xgroup_create_mkstream::<_, _, _, ()>(space.key(ITEMS_STREAM), &payload.name, &start).await {
+        Ok(()) => Ok(StatusCode::CREATED),
+        Err(e) if e.code() == Some("BUSYGROUP") => Err(Error::Conflict(format!("Consumer group already exists: {}", payload.name))),
+        Err(e) => Err(Error::from(e))
+    }
+}
+
+/// POST /api/items/events/groups/:group/read - Fetch events for a consumer of the group
+///
+/// Entries stay pending until acknowledged, so a consumer first gets back whatever it was handed
+/// before and never acknowledged, then entries other consumers left idle for `claim_idle_ms`,
+/// and only then new events.
+#[utoipa::path(
+    post, path = "/api/items/events/groups/{group}/read", tag = "events",
+    params(("group" = String, Path, description = "Consumer group name")),
+    request_body = ReadGroupPayload,
+    responses((status = OK, body = EventPage), ApiErrors)
+)]
+pub async fn read_event_group(
+    State(state): State<AppState>,
+    tenant: Tenant,
+    Accept(format): Accept,
+    Path(group): Path<String>,
+    Payload(payload): Payload<ReadGroupPayload>,
+) -> Result<Negotiated<EventPage>> {
+    let mut con = state.redis_pool.get().await.map_err(map_pool_error)?;
+    let space = Keyspace::from(&tenant);
+    let count = payload.count.unwrap_or(DEFAULT_EVENT_PAGE).clamp(1, MAX_EVENT_PAGE);
+
+    let stream = space.key(ITEMS_STREAM);
+    let options = StreamReadOptions::default().group(&group, &payload.consumer).count(count);
+    let pending: StreamReadReply = con.xread_options(&[&stream], &["0"], &options)
+        .await
+        .map_err(|e| map_group_error(&group, e))?;
+
+    let entries: Vec<StreamId> = pending.keys.into_iter().flat_map(|key| key.ids).collect();
+    if !entries.is_empty() {
+        return Ok(Negotiated(format, event_page(entries)));
+    }
+
+    if let Some(idle) = payload.claim_idle_ms {
+        let claimed: StreamAutoClaimReply = con
+            .xautoclaim_options(&stream, &group, &payload.consumer, idle, "0-0", StreamAutoClaimOptions::default().count(count))
+            .await
+            .map_err(|e| map_group_error(&group, e))?;
+
+        if !claimed.claimed.is_empty() {
+            return Ok(Negotiated(format, event_page(claimed.claimed)));
+        }
+    }
+
+    let mut options = options;
+    if let Some(block_ms) = payload.block_ms {
+        options = options.block(block_ms.min(MAX_BLOCK_MS));
+    }
+
+    let fresh: StreamReadReply = con.xread_options(&[&stream], &[">"], &options)
+        .await
+        .map_err(|e| map_group_error(&group, e))?;
+
+    Ok(Negotiated(format, event_page(fresh.keys.into_iter().flat_map(|key| key.ids).collect())))
+}
+
+/// POST /api/items/events/groups/:group/ack - Mark events as processed by the group
+#[utoipa::path(
+    post, path = "/api/items/events/groups/{group}/ack", tag = "events",
+    params(("group" = String, Path, description = "Consumer group name")),
+    request_body = AckPayload,
+    responses((status = OK, body = AckReport), ApiErrors)
+)]
+pub async fn ack_event_group(
+    State(state): State<AppState>,
+    tenant: Tenant,
+    Accept(format): Accept,
+    Path(group): Path<String>,
+    Payload(payload): Payload<AckPayload>,
+) -> Result<Negotiated<AckReport>> {
+    if payload.ids.is_empty() {
+        return Ok(Negotiated(format, AckReport { acknowledged: 0 }));
+    }
+
+    for id in &payload.ids {
+        validate_stream_id(id)?;
+    }
+
+    let mut con = state.redis_pool.get().await.map_err(map_pool_error)?;
+    let space = Keyspace::from(&tenant);
+    let acknowledged: usize = con.xack(space.key(ITEMS_STREAM), &group, payload.ids.as_slice())
+        .await
+        .map_err(|e| map_group_error(&group, e))?;
+
+    Ok(Negotiated(format, AckReport { acknowledged }))
+}
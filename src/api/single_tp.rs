@@ -1,66 +1,270 @@
-use axum::{extract::Path, Json, http::StatusCode};
-use crate::{error::tok_postgres::Error, prelude::tok_postgres::{Datas, DatasPayload, PgConnection, Result}};
+use std::time::Duration;
+use axum::{body::Body, extract::{Path, Query}, http::{HeaderMap, StatusCode}, response::Response};
+use futures::TryStreamExt;
+use tokio_postgres::types::ToSql;
+use crate::{audit::{Actor, Operation, PURGE_ACTOR}, auth::Principal, cache::{datas_key, datas_list_key}, codec::{Accept, Negotiated, Payload}, error::tok_postgres::Error, export::{self, ExportQuery}, idempotency::IdempotencyKey, import, openapi::ApiErrors, prelude::tok_postgres::{Datas, DatasPayload, ImportQuery, ImportReport, PgClient, PgConnection, Result, TrashedDatas}, tenant::Tenant};
 
+// The shared connection can't hold a transaction per request, so it runs with `app.all_tenants` on
+// and every statement names the tenant it works in.
 
-pub async fn get_datas(PgConnection(state): PgConnection) -> Result<Json<Vec<Datas>>> {
-    let res = state.client
-        .query(&state.get_datas, &[])
+#[utoipa::path(get, path = "/api/datas", tag = "datas", responses((status = OK, body = Vec<Datas>), ApiErrors))]
+pub async fn get_datas(PgConnection(state): PgConnection, tenant: Tenant, Accept(format): Accept) -> Result<Negotiated<Vec<Datas>>> {
+    let res = state.cache.get_or_load(&datas_list_key(&tenant.0), || async {
+        let res = state.client
+            .query(&state.get_datas, &[&tenant.0])
+            .await?
+            .drain(..)
+            .map(|x| {
+                Datas {
+                    id: x.get(0),
+                    name: x.get(1),
+                    flags: x.get(2),
+                    sys: x.get(3),
+                }
+            })
+            .collect();
+
+        Ok::<_, Error>(res)
+    }).await?;
+
+    Ok(Negotiated(format, res))
+}
+
+#[utoipa::path(
+    get, path = "/api/datas/export", tag = "datas",
+    params(ExportQuery),
+    responses(
+        (status = OK, content((Datas = "application/x-ndjson"), (String = "text/csv"), (Vec<Datas> = "application/json"))),
+        ApiErrors
+    )
+)]
+pub async fn export_datas(
+    PgConnection(state): PgConnection,
+    tenant: Tenant,
+    Query(query): Query<ExportQuery>
+) -> Result<Response> {
+    let rows = state.client
+        .query_raw(&state.get_datas, [&tenant.0 as &(dyn ToSql + Sync)])
         .await?
-        .drain(..)
-        .map(|x| {
+        .map_ok(|x| {
             Datas {
                 id: x.get(0),
                 name: x.get(1),
                 flags: x.get(2),
                 sys: x.get(3),
             }
-        })
-        .collect();
+        });
 
-    Ok(Json(res))
+    Ok(export::stream_response(query.format, rows))
 }
 
+#[utoipa::path(get, path = "/api/datas/{id}", tag = "datas", params(("id" = i32, Path, description = "Datas id")), responses((status = OK, body = Datas), ApiErrors))]
 pub async fn get_data(
     PgConnection(state): PgConnection,
-    Path(id): Path<i32>
-) -> Result<Json<Datas>> {
-    let res = state.client.query_opt(&state.get_data, &[&id]).await?;
+    Path(id): Path<i32>,
+    tenant: Tenant,
+    Accept(format): Accept
+) -> Result<Negotiated<Datas>> {
+    // Misses are cached too, so hammering an unknown id doesn't reach Postgres either
+    let res = state.cache.get_or_load(&datas_key(&tenant.0, id), || async {
+        let res = state.client.query_opt(&state.get_data, &[&id, &tenant.0]).await?;
 
-    match res {
-        Some(x) => Ok(Json(Datas {
+        Ok::<_, Error>(res.map(|x| Datas {
             id: x.get(0),
             name: x.get(1),
             flags: x.get(2),
             sys: x.get(3),
-        })),
+        }))
+    }).await?;
+
+    match res {
+        Some(x) => Ok(Negotiated(format, x)),
         None => Err(Error::NotFound("Not here btw".to_string()))
     }
 }
 
+/// The subject that has to own a row for `principal` to change it, `None` when anyone may
+fn required_owner(principal: Option<&Principal>) -> Option<&str> {
+    principal.filter(|p| !p.is_admin()).map(|p| p.subject.as_str())
+}
+
+/// Tells a write that matched nothing because of ownership apart from one that found no row
+async fn check_unchanged(state: &PgClient, tenant: &Tenant, id: i32, owner: Option<&str>) -> Result<()> {
+    let Some(subject) = owner else {
+        return Ok(());
+    };
+
+    let row = state.client.query_opt("SELECT owner FROM items.datas WHERE id = $1 AND tenant_id = $2", &[&id, &tenant.0]).await?;
+    let current: Option<String> = row.and_then(|row| row.get(0));
+
+    match current {
+        Some(current) if current != subject => Err(Error::Forbidden(format!("Datas {} belongs to another subject", id))),
+        _ => Ok(())
+    }
+}
+
+#[utoipa::path(
+    post, path = "/api/datas", tag = "datas",
+    params(Actor, IdempotencyKey),
+    request_body = DatasPayload,
+    responses((status = CREATED, body = i32, description = "Id of the new row"), ApiErrors)
+)]
 pub async fn create_datas(
     PgConnection(state): PgConnection,
-    Json(payload): Json<DatasPayload>
-) -> Result<(StatusCode, Json<i32>)> {
-    let id = state.client.query_one(&state.create_datas, &[&payload.name, &payload.flags, &payload.sys]).await?;
+    Actor(actor): Actor,
+    principal: Option<Principal>,
+    tenant: Tenant,
+    Accept(format): Accept,
+    Payload(payload): Payload<DatasPayload>
+) -> Result<(StatusCode, Negotiated<i32>)> {
+    let owner = principal.map(|p| p.subject);
+    let id = state.client.query_one(&state.create_datas, &[&payload.name, &payload.flags, &payload.sys, &owner, &tenant.0, &actor]).await?;
+    state.cache.invalidate_datas(&tenant.0, id.get(0)).await;
 
-    Ok((StatusCode::CREATED, Json(id.get(0))))
+    Ok((StatusCode::CREATED, Negotiated(format, id.get::<_, i32>(0))))
 }
 
+#[utoipa::path(
+    put, path = "/api/datas/{id}", tag = "datas",
+    params(("id" = i32, Path, description = "Datas id"), Actor),
+    request_body = DatasPayload,
+    responses((status = OK), ApiErrors)
+)]
 pub async fn edit_datas(
     PgConnection(state): PgConnection,
     Path(id): Path<i32>,
-    Json(payload): Json<DatasPayload>,
+    Actor(actor): Actor,
+    principal: Option<Principal>,
+    tenant: Tenant,
+    Payload(payload): Payload<DatasPayload>,
 ) -> Result<StatusCode> {
-    state.client.execute(&state.edit_datas, &[&payload.name, &payload.flags, &payload.sys, &id]).await?;
+    let owner = required_owner(principal.as_ref());
+    let edited = state.client.execute(&state.edit_datas, &[&payload.name, &payload.flags, &payload.sys, &id, &owner, &tenant.0, &actor]).await?;
+    if edited == 0 {
+        check_unchanged(&state, &tenant, id, owner).await?;
+    }
+    state.cache.invalidate_datas(&tenant.0, id).await;
 
     Ok(StatusCode::OK)
 }
 
+/// DELETE /api/datas/:id - Move a row to the trash
+#[utoipa::path(delete, path = "/api/datas/{id}", tag = "datas", params(("id" = i32, Path, description = "Datas id"), Actor), responses((status = OK), ApiErrors))]
 pub async fn destroy_datas(
     PgConnection(state): PgConnection,
-    Path(id): Path<i32>
+    Path(id): Path<i32>,
+    Actor(actor): Actor,
+    principal: Option<Principal>,
+    tenant: Tenant
 ) -> Result<()> {
-    state.client.execute(&state.destroy_datas, &[&id]).await?;
+    let owner = required_owner(principal.as_ref());
+    let destroyed = state.client.execute(&state.destroy_datas, &[&id, &owner, &tenant.0, &actor]).await?;
+    if destroyed == 0 {
+        check_unchanged(&state, &tenant, id, owner).await?;
+    }
+    state.cache.invalidate_datas(&tenant.0, id).await;
 
     Ok(())
 }
+
+/// GET /api/datas/trash - Deleted rows that can still be restored, most recent first
+#[utoipa::path(get, path = "/api/datas/trash", tag = "datas", responses((status = OK, body = Vec<TrashedDatas>), ApiErrors))]
+pub async fn get_trash(PgConnection(state): PgConnection, tenant: Tenant, Accept(format): Accept) -> Result<Negotiated<Vec<TrashedDatas>>> {
+    let res = state.client
+        .query(&state.trash_datas, &[&tenant.0])
+        .await?
+        .drain(..)
+        .map(|x| {
+            TrashedDatas {
+                id: x.get(0),
+                name: x.get(1),
+                flags: x.get(2),
+                sys: x.get(3),
+                deleted_at: x.get(4),
+            }
+        })
+        .collect();
+
+    Ok(Negotiated(format, res))
+}
+
+/// POST /api/datas/:id/restore - Take a row back out of the trash
+#[utoipa::path(
+    post, path = "/api/datas/{id}/restore", tag = "datas",
+    params(("id" = i32, Path, description = "Datas id"), Actor),
+    responses((status = OK, body = Datas), ApiErrors)
+)]
+pub async fn restore_trashed(
+    PgConnection(state): PgConnection,
+    Path(id): Path<i32>,
+    Actor(actor): Actor,
+    principal: Option<Principal>,
+    tenant: Tenant,
+    Accept(format): Accept
+) -> Result<Negotiated<Datas>> {
+    let owner = required_owner(principal.as_ref());
+    let res = state.client.query_opt(&state.restore_datas, &[&id, &owner, &tenant.0, &actor]).await?;
+    if res.is_none() {
+        check_unchanged(&state, &tenant, id, owner).await?;
+    }
+    state.cache.invalidate_datas(&tenant.0, id).await;
+
+    match res {
+        Some(x) => Ok(Negotiated(format, Datas {
+            id: x.get(0),
+            name: x.get(1),
+            flags: x.get(2),
+            sys: x.get(3),
+        })),
+        None => Err(Error::NotFound(format!("Datas {} is not in the trash", id)))
+    }
+}
+
+/// Removes rows that have been in the trash for longer than `retention`, in every tenant
+pub async fn purge_trash(state: &PgClient, retention: Duration) -> Result<u64> {
+    let purged = state.client
+        .execute(
+            "WITH purged AS (
+                 DELETE FROM items.datas WHERE deleted_at < now() - make_interval(secs => $1)
+                 RETURNING id, name, flags, sys, tenant_id
+             )
+             INSERT INTO items.datas_history (datas_id, operation, before, after, actor, tenant_id)
+             SELECT id, $2, to_jsonb(purged) - 'tenant_id', NULL, $3, tenant_id FROM purged",
+            &[&retention.as_secs_f64(), &Operation::Purge.as_str(), &PURGE_ACTOR]
+        )
+        .await?;
+
+    Ok(purged)
+}
+
+#[utoipa::path(
+    post, path = "/api/datas/import", tag = "datas",
+    params(ImportQuery, Actor),
+    request_body(content((String = "text/csv"), (DatasPayload = "application/x-ndjson")), description = "One row per line"),
+    responses((status = OK, body = ImportReport), ApiErrors)
+)]
+pub async fn import_datas(
+    PgConnection(state): PgConnection,
+    tenant: Tenant,
+    Actor(actor): Actor,
+    Query(query): Query<ImportQuery>,
+    Accept(accept): Accept,
+    headers: HeaderMap,
+    body: Body
+) -> Result<Negotiated<ImportReport>> {
+    let format = import::resolve_format(&query, &headers)?;
+
+    // The upload streams for as long as the client takes, so it goes over a connection of its own
+    let (client, connection) = state.config.connect(state.tls.clone()).await?;
+    tokio::spawn(async move {
+        if let Err(e) = connection.await {
+            tracing::warn!("Import connection error: {}", e);
+        }
+    });
+    client.batch_execute("SET app.all_tenants = 'on'").await?;
+
+    let report = import::copy_datas(&client, &tenant.0, &actor, format, body).await?;
+    state.cache.invalidate(&[datas_list_key(&tenant.0)]).await;
+
+    Ok(Negotiated(accept, report))
+}
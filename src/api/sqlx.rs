@@ -1,57 +1,536 @@
-use axum::{extract::{Path, State}, http::StatusCode, Json};
-use sqlx::{query_as, query};
-use crate::{error::sqlx::Error, prelude::sqlx::{AppState, Datas, DatasPayload, Result}};
+use std::time::Duration;
+use axum::{extract::{ws::WebSocketUpgrade, Path, Query, State}, http::StatusCode, response::{IntoResponse, Response}};
+use futures::StreamExt;
+use sqlx::{query_as, query, query_scalar, PgPool, Postgres, Transaction};
+use crate::{audit::{Actor, HistoryEntry, Operation, PURGE_ACTOR}, auth::Principal, cache::{datas_key, datas_list_key}, codec::{Accept, Negotiated, Payload}, error::sqlx::Error, events::{self, ChangeEvent, Feed}, export::{self, ExportQuery}, idempotency::IdempotencyKey, openapi::ApiErrors, prelude::sqlx::{AppState, CreateDatasPayload, Datas, DatasPayload, DatasWithNiceties, Niceties, Result, TrashedDatas, UpsertDatasPayload}, replica::Consistency, search::{SearchHit, SearchQuery, SearchResults}, tenant::Tenant, ws};
 
-pub async fn get_datas(State(app): State<AppState>) -> Result<Json<Vec<Datas>>> {
-    let x = query_as!(Datas, "SELECT * FROM items.datas").fetch_all(&app.pg_pool).await?;
+/// Opens a transaction that only sees `tenant`'s rows, the row level security policies read `app.tenant`
+pub(crate) async fn begin(pool: &PgPool, tenant: &Tenant) -> Result<Transaction<'static, Postgres>> {
+    let mut tx = pool.begin().await?;
+    query!("SELECT set_config('app.tenant', $1, true)", tenant.0).fetch_one(&mut *tx).await?;
 
-    Ok(Json(x))
+    Ok(tx)
 }
 
+#[utoipa::path(get, path = "/api/datas", tag = "datas", params(Consistency), responses((status = OK, body = Vec<Datas>), ApiErrors))]
+pub async fn get_datas(State(app): State<AppState>, tenant: Tenant, consistency: Consistency, Accept(format): Accept) -> Result<Negotiated<Vec<Datas>>> {
+    let pool = app.replicas.pick_for(&app.cache, consistency).unwrap_or(&app.pg_pool);
+    let x = app.cache.get_or_load(&datas_list_key(&tenant.0), || async {
+        let mut tx = begin(pool, &tenant).await?;
+        let rows = query_as!(Datas, "SELECT id, name, flags, sys FROM items.datas WHERE deleted_at IS NULL").fetch_all(&mut *tx).await?;
+        tx.commit().await?;
+
+        Ok::<_, Error>(rows)
+    }).await?;
+
+    Ok(Negotiated(format, x))
+}
+
+#[utoipa::path(
+    get, path = "/api/datas/export", tag = "datas",
+    params(ExportQuery, Consistency),
+    responses(
+        (status = OK, content((Datas = "application/x-ndjson"), (String = "text/csv"), (Vec<Datas> = "application/json"))),
+        ApiErrors
+    )
+)]
+pub async fn export_datas(State(app): State<AppState>, tenant: Tenant, consistency: Consistency, Query(query): Query<ExportQuery>) -> Response {
+    let pool = app.replicas.pick(consistency).unwrap_or(&app.pg_pool).clone();
+    let rows = async_stream::stream! {
+        // Dropping the stream early rolls the transaction back
+        let mut tx = match begin(&pool, &tenant).await {
+            Ok(tx) => tx,
+            Err(e) => {
+                yield Err(e);
+                return;
+            }
+        };
+
+        let mut rows = query_as!(Datas, "SELECT id, name, flags, sys FROM items.datas WHERE deleted_at IS NULL").fetch(&mut *tx);
+
+        while let Some(row) = rows.next().await {
+            yield row.map_err(Error::from);
+        }
+    };
+
+    export::stream_response(query.format, rows)
+}
+
+/// GET /api/datas/events - Live datas changes as Server-Sent Events, see [`events::sse`]
+#[utoipa::path(
+    get, path = "/api/datas/events", tag = "events",
+    responses((status = OK, body = ChangeEvent<Datas>, content_type = "text/event-stream"), ApiErrors)
+)]
+pub async fn datas_events(feed: State<Feed<Datas>>, tenant: Tenant) -> impl IntoResponse {
+    events::sse(feed, tenant).await
+}
+
+/// GET /ws - Live datas change subscriptions over a WebSocket, see [`ws::handler`]
+#[utoipa::path(
+    get, path = "/ws", tag = "events",
+    responses((status = SWITCHING_PROTOCOLS, description = "Upgraded, `ChangeEvent` messages follow"), ApiErrors)
+)]
+pub async fn datas_socket(socket: WebSocketUpgrade, feed: State<Feed<Datas>>, tenant: Tenant) -> Response {
+    ws::handler(socket, feed, tenant).await
+}
+
+#[utoipa::path(get, path = "/api/datas/{id}", tag = "datas", params(("id" = i32, Path, description = "Datas id"), Consistency), responses((status = OK, body = Datas), ApiErrors))]
 pub async fn get_data(
     State(app): State<AppState>,
     Path(id): Path<i32>,
-) -> Result<Json<Datas>> {
-    let x = query_as!(Datas, "SELECT * FROM items.datas WHERE id = $1", id).fetch_optional(&app.pg_pool).await?;
+    tenant: Tenant,
+    consistency: Consistency,
+    Accept(format): Accept,
+) -> Result<Negotiated<Datas>> {
+    let pool = app.replicas.pick_for(&app.cache, consistency).unwrap_or(&app.pg_pool);
+    // Misses are cached too, so hammering an unknown id doesn't reach Postgres either
+    let x = app.cache.get_or_load(&datas_key(&tenant.0, id), || async {
+        let mut tx = begin(pool, &tenant).await?;
+        let row = query_as!(Datas, "SELECT id, name, flags, sys FROM items.datas WHERE id = $1 AND deleted_at IS NULL", id).fetch_optional(&mut *tx).await?;
+        tx.commit().await?;
+
+        Ok::<_, Error>(row)
+    }).await?;
 
     match x {
-        Some(x) => Ok(Json(x)),
+        Some(x) => Ok(Negotiated(format, x)),
         None => Err(Error::NotFound("Invalid ID, didn't find the requested data".to_string()))
     }
 }
 
+/// Writes the audit row for a change, inside the transaction that made it
+async fn record_history(
+    tx: &mut Transaction<'_, Postgres>,
+    datas_id: i32,
+    operation: Operation,
+    before: Option<&Datas>,
+    after: Option<&Datas>,
+    actor: &str
+) -> Result<()> {
+    let before = before.map(serde_json::to_value).transpose()?;
+    let after = after.map(serde_json::to_value).transpose()?;
+
+    query!(
+        "INSERT INTO items.datas_history (datas_id, operation, before, after, actor) VALUES ($1, $2, $3, $4, $5)",
+        datas_id,
+        operation.as_str(),
+        before,
+        after,
+        actor
+    ).execute(&mut **tx).await?;
+
+    Ok(())
+}
+
+/// Refuses the change unless `principal` owns row `id`, which stays locked until the transaction ends.
+/// A missing row passes, the caller reports it.
+async fn check_owner(tx: &mut Transaction<'_, Postgres>, id: i32, principal: Option<&Principal>) -> Result<()> {
+    let Some(principal) = principal.filter(|p| !p.is_admin()) else {
+        return Ok(());
+    };
+
+    let owner = query_scalar!("SELECT owner FROM items.datas WHERE id = $1 FOR UPDATE", id)
+        .fetch_optional(&mut **tx)
+        .await?
+        .flatten();
+
+    if principal.may_modify(owner.as_deref()) {
+        Ok(())
+    }
+    else {
+        Err(Error::Forbidden(format!("Datas {} belongs to another subject", id)))
+    }
+}
+
+#[utoipa::path(
+    post, path = "/api/datas", tag = "datas",
+    params(Actor, IdempotencyKey),
+    request_body = CreateDatasPayload,
+    responses((status = CREATED, body = DatasWithNiceties), ApiErrors)
+)]
 pub async fn create_datas(
     State(app): State<AppState>,
-    Json(payload): Json<DatasPayload>,
-) -> Result<StatusCode> {
-    query!(
-        "INSERT INTO items.datas (name, flags, sys) VALUES ($1, $2, $3)",
+    Actor(actor): Actor,
+    principal: Option<Principal>,
+    tenant: Tenant,
+    Accept(format): Accept,
+    Payload(payload): Payload<CreateDatasPayload>,
+) -> Result<(StatusCode, Negotiated<DatasWithNiceties>)> {
+    let created = insert_datas(&app, &tenant, &actor, principal.as_ref(), payload).await?;
+
+    Ok((StatusCode::CREATED, Negotiated(format, created)))
+}
+
+/// Inserts a row owned by `principal` together with its niceties and audits it, all in one transaction.
+/// Behind both `POST /api/datas` and the GraphQL `createDatas`.
+pub async fn insert_datas(app: &AppState, tenant: &Tenant, actor: &str, principal: Option<&Principal>, payload: CreateDatasPayload) -> Result<DatasWithNiceties> {
+    let mut tx = begin(&app.pg_pool, tenant).await?;
+
+    let created = query_as!(
+        Datas,
+        "INSERT INTO items.datas (name, flags, sys, owner) VALUES ($1, $2, $3, $4) RETURNING id, name, flags, sys",
         payload.name,
         payload.flags,
         payload.sys,
-    ).execute(&app.pg_pool).await?;
+        principal.map(|p| p.subject.as_str()),
+    ).fetch_one(&mut *tx).await?;
 
-    Ok(StatusCode::CREATED)
+    let niceties = if payload.niceties.is_empty() {
+        Vec::new()
+    }
+    else {
+        let mems: Vec<i64> = payload.niceties.iter().map(|n| n.mem).collect();
+        let stacks: Vec<i16> = payload.niceties.iter().map(|n| n.stack).collect();
+        let infos: Vec<String> = payload.niceties.into_iter().map(|n| n.info).collect();
+
+        query_as!(
+            Niceties,
+            "INSERT INTO items.niceties (datas_id, mem, stack, info)
+             SELECT $1::int, mem, stack, info FROM UNNEST($2::bigint[], $3::smallint[], $4::text[]) AS n (mem, stack, info)
+             RETURNING id, datas_id, mem, stack, info",
+            created.id,
+            &mems,
+            &stacks,
+            &infos
+        ).fetch_all(&mut *tx).await?
+    };
+
+    record_history(&mut tx, created.id, Operation::Insert, None, Some(&created), actor).await?;
+    tx.commit().await?;
+    app.cache.invalidate_datas(&tenant.0, created.id).await;
+
+    Ok(DatasWithNiceties { datas: created, niceties })
 }
 
+#[utoipa::path(
+    put, path = "/api/datas/{id}", tag = "datas",
+    params(("id" = i32, Path, description = "Datas id"), Actor),
+    request_body = DatasPayload,
+    responses((status = OK, body = i32, description = "Id of the edited row"), ApiErrors)
+)]
 pub async fn edit_datas(
     State(app): State<AppState>,
     Path(id): Path<i32>,
-    Json(payload): Json<DatasPayload>,
-) -> Result<Json<i32>> {
-    let x = query!(
-        "UPDATE items.datas SET name = $1, flags = $2, sys = $3 WHERE id = $4 RETURNING id",
+    Actor(actor): Actor,
+    principal: Option<Principal>,
+    tenant: Tenant,
+    Accept(format): Accept,
+    Payload(payload): Payload<DatasPayload>,
+) -> Result<Negotiated<i32>> {
+    let updated = update_datas(&app, &tenant, &actor, principal.as_ref(), id, payload).await?;
+
+    Ok(Negotiated(format, updated.id))
+}
+
+/// Overwrites row `id` if `principal` may and audits it, behind both `PUT /api/datas/{id}` and the GraphQL `editDatas`
+pub async fn update_datas(app: &AppState, tenant: &Tenant, actor: &str, principal: Option<&Principal>, id: i32, payload: DatasPayload) -> Result<Datas> {
+    let mut tx = begin(&app.pg_pool, tenant).await?;
+    check_owner(&mut tx, id, principal).await?;
+
+    let before = query_as!(Datas, "SELECT id, name, flags, sys FROM items.datas WHERE id = $1 AND deleted_at IS NULL FOR UPDATE", id)
+        .fetch_optional(&mut *tx)
+        .await?
+        .ok_or_else(|| Error::NotFound("Invalid ID, didn't find the requested data".to_string()))?;
+
+    let after = query_as!(
+        Datas,
+        "UPDATE items.datas SET name = $1, flags = $2, sys = $3 WHERE id = $4 RETURNING id, name, flags, sys",
         payload.name,
         payload.flags,
         payload.sys,
         id
-    ).fetch_one(&app.pg_pool).await?;
+    ).fetch_one(&mut *tx).await?;
 
-    Ok(Json(x.id))
+    record_history(&mut tx, id, Operation::Update, Some(&before), Some(&after), actor).await?;
+    tx.commit().await?;
+    app.cache.invalidate_datas(&tenant.0, id).await;
+
+    Ok(after)
 }
 
-pub async fn destroy_datas(State(app): State<AppState>, Path(id): Path<i32>) -> Result<()> {
-    query!("DELETE FROM items.datas WHERE id = $1", id).execute(&app.pg_pool).await?;
+#[utoipa::path(
+    put, path = "/api/datas/by-name/{name}", tag = "datas",
+    params(("name" = String, Path, description = "Datas name, unique within the tenant"), Actor),
+    request_body = UpsertDatasPayload,
+    responses(
+        (status = CREATED, body = Datas, description = "No row had the name, a new one was created"),
+        (status = OK, body = Datas, description = "The row with the name was updated, or taken back out of the trash"),
+        ApiErrors
+    )
+)]
+pub async fn upsert_datas(
+    State(app): State<AppState>,
+    Path(name): Path<String>,
+    Actor(actor): Actor,
+    principal: Option<Principal>,
+    tenant: Tenant,
+    Accept(format): Accept,
+    Payload(payload): Payload<UpsertDatasPayload>,
+) -> Result<(StatusCode, Negotiated<Datas>)> {
+    let mut tx = begin(&app.pg_pool, &tenant).await?;
+
+    let before = query_as!(Datas, "SELECT id, name, flags, sys FROM items.datas WHERE name = $1 FOR UPDATE", name)
+        .fetch_optional(&mut *tx)
+        .await?;
+
+    // The ownership check sits in the statement, so a row another request inserted after the lookup is covered too
+    let owner = principal.as_ref().map(|p| p.subject.as_str());
+    let any_owner = principal.as_ref().is_none_or(|p| p.is_admin());
+    let upserted = query!(
+        r#"INSERT INTO items.datas (name, flags, sys, owner) VALUES ($1, $2, $3, $4)
+           ON CONFLICT (tenant_id, name) DO UPDATE SET flags = EXCLUDED.flags, sys = EXCLUDED.sys, deleted_at = NULL
+           WHERE $5 OR items.datas.owner IS NULL OR items.datas.owner = $4
+           RETURNING id, name, flags, sys, (xmax = 0) AS "created!""#,
+        name,
+        payload.flags,
+        payload.sys,
+        owner,
+        any_owner
+    ).fetch_optional(&mut *tx).await?
+    .ok_or_else(|| Error::Forbidden(format!("Datas '{}' belongs to another subject", name)))?;
+
+    let after = Datas { id: upserted.id, name: upserted.name, flags: upserted.flags, sys: upserted.sys };
+    let (status, operation) = if upserted.created {
+        (StatusCode::CREATED, Operation::Insert)
+    }
+    else {
+        (StatusCode::OK, Operation::Update)
+    };
+
+    record_history(&mut tx, after.id, operation, before.as_ref(), Some(&after), &actor).await?;
+    tx.commit().await?;
+    app.cache.invalidate_datas(&tenant.0, after.id).await;
+
+    Ok((status, Negotiated(format, after)))
+}
+
+/// DELETE /api/datas/:id - Move a row to the trash
+#[utoipa::path(delete, path = "/api/datas/{id}", tag = "datas", params(("id" = i32, Path, description = "Datas id"), Actor), responses((status = OK), ApiErrors))]
+pub async fn destroy_datas(
+    State(app): State<AppState>,
+    Path(id): Path<i32>,
+    Actor(actor): Actor,
+    principal: Option<Principal>,
+    tenant: Tenant,
+) -> Result<()> {
+    let mut tx = begin(&app.pg_pool, &tenant).await?;
+    check_owner(&mut tx, id, principal.as_ref()).await?;
+
+    let deleted = query_as!(
+        Datas,
+        "UPDATE items.datas SET deleted_at = now() WHERE id = $1 AND deleted_at IS NULL RETURNING id, name, flags, sys",
+        id
+    ).fetch_optional(&mut *tx).await?;
+
+    if let Some(deleted) = deleted {
+        record_history(&mut tx, id, Operation::Delete, Some(&deleted), None, &actor).await?;
+    }
+    tx.commit().await?;
+    app.cache.invalidate_datas(&tenant.0, id).await;
 
     Ok(())
 }
+
+/// GET /api/datas/:id/history - Every recorded change of a datas row, newest first
+#[utoipa::path(
+    get, path = "/api/datas/{id}/history", tag = "datas",
+    params(("id" = i32, Path, description = "Datas id"), Consistency),
+    responses((status = OK, body = Vec<HistoryEntry>), ApiErrors)
+)]
+pub async fn get_history(
+    State(app): State<AppState>,
+    Path(id): Path<i32>,
+    tenant: Tenant,
+    consistency: Consistency,
+    Accept(format): Accept,
+) -> Result<Negotiated<Vec<HistoryEntry>>> {
+    let mut tx = begin(app.replicas.pick(consistency).unwrap_or(&app.pg_pool), &tenant).await?;
+    let entries = query_as!(
+        HistoryEntry,
+        r#"SELECT id AS "version!", datas_id, operation, before, after, actor, changed_at
+         FROM items.datas_history WHERE datas_id = $1 ORDER BY id DESC"#,
+        id
+    ).fetch_all(&mut *tx).await?;
+    tx.commit().await?;
+
+    Ok(Negotiated(format, entries))
+}
+
+/// POST /api/datas/:id/history/:version/restore - Put the row back the way `version` left it
+///
+/// Works for trashed and purged rows too, they come back under their old id.
+#[utoipa::path(
+    post, path = "/api/datas/{id}/history/{version}/restore", tag = "datas",
+    params(("id" = i32, Path, description = "Datas id"), ("version" = i64, Path, description = "History entry to restore"), Actor),
+    responses((status = OK, body = Datas), ApiErrors)
+)]
+pub async fn restore_datas(
+    State(app): State<AppState>,
+    Path((id, version)): Path<(i32, i64)>,
+    Actor(actor): Actor,
+    principal: Option<Principal>,
+    tenant: Tenant,
+    Accept(format): Accept,
+) -> Result<Negotiated<Datas>> {
+    let mut tx = begin(&app.pg_pool, &tenant).await?;
+    check_owner(&mut tx, id, principal.as_ref()).await?;
+
+    let snapshot = query!("SELECT after FROM items.datas_history WHERE id = $1 AND datas_id = $2", version, id)
+        .fetch_optional(&mut *tx)
+        .await?
+        .ok_or_else(|| Error::NotFound(format!("Version {} of datas {}", version, id)))?;
+
+    let Some(after) = snapshot.after else {
+        return Err(Error::BadRequest(format!("Version {} deleted the row, there is nothing to restore", version)));
+    };
+    let target: DatasPayload = serde_json::from_value(after)?;
+
+    let before = query_as!(Datas, "SELECT id, name, flags, sys FROM items.datas WHERE id = $1 AND deleted_at IS NULL FOR UPDATE", id)
+        .fetch_optional(&mut *tx)
+        .await?;
+
+    let restored = query_as!(
+        Datas,
+        "INSERT INTO items.datas (id, name, flags, sys, owner) VALUES ($1, $2, $3, $4, $5)
+         ON CONFLICT (id) DO UPDATE SET name = EXCLUDED.name, flags = EXCLUDED.flags, sys = EXCLUDED.sys, deleted_at = NULL
+         RETURNING id, name, flags, sys",
+        id,
+        target.name,
+        target.flags,
+        target.sys,
+        principal.as_ref().map(|p| p.subject.as_str())
+    ).fetch_one(&mut *tx).await?;
+
+    record_history(&mut tx, id, Operation::Restore, before.as_ref(), Some(&restored), &actor).await?;
+    tx.commit().await?;
+    app.cache.invalidate_datas(&tenant.0, id).await;
+
+    Ok(Negotiated(format, restored))
+}
+
+/// GET /api/datas/trash - Deleted rows that can still be restored, most recent first
+#[utoipa::path(get, path = "/api/datas/trash", tag = "datas", params(Consistency), responses((status = OK, body = Vec<TrashedDatas>), ApiErrors))]
+pub async fn get_trash(State(app): State<AppState>, tenant: Tenant, consistency: Consistency, Accept(format): Accept) -> Result<Negotiated<Vec<TrashedDatas>>> {
+    let mut tx = begin(app.replicas.pick(consistency).unwrap_or(&app.pg_pool), &tenant).await?;
+    let trashed = query_as!(
+        TrashedDatas,
+        r#"SELECT id, name, flags, sys, deleted_at AS "deleted_at!"
+           FROM items.datas WHERE deleted_at IS NOT NULL ORDER BY deleted_at DESC"#
+    ).fetch_all(&mut *tx).await?;
+    tx.commit().await?;
+
+    Ok(Negotiated(format, trashed))
+}
+
+/// POST /api/datas/:id/restore - Take a row back out of the trash
+#[utoipa::path(
+    post, path = "/api/datas/{id}/restore", tag = "datas",
+    params(("id" = i32, Path, description = "Datas id"), Actor),
+    responses((status = OK, body = Datas), ApiErrors)
+)]
+pub async fn restore_trashed(
+    State(app): State<AppState>,
+    Path(id): Path<i32>,
+    Actor(actor): Actor,
+    principal: Option<Principal>,
+    tenant: Tenant,
+    Accept(format): Accept,
+) -> Result<Negotiated<Datas>> {
+    let mut tx = begin(&app.pg_pool, &tenant).await?;
+    check_owner(&mut tx, id, principal.as_ref()).await?;
+
+    let restored = query_as!(
+        Datas,
+        "UPDATE items.datas SET deleted_at = NULL WHERE id = $1 AND deleted_at IS NOT NULL RETURNING id, name, flags, sys",
+        id
+    ).fetch_optional(&mut *tx).await?;
+
+    let restored = restored.ok_or_else(|| Error::NotFound(format!("Datas {} is not in the trash", id)))?;
+
+    record_history(&mut tx, id, Operation::Restore, None, Some(&restored), &actor).await?;
+    tx.commit().await?;
+    app.cache.invalidate_datas(&tenant.0, id).await;
+
+    Ok(Negotiated(format, restored))
+}
+
+/// Removes rows that have been in the trash for longer than `retention` in every tenant, auditing each one
+pub async fn purge_trash(pool: &PgPool, retention: Duration) -> Result<u64> {
+    let mut tx = pool.begin().await?;
+    query!("SELECT set_config('app.all_tenants', 'on', true)").fetch_one(&mut *tx).await?;
+
+    let purged = query!(
+        "WITH purged AS (
+             DELETE FROM items.datas WHERE deleted_at < now() - make_interval(secs => $1)
+             RETURNING id, name, flags, sys, tenant_id
+         )
+         INSERT INTO items.datas_history (datas_id, operation, before, after, actor, tenant_id)
+         SELECT id, $2, to_jsonb(purged) - 'tenant_id', NULL, $3, tenant_id FROM purged",
+        retention.as_secs_f64(),
+        Operation::Purge.as_str(),
+        PURGE_ACTOR
+    ).execute(&mut *tx).await?;
+    tx.commit().await?;
+
+    Ok(purged.rows_affected())
+}
+
+/// GET /api/search?q=...&page=&per_page= - Full-text search over datas names and niceties info
+#[utoipa::path(get, path = "/api/search", tag = "datas", params(SearchQuery, Consistency), responses((status = OK, body = SearchResults), ApiErrors))]
+pub async fn search(
+    State(app): State<AppState>,
+    tenant: Tenant,
+    consistency: Consistency,
+    Query(query): Query<SearchQuery>,
+    Accept(format): Accept,
+) -> Result<Negotiated<SearchResults>> {
+    if query.q.trim().is_empty() {
+        return Err(Error::BadRequest("The search query `q` can't be empty".to_string()));
+    }
+
+    let (page, per_page) = query.page();
+    let (limit, offset) = query.limit_offset();
+    let mut tx = begin(app.replicas.pick(consistency).unwrap_or(&app.pg_pool), &tenant).await?;
+
+    // Headlines are costly, so they are only built for the page that is returned. The total is counted over every
+    // hit and comes back on a row of its own when the page is past the end.
+    let rows = query!(
+        r#"WITH query AS (SELECT websearch_to_tsquery('english', $1) AS q),
+         hits AS (
+             SELECT 'datas' AS kind, d.id, d.id AS datas_id, ts_rank(d.search, query.q) AS rank, d.name AS body
+             FROM items.datas d, query
+             WHERE d.search @@ query.q AND d.deleted_at IS NULL
+             UNION ALL
+             SELECT 'niceties', n.id, n.datas_id, ts_rank(n.search, query.q), n.info
+             FROM items.niceties n JOIN items.datas d ON d.id = n.datas_id AND d.deleted_at IS NULL, query
+             WHERE n.search @@ query.q
+         ),
+         counted AS (SELECT count(*) AS total FROM hits),
+         page AS (
+             SELECT * FROM hits ORDER BY rank DESC, kind, id LIMIT $2 OFFSET $3
+         )
+         SELECT page.kind AS "kind?", page.id AS "id?", page.datas_id AS "datas_id?", page.rank AS "rank?",
+                ts_headline('english', page.body, query.q, 'StartSel=<mark>, StopSel=</mark>') AS "headline?",
+                counted.total AS "total!"
+         FROM counted CROSS JOIN query LEFT JOIN page ON true
+         ORDER BY page.rank DESC, page.kind, page.id"#,
+        query.q,
+        limit,
+        offset
+    ).fetch_all(&mut *tx).await?;
+    tx.commit().await?;
+
+    let total = rows.first().map(|row| row.total).unwrap_or_default();
+    let hits = rows
+        .into_iter()
+        .filter_map(|row| Some(SearchHit {
+            kind: row.kind?,
+            id: row.id?,
+            datas_id: row.datas_id?,
+            rank: row.rank?,
+            headline: row.headline?
+        }))
+        .collect();
+
+    Ok(Negotiated(format, SearchResults { total, page, per_page, hits }))
+}
@@ -1,73 +1,626 @@
-use axum::{extract::{Path, State}, Json, http::StatusCode};
-use crate::{error::tok_postgres::{map_pool_error, Error}, prelude::tok_postgres::{AppState, Datas, DatasPayload, Result}};
+use std::time::Duration;
+use axum::{body::Body, extract::{ws::WebSocketUpgrade, Path, Query, State}, http::{HeaderMap, StatusCode}, response::{IntoResponse, Response}};
+use futures::{pin_mut, StreamExt};
+use tokio_postgres::{types::ToSql, Client, Row, Transaction};
+use crate::{audit::{Actor, HistoryEntry, Operation, PURGE_ACTOR}, auth::Principal, cache::{datas_key, datas_list_key}, codec::{Accept, Negotiated, Payload}, error::tok_postgres::{map_pool_error, Error}, events::{self, ChangeEvent, Feed}, export::{self, ExportQuery}, idempotency::IdempotencyKey, import, openapi::ApiErrors, prelude::tok_postgres::{AppState, CreateDatasPayload, Datas, DatasPayload, DatasWithNiceties, ImportQuery, ImportReport, Niceties, PgPool, Result, TrashedDatas, UpsertDatasPayload}, replica::Consistency, search::{SearchHit, SearchQuery, SearchResults}, tenant::Tenant, ws};
 
+/// Opens a transaction that only sees `tenant`'s rows, the row level security policies read `app.tenant`
+async fn begin<'a>(client: &'a mut Client, tenant: &Tenant) -> Result<Transaction<'a>> {
+    let tx = client.transaction().await?;
+    tx.execute("SELECT set_config('app.tenant', $1, true)", &[&tenant.0]).await?;
 
-pub async fn get_datas(State(state): State<AppState>) -> Result<Json<Vec<Datas>>> {
-    let conn = state.pg_pool.get().await.map_err(map_pool_error)?;
+    Ok(tx)
+}
 
-    let res = conn
-        .query(&state.get_datas, &[])
-        .await?
-        .drain(..)
-        .map(|x| {
-            Datas {
+#[utoipa::path(get, path = "/api/datas", tag = "datas", params(Consistency), responses((status = OK, body = Vec<Datas>), ApiErrors))]
+pub async fn get_datas(State(state): State<AppState>, tenant: Tenant, consistency: Consistency, Accept(format): Accept) -> Result<Negotiated<Vec<Datas>>> {
+    let replica = state.replicas.pick_for(&state.cache, consistency);
+    let res = state.cache.get_or_load(&datas_list_key(&tenant.0), || async {
+        let mut conn = replica.unwrap_or(&state.pg_pool).get().await.map_err(map_pool_error)?;
+        let tx = begin(&mut conn, &tenant).await?;
+
+        let rows = match replica {
+            Some(_) => tx.query(SELECT_DATAS, &[]).await?,
+            None => tx.query(&state.get_datas, &[]).await?
+        };
+        let res = rows
+            .into_iter()
+            .map(|x| {
+                Datas {
+                    id: x.get(0),
+                    name: x.get(1),
+                    flags: x.get(2),
+                    sys: x.get(3),
+                }
+            })
+            .collect();
+        tx.commit().await?;
+
+        Ok::<_, Error>(res)
+    }).await?;
+
+    Ok(Negotiated(format, res))
+}
+
+#[utoipa::path(
+    get, path = "/api/datas/export", tag = "datas",
+    params(ExportQuery, Consistency),
+    responses(
+        (status = OK, content((Datas = "application/x-ndjson"), (String = "text/csv"), (Vec<Datas> = "application/json"))),
+        ApiErrors
+    )
+)]
+pub async fn export_datas(
+    State(state): State<AppState>,
+    tenant: Tenant,
+    consistency: Consistency,
+    Query(query): Query<ExportQuery>
+) -> Result<Response> {
+    let replica = state.replicas.pick(consistency);
+    let on_replica = replica.is_some();
+    let mut conn = replica.unwrap_or(&state.pg_pool).get_owned().await.map_err(map_pool_error)?;
+    let get_datas = state.get_datas.clone();
+
+    // The pooled connection moves into the stream so it is only released once the export is done,
+    // the transaction rolls back if the client goes away halfway
+    let rows = async_stream::stream! {
+        let tx = match begin(&mut conn, &tenant).await {
+            Ok(tx) => tx,
+            Err(e) => {
+                yield Err(e);
+                return;
+            }
+        };
+
+        let no_params = std::iter::empty::<&(dyn ToSql + Sync)>();
+        let stream = if on_replica {
+            tx.query_raw(SELECT_DATAS, no_params).await
+        }
+        else {
+            tx.query_raw(&get_datas, no_params).await
+        };
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                yield Err(Error::from(e));
+                return;
+            }
+        };
+        pin_mut!(stream);
+
+        while let Some(row) = stream.next().await {
+            yield row.map_err(Error::from).map(|x| Datas {
                 id: x.get(0),
                 name: x.get(1),
                 flags: x.get(2),
                 sys: x.get(3),
-            }
-        })
-        .collect();
+            });
+        }
+    };
+
+    Ok(export::stream_response(query.format, rows))
+}
 
-    Ok(Json(res))
+/// GET /api/datas/events - Live datas changes as Server-Sent Events, see [`events::sse`]
+#[utoipa::path(
+    get, path = "/api/datas/events", tag = "events",
+    responses((status = OK, body = ChangeEvent<Datas>, content_type = "text/event-stream"), ApiErrors)
+)]
+pub async fn datas_events(feed: State<Feed<Datas>>, tenant: Tenant) -> impl IntoResponse {
+    events::sse(feed, tenant).await
 }
 
+/// GET /ws - Live datas change subscriptions over a WebSocket, see [`ws::handler`]
+#[utoipa::path(
+    get, path = "/ws", tag = "events",
+    responses((status = SWITCHING_PROTOCOLS, description = "Upgraded, `ChangeEvent` messages follow"), ApiErrors)
+)]
+pub async fn datas_socket(socket: WebSocketUpgrade, feed: State<Feed<Datas>>, tenant: Tenant) -> Response {
+    ws::handler(socket, feed, tenant).await
+}
+
+#[utoipa::path(get, path = "/api/datas/{id}", tag = "datas", params(("id" = i32, Path, description = "Datas id"), Consistency), responses((status = OK, body = Datas), ApiErrors))]
 pub async fn get_data(
     State(state): State<AppState>,
-    Path(id): Path<i32>
-) -> Result<Json<Datas>> {
-    let conn = state.pg_pool.get().await.map_err(map_pool_error)?;
+    Path(id): Path<i32>,
+    tenant: Tenant,
+    consistency: Consistency,
+    Accept(format): Accept
+) -> Result<Negotiated<Datas>> {
+    let replica = state.replicas.pick_for(&state.cache, consistency);
+    // Misses are cached too, so hammering an unknown id doesn't reach Postgres either
+    let res = state.cache.get_or_load(&datas_key(&tenant.0, id), || async {
+        let mut conn = replica.unwrap_or(&state.pg_pool).get().await.map_err(map_pool_error)?;
+        let tx = begin(&mut conn, &tenant).await?;
+        let row = match replica {
+            Some(_) => tx.query_opt(SELECT_DATA, &[&id]).await?,
+            None => tx.query_opt(&state.get_data, &[&id]).await?
+        };
+        let row = row.map(|x| datas_from_row(&x));
+        tx.commit().await?;
 
-    let res = conn.query_opt(&state.get_data, &[&id]).await?;
+        Ok::<_, Error>(row)
+    }).await?;
 
     match res {
-        Some(x) => Ok(Json(Datas {
-            id: x.get(0),
-            name: x.get(1),
-            flags: x.get(2),
-            sys: x.get(3),
-        })),
+        Some(x) => Ok(Negotiated(format, x)),
         None => Err(Error::NotFound("Not here btw".to_string()))
     }
 }
 
+/// Prepared as [`AppState::get_datas`] and [`AppState::get_data`], sent as is to replicas
+pub const SELECT_DATAS: &str = "SELECT id, name, flags, sys FROM items.datas WHERE deleted_at IS NULL";
+pub const SELECT_DATA: &str = "SELECT id, name, flags, sys FROM items.datas WHERE id = $1 AND deleted_at IS NULL";
+const SELECT_FOR_UPDATE: &str = "SELECT id, name, flags, sys FROM items.datas WHERE id = $1 AND deleted_at IS NULL FOR UPDATE";
+const SELECT_TRASH: &str = "SELECT id, name, flags, sys, deleted_at FROM items.datas WHERE deleted_at IS NOT NULL ORDER BY deleted_at DESC";
+const RESTORE_TRASHED: &str = "UPDATE items.datas SET deleted_at = NULL WHERE id = $1 AND deleted_at IS NOT NULL RETURNING id, name, flags, sys";
+/// Headlines are costly, so they are only built for the page that is returned.
+/// The total is counted over every hit and comes back on a row of its own when the page is past the end.
+const SEARCH: &str = "
+    WITH query AS (SELECT websearch_to_tsquery('english', $1) AS q),
+    hits AS (
+        SELECT 'datas' AS kind, d.id, d.id AS datas_id, ts_rank(d.search, query.q) AS rank, d.name AS body
+        FROM items.datas d, query
+        WHERE d.search @@ query.q AND d.deleted_at IS NULL
+        UNION ALL
+        SELECT 'niceties', n.id, n.datas_id, ts_rank(n.search, query.q), n.info
+        FROM items.niceties n JOIN items.datas d ON d.id = n.datas_id AND d.deleted_at IS NULL, query
+        WHERE n.search @@ query.q
+    ),
+    counted AS (SELECT count(*) AS total FROM hits),
+    page AS (
+        SELECT * FROM hits ORDER BY rank DESC, kind, id LIMIT $2 OFFSET $3
+    )
+    SELECT page.kind, page.id, page.datas_id, page.rank,
+           ts_headline('english', page.body, query.q, 'StartSel=<mark>, StopSel=</mark>'),
+           counted.total
+    FROM counted CROSS JOIN query LEFT JOIN page ON true
+    ORDER BY page.rank DESC, page.kind, page.id";
+const INSERT_HISTORY: &str = "INSERT INTO items.datas_history (datas_id, operation, before, after, actor) VALUES ($1, $2, $3, $4, $5)";
+
+fn datas_from_row(x: &Row) -> Datas {
+    Datas {
+        id: x.get(0),
+        name: x.get(1),
+        flags: x.get(2),
+        sys: x.get(3),
+    }
+}
+
+fn niceties_from_row(x: &Row) -> Niceties {
+    Niceties {
+        id: x.get(0),
+        datas_id: x.get(1),
+        mem: x.get(2),
+        stack: x.get(3),
+        info: x.get(4),
+    }
+}
+
+fn trashed_from_row(x: &Row) -> TrashedDatas {
+    TrashedDatas {
+        id: x.get(0),
+        name: x.get(1),
+        flags: x.get(2),
+        sys: x.get(3),
+        deleted_at: x.get(4),
+    }
+}
+
+/// Writes the audit row for a change, inside the transaction that made it
+async fn record_history(
+    tx: &Transaction<'_>,
+    datas_id: i32,
+    operation: Operation,
+    before: Option<&Datas>,
+    after: Option<&Datas>,
+    actor: &str
+) -> Result<()> {
+    let before = before.map(serde_json::to_value).transpose()?;
+    let after = after.map(serde_json::to_value).transpose()?;
+
+    tx.execute(INSERT_HISTORY, &[&datas_id, &operation.as_str(), &before, &after, &actor]).await?;
+
+    Ok(())
+}
+
+/// Refuses the change unless `principal` owns row `id`, which stays locked until the transaction ends.
+/// A missing row passes, the caller reports it.
+async fn check_owner(tx: &Transaction<'_>, id: i32, principal: Option<&Principal>) -> Result<()> {
+    let Some(principal) = principal.filter(|p| !p.is_admin()) else {
+        return Ok(());
+    };
+
+    let owner: Option<String> = tx
+        .query_opt("SELECT owner FROM items.datas WHERE id = $1 FOR UPDATE", &[&id])
+        .await?
+        .and_then(|row| row.get(0));
+
+    if principal.may_modify(owner.as_deref()) {
+        Ok(())
+    }
+    else {
+        Err(Error::Forbidden(format!("Datas {} belongs to another subject", id)))
+    }
+}
+
+#[utoipa::path(
+    post, path = "/api/datas", tag = "datas",
+    params(Actor, IdempotencyKey),
+    request_body = CreateDatasPayload,
+    responses((status = CREATED, body = DatasWithNiceties, description = "The new row and its niceties"), ApiErrors)
+)]
 pub async fn create_datas(
     State(state): State<AppState>,
-    Json(payload): Json<DatasPayload>
-) -> Result<(StatusCode, Json<i32>)> {
-    let conn = state.pg_pool.get().await.map_err(map_pool_error)?;
-    let id = conn.query_one(&state.create_datas, &[&payload.name, &payload.flags, &payload.sys]).await?;
+    Actor(actor): Actor,
+    principal: Option<Principal>,
+    tenant: Tenant,
+    Accept(format): Accept,
+    Payload(payload): Payload<CreateDatasPayload>
+) -> Result<(StatusCode, Negotiated<DatasWithNiceties>)> {
+    let mut conn = state.pg_pool.get().await.map_err(map_pool_error)?;
+    let tx = begin(&mut conn, &tenant).await?;
 
-    Ok((StatusCode::CREATED, Json(id.get(0))))
+    let owner = principal.map(|p| p.subject);
+    let row = tx.query_one(
+        "INSERT INTO items.datas (name, flags, sys, owner) VALUES ($1, $2, $3, $4) RETURNING id, name, flags, sys",
+        &[&payload.name, &payload.flags, &payload.sys, &owner]
+    ).await?;
+    let created = datas_from_row(&row);
+
+    // The niceties go in with the row or not at all
+    let niceties = if payload.niceties.is_empty() {
+        Vec::new()
+    }
+    else {
+        let mems: Vec<i64> = payload.niceties.iter().map(|n| n.mem).collect();
+        let stacks: Vec<i16> = payload.niceties.iter().map(|n| n.stack).collect();
+        let infos: Vec<&str> = payload.niceties.iter().map(|n| n.info.as_str()).collect();
+
+        let rows = tx.query(
+            "INSERT INTO items.niceties (datas_id, mem, stack, info)
+             SELECT $1::int, mem, stack, info FROM UNNEST($2::bigint[], $3::smallint[], $4::text[]) AS n (mem, stack, info)
+             RETURNING id, datas_id, mem, stack, info",
+            &[&created.id, &mems, &stacks, &infos]
+        ).await?;
+        rows.iter().map(niceties_from_row).collect()
+    };
+
+    record_history(&tx, created.id, Operation::Insert, None, Some(&created), &actor).await?;
+    tx.commit().await?;
+    state.cache.invalidate_datas(&tenant.0, created.id).await;
+
+    Ok((StatusCode::CREATED, Negotiated(format, DatasWithNiceties { datas: created, niceties })))
 }
 
+#[utoipa::path(
+    put, path = "/api/datas/{id}", tag = "datas",
+    params(("id" = i32, Path, description = "Datas id"), Actor),
+    request_body = DatasPayload,
+    responses((status = OK), ApiErrors)
+)]
 pub async fn edit_datas(
     State(state): State<AppState>,
     Path(id): Path<i32>,
-    Json(payload): Json<DatasPayload>,
+    Actor(actor): Actor,
+    principal: Option<Principal>,
+    tenant: Tenant,
+    Payload(payload): Payload<DatasPayload>,
 ) -> Result<StatusCode> {
-    let conn = state.pg_pool.get().await.map_err(map_pool_error)?;
-    conn.execute(&state.edit_datas, &[&payload.name, &payload.flags, &payload.sys, &id]).await?;
+    let mut conn = state.pg_pool.get().await.map_err(map_pool_error)?;
+    let tx = begin(&mut conn, &tenant).await?;
+    check_owner(&tx, id, principal.as_ref()).await?;
+
+    let before = match tx.query_opt(SELECT_FOR_UPDATE, &[&id]).await? {
+        Some(row) => datas_from_row(&row),
+        None => return Err(Error::NotFound("Not here btw".to_string()))
+    };
+
+    let row = tx.query_one(
+        "UPDATE items.datas SET name = $1, flags = $2, sys = $3 WHERE id = $4 RETURNING id, name, flags, sys",
+        &[&payload.name, &payload.flags, &payload.sys, &id]
+    ).await?;
+    let after = datas_from_row(&row);
+
+    record_history(&tx, id, Operation::Update, Some(&before), Some(&after), &actor).await?;
+    tx.commit().await?;
+    state.cache.invalidate_datas(&tenant.0, id).await;
 
     Ok(StatusCode::OK)
 }
 
+#[utoipa::path(
+    put, path = "/api/datas/by-name/{name}", tag = "datas",
+    params(("name" = String, Path, description = "Datas name, unique within the tenant"), Actor),
+    request_body = UpsertDatasPayload,
+    responses(
+        (status = CREATED, body = Datas, description = "No row had the name, a new one was created"),
+        (status = OK, body = Datas, description = "The row with the name was updated, or taken back out of the trash"),
+        ApiErrors
+    )
+)]
+pub async fn upsert_datas(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+    Actor(actor): Actor,
+    principal: Option<Principal>,
+    tenant: Tenant,
+    Accept(format): Accept,
+    Payload(payload): Payload<UpsertDatasPayload>
+) -> Result<(StatusCode, Negotiated<Datas>)> {
+    let mut conn = state.pg_pool.get().await.map_err(map_pool_error)?;
+    let tx = begin(&mut conn, &tenant).await?;
+
+    let before = tx.query_opt("SELECT id, name, flags, sys FROM items.datas WHERE name = $1 FOR UPDATE", &[&name]).await?;
+    let before = before.as_ref().map(datas_from_row);
+
+    // The ownership check sits in the statement, so a row another request inserted after the lookup is covered too
+    let owner = principal.as_ref().map(|p| p.subject.as_str());
+    let any_owner = principal.as_ref().is_none_or(|p| p.is_admin());
+    let row = tx.query_opt(
+        "INSERT INTO items.datas (name, flags, sys, owner) VALUES ($1, $2, $3, $4)
+         ON CONFLICT (tenant_id, name) DO UPDATE SET flags = EXCLUDED.flags, sys = EXCLUDED.sys, deleted_at = NULL
+         WHERE $5 OR items.datas.owner IS NULL OR items.datas.owner = $4
+         RETURNING id, name, flags, sys, (xmax = 0) AS created",
+        &[&name, &payload.flags, &payload.sys, &owner, &any_owner]
+    ).await?
+    .ok_or_else(|| Error::Forbidden(format!("Datas '{}' belongs to another subject", name)))?;
+
+    let after = datas_from_row(&row);
+    let (status, operation) = if row.get::<_, bool>(4) {
+        (StatusCode::CREATED, Operation::Insert)
+    }
+    else {
+        (StatusCode::OK, Operation::Update)
+    };
+
+    record_history(&tx, after.id, operation, before.as_ref(), Some(&after), &actor).await?;
+    tx.commit().await?;
+    state.cache.invalidate_datas(&tenant.0, after.id).await;
+
+    Ok((status, Negotiated(format, after)))
+}
+
+/// DELETE /api/datas/:id - Move a row to the trash
+#[utoipa::path(delete, path = "/api/datas/{id}", tag = "datas", params(("id" = i32, Path, description = "Datas id"), Actor), responses((status = OK), ApiErrors))]
 pub async fn destroy_datas(
     State(state): State<AppState>,
-    Path(id): Path<i32>
+    Path(id): Path<i32>,
+    Actor(actor): Actor,
+    principal: Option<Principal>,
+    tenant: Tenant
 ) -> Result<()> {
-    let conn = state.pg_pool.get().await.map_err(map_pool_error)?;
-    conn.execute(&state.destroy_datas, &[&id]).await?;
+    let mut conn = state.pg_pool.get().await.map_err(map_pool_error)?;
+    let tx = begin(&mut conn, &tenant).await?;
+    check_owner(&tx, id, principal.as_ref()).await?;
+
+    let deleted = tx.query_opt(
+        "UPDATE items.datas SET deleted_at = now() WHERE id = $1 AND deleted_at IS NULL RETURNING id, name, flags, sys",
+        &[&id]
+    ).await?;
+
+    if let Some(row) = deleted {
+        record_history(&tx, id, Operation::Delete, Some(&datas_from_row(&row)), None, &actor).await?;
+    }
+    tx.commit().await?;
+    state.cache.invalidate_datas(&tenant.0, id).await;
 
     Ok(())
 }
+
+/// GET /api/datas/:id/history - Every recorded change of a datas row, newest first
+#[utoipa::path(
+    get, path = "/api/datas/{id}/history", tag = "datas",
+    params(("id" = i32, Path, description = "Datas id"), Consistency),
+    responses((status = OK, body = Vec<HistoryEntry>), ApiErrors)
+)]
+pub async fn get_history(
+    State(state): State<AppState>,
+    Path(id): Path<i32>,
+    tenant: Tenant,
+    consistency: Consistency,
+    Accept(format): Accept
+) -> Result<Negotiated<Vec<HistoryEntry>>> {
+    let mut conn = state.replicas.pick(consistency).unwrap_or(&state.pg_pool).get().await.map_err(map_pool_error)?;
+    let tx = begin(&mut conn, &tenant).await?;
+
+    let entries = tx
+        .query(
+            "SELECT id, datas_id, operation, before, after, actor, changed_at
+             FROM items.datas_history WHERE datas_id = $1 ORDER BY id DESC",
+            &[&id]
+        )
+        .await?
+        .iter()
+        .map(|x| HistoryEntry {
+            version: x.get(0),
+            datas_id: x.get(1),
+            operation: x.get(2),
+            before: x.get(3),
+            after: x.get(4),
+            actor: x.get(5),
+            changed_at: x.get(6),
+        })
+        .collect();
+    tx.commit().await?;
+
+    Ok(Negotiated(format, entries))
+}
+
+/// POST /api/datas/:id/history/:version/restore - Put the row back the way `version` left it
+///
+/// Works for trashed and purged rows too, they come back under their old id.
+#[utoipa::path(
+    post, path = "/api/datas/{id}/history/{version}/restore", tag = "datas",
+    params(("id" = i32, Path, description = "Datas id"), ("version" = i64, Path, description = "History entry to restore"), Actor),
+    responses((status = OK, body = Datas), ApiErrors)
+)]
+pub async fn restore_datas(
+    State(state): State<AppState>,
+    Path((id, version)): Path<(i32, i64)>,
+    Actor(actor): Actor,
+    principal: Option<Principal>,
+    tenant: Tenant,
+    Accept(format): Accept
+) -> Result<Negotiated<Datas>> {
+    let mut conn = state.pg_pool.get().await.map_err(map_pool_error)?;
+    let tx = begin(&mut conn, &tenant).await?;
+    check_owner(&tx, id, principal.as_ref()).await?;
+
+    let snapshot = tx
+        .query_opt("SELECT after FROM items.datas_history WHERE id = $1 AND datas_id = $2", &[&version, &id])
+        .await?
+        .ok_or_else(|| Error::NotFound(format!("Version {} of datas {}", version, id)))?;
+
+    let Some(after) = snapshot.get::<_, Option<serde_json::Value>>(0) else {
+        return Err(Error::BadRequest(format!("Version {} deleted the row, there is nothing to restore", version)));
+    };
+    let target: DatasPayload = serde_json::from_value(after)?;
+
+    let before = tx.query_opt(SELECT_FOR_UPDATE, &[&id]).await?.map(|row| datas_from_row(&row));
+
+    let owner = principal.map(|p| p.subject);
+    let row = tx.query_one(
+        "INSERT INTO items.datas (id, name, flags, sys, owner) VALUES ($1, $2, $3, $4, $5)
+         ON CONFLICT (id) DO UPDATE SET name = EXCLUDED.name, flags = EXCLUDED.flags, sys = EXCLUDED.sys, deleted_at = NULL
+         RETURNING id, name, flags, sys",
+        &[&id, &target.name, &target.flags, &target.sys, &owner]
+    ).await?;
+    let restored = datas_from_row(&row);
+
+    record_history(&tx, id, Operation::Restore, before.as_ref(), Some(&restored), &actor).await?;
+    tx.commit().await?;
+    state.cache.invalidate_datas(&tenant.0, id).await;
+
+    Ok(Negotiated(format, restored))
+}
+
+/// GET /api/datas/trash - Deleted rows that can still be restored, most recent first
+#[utoipa::path(get, path = "/api/datas/trash", tag = "datas", params(Consistency), responses((status = OK, body = Vec<TrashedDatas>), ApiErrors))]
+pub async fn get_trash(State(state): State<AppState>, tenant: Tenant, consistency: Consistency, Accept(format): Accept) -> Result<Negotiated<Vec<TrashedDatas>>> {
+    let mut conn = state.replicas.pick(consistency).unwrap_or(&state.pg_pool).get().await.map_err(map_pool_error)?;
+    let tx = begin(&mut conn, &tenant).await?;
+
+    let res = tx
+        .query(SELECT_TRASH, &[])
+        .await?
+        .iter()
+        .map(trashed_from_row)
+        .collect();
+    tx.commit().await?;
+
+    Ok(Negotiated(format, res))
+}
+
+/// POST /api/datas/:id/restore - Take a row back out of the trash
+#[utoipa::path(
+    post, path = "/api/datas/{id}/restore", tag = "datas",
+    params(("id" = i32, Path, description = "Datas id"), Actor),
+    responses((status = OK, body = Datas), ApiErrors)
+)]
+pub async fn restore_trashed(
+    State(state): State<AppState>,
+    Path(id): Path<i32>,
+    Actor(actor): Actor,
+    principal: Option<Principal>,
+    tenant: Tenant,
+    Accept(format): Accept
+) -> Result<Negotiated<Datas>> {
+    let mut conn = state.pg_pool.get().await.map_err(map_pool_error)?;
+    let tx = begin(&mut conn, &tenant).await?;
+    check_owner(&tx, id, principal.as_ref()).await?;
+
+    let restored = match tx.query_opt(RESTORE_TRASHED, &[&id]).await? {
+        Some(row) => datas_from_row(&row),
+        None => return Err(Error::NotFound(format!("Datas {} is not in the trash", id)))
+    };
+
+    record_history(&tx, id, Operation::Restore, None, Some(&restored), &actor).await?;
+    tx.commit().await?;
+    state.cache.invalidate_datas(&tenant.0, id).await;
+
+    Ok(Negotiated(format, restored))
+}
+
+/// Removes rows that have been in the trash for longer than `retention` in every tenant, auditing each one
+pub async fn purge_trash(pool: &PgPool, retention: Duration) -> Result<u64> {
+    let mut conn = pool.get().await.map_err(map_pool_error)?;
+    let tx = conn.transaction().await?;
+    tx.batch_execute("SELECT set_config('app.all_tenants', 'on', true)").await?;
+
+    let purged = tx.execute(
+        "WITH purged AS (
+             DELETE FROM items.datas WHERE deleted_at < now() - make_interval(secs => $1)
+             RETURNING id, name, flags, sys, tenant_id
+         )
+         INSERT INTO items.datas_history (datas_id, operation, before, after, actor, tenant_id)
+         SELECT id, $2, to_jsonb(purged) - 'tenant_id', NULL, $3, tenant_id FROM purged",
+        &[&retention.as_secs_f64(), &Operation::Purge.as_str(), &PURGE_ACTOR]
+    ).await?;
+    tx.commit().await?;
+
+    Ok(purged)
+}
+
+/// GET /api/search?q=...&page=&per_page= - Full-text search over datas names and niceties info
+#[utoipa::path(get, path = "/api/search", tag = "datas", params(SearchQuery, Consistency), responses((status = OK, body = SearchResults), ApiErrors))]
+pub async fn search(
+    State(state): State<AppState>,
+    tenant: Tenant,
+    consistency: Consistency,
+    Query(query): Query<SearchQuery>,
+    Accept(format): Accept
+) -> Result<Negotiated<SearchResults>> {
+    if query.q.trim().is_empty() {
+        return Err(Error::BadRequest("The search query `q` can't be empty".to_string()));
+    }
+
+    let (page, per_page) = query.page();
+    let (limit, offset) = query.limit_offset();
+    let mut conn = state.replicas.pick(consistency).unwrap_or(&state.pg_pool).get().await.map_err(map_pool_error)?;
+    let tx = begin(&mut conn, &tenant).await?;
+
+    let rows = tx.query(SEARCH, &[&query.q, &limit, &offset]).await?;
+    tx.commit().await?;
+
+    let total = rows.first().map(|x| x.get::<_, i64>(5)).unwrap_or_default();
+    // A page past the end is a single row holding only the total
+    let hits = rows
+        .iter()
+        .filter(|x| x.get::<_, Option<String>>(0).is_some())
+        .map(|x| SearchHit {
+            kind: x.get(0),
+            id: x.get(1),
+            datas_id: x.get(2),
+            rank: x.get(3),
+            headline: x.get(4),
+        })
+        .collect();
+
+    Ok(Negotiated(format, SearchResults { total, page, per_page, hits }))
+}
+
+#[utoipa::path(
+    post, path = "/api/datas/import", tag = "datas",
+    params(ImportQuery, Actor),
+    request_body(content((String = "text/csv"), (DatasPayload = "application/x-ndjson")), description = "One row per line"),
+    responses((status = OK, body = ImportReport), ApiErrors)
+)]
+pub async fn import_datas(
+    State(state): State<AppState>,
+    tenant: Tenant,
+    Actor(actor): Actor,
+    Query(query): Query<ImportQuery>,
+    Accept(accept): Accept,
+    headers: HeaderMap,
+    body: Body
+) -> Result<Negotiated<ImportReport>> {
+    let format = import::resolve_format(&query, &headers)?;
+    let mut conn = state.pg_pool.get().await.map_err(map_pool_error)?;
+    let tx = begin(&mut conn, &tenant).await?;
+    let report = import::copy_datas(tx.client(), &tenant.0, &actor, format, body).await?;
+    tx.commit().await?;
+    state.cache.invalidate(&[datas_list_key(&tenant.0)]).await;
+
+    Ok(Negotiated(accept, report))
+}
@@ -1,6 +1,22 @@
+use std::net::SocketAddr;
 use anyhow::Result;
 use tokio::net::TcpListener;
-use axum::{Router, routing::get};
+use axum::middleware;
+use utoipa::OpenApi;
+use utoipa_axum::{router::OpenApiRouter, routes};
+use crate::{
+    auth::{self, Auth, KeyStore},
+    cache::{self, Cache},
+    events::{self, Feed, DATAS_CHANNEL, ITEMS_CHANNEL},
+    graphql,
+    grpc,
+    idempotency::{self, Idempotency},
+    openapi::{self, ApiDoc, NicetiesDoc},
+    rate_limit::{self, RateLimiter},
+    replica::{self, Replicas},
+    tls,
+    trash
+};
 
 pub async fn redis() -> Result<()> {
     use bb8_redis::{bb8, RedisConnectionManager};
@@ -17,17 +33,35 @@ pub async fn redis() -> Result<()> {
         .init();
 
     let redis_url = std::env::var("REDIS_URL").expect("REDIS_URL must be set");
-    let manager = RedisConnectionManager::new(redis_url)?;
+    let pubsub_client = redis::Client::open(tls::redis(&redis_url)?)?;
+    let manager = RedisConnectionManager::new(tls::redis(&redis_url)?)?;
     let redis_pool = bb8::Pool::builder().build(manager).await?;
-    let app_state = AppState { redis_pool };
 
-    let app = Router::new()
-        .route("/api/items", get(get_items).post(create_item))
-        .route(
-            "/api/items/{id}",
-            get(get_item).put(update_item).delete(delete_item)
-        )
-        .with_state(app_state);
+    let items_feed = Feed::new();
+    tokio::spawn(events::listen_redis(pubsub_client, ITEMS_CHANNEL, items_feed.clone()));
+
+    let purge_pool = redis_pool.clone();
+    tokio::spawn(trash::purge_job(trash::retention_from_env(), move |retention| {
+        let pool = purge_pool.clone();
+        async move { purge_trash(&pool, retention).await }
+    }));
+
+    let auth_state = Auth::from_env(KeyStore::Redis(redis_pool.clone())).await?;
+    let idempotency = Idempotency::from_env(idempotency::Store::Redis(redis_pool.clone())).await?;
+    let app_state = AppState { redis_pool, layout: Layout::from_env()?, items_feed };
+
+    let rate_limiter = RateLimiter::from_env().await?;
+    // The specification is collected from the same `routes!` that register the handlers, so it can't drift from them
+    let (api, spec) = redis_routes()
+        .merge(OpenApiRouter::from(graphql::routes(graphql::items::schema(app_state.clone()))))
+        .merge(OpenApiRouter::from(grpc::items::routes(app_state.clone())))
+        .layer(middleware::from_fn_with_state(idempotency, idempotency::replay))
+        .layer(middleware::from_fn_with_state(rate_limiter.clone(), rate_limit::limit_caller))
+        .layer(middleware::from_fn_with_state(auth_state, auth::require))
+        .layer(middleware::from_fn_with_state(rate_limiter, rate_limit::limit))
+        .with_state(app_state)
+        .split_for_parts();
+    let app = api.merge(openapi::docs(spec)).merge(graphql::graphiql());
 
     let port = std::env::var("PORT").unwrap_or("3000".to_string());
     let addr = format!("0.0.0.0:{}", port);
@@ -35,14 +69,14 @@ pub async fn redis() -> Result<()> {
 
     tracing::info!("🚀 Server listening on {}", addr);
 
-    axum::serve(listener, app).await?;
+    axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>()).await?;
 
     Ok(())
 }
 
 
 pub async fn sqlx() -> Result<()> {
-    use sqlx::PgPool;
+    use sqlx::{postgres::PgPoolOptions, PgPool};
     use crate::prelude::sqlx::*;
     use crate::api::sqlx::*;
 
@@ -57,25 +91,50 @@ pub async fn sqlx() -> Result<()> {
 
     let database_url = std::env::var("DATABASE_URL").expect("DATABASE_URL must be set");
     let pg_pool = PgPool::connect(&database_url).await?;
-    let app_state = AppState { pg_pool };
 
-    let app = Router::new()
-        .route("/api/datas", get(get_datas).post(create_datas))
-        .route("/api/datas/{id}", get(get_data).put(edit_datas).delete(destroy_datas))
-        .with_state(app_state);
+    let datas_feed = Feed::new();
+    tokio::spawn(events::listen_postgres(database_url, DATAS_CHANNEL, datas_feed.clone()));
+
+    let purge_pool = pg_pool.clone();
+    tokio::spawn(trash::purge_job(trash::retention_from_env(), move |retention| {
+        let pool = purge_pool.clone();
+        async move { purge_trash(&pool, retention).await }
+    }));
+
+    let replicas = Replicas::from_env(|url| {
+        Ok(PgPoolOptions::new().acquire_timeout(replica::CONNECT_TIMEOUT).connect_lazy(url)?)
+    })?;
+    let read_your_writes = replicas.read_your_writes();
+
+    let auth_state = Auth::from_env(KeyStore::Postgres(pg_pool.clone())).await?;
+    let app_state = AppState { pg_pool, replicas, datas_feed, cache: Cache::from_env().await? };
+
+    let idempotency = Idempotency::from_env(idempotency::Store::Postgres(app_state.pg_pool.clone())).await?;
+    let rate_limiter = RateLimiter::from_env().await?;
+    let (api, spec) = sqlx_routes()
+        .merge(OpenApiRouter::from(graphql::routes(graphql::datas::schema(app_state.clone()))))
+        .merge(OpenApiRouter::from(grpc::datas::routes(app_state.clone())))
+        .layer(middleware::from_fn_with_state(read_your_writes, replica::mark_writes))
+        .layer(middleware::from_fn_with_state(idempotency, idempotency::replay))
+        .layer(middleware::from_fn_with_state(rate_limiter.clone(), rate_limit::limit_caller))
+        .layer(middleware::from_fn_with_state(auth_state, auth::require))
+        .layer(middleware::from_fn_with_state(rate_limiter, rate_limit::limit))
+        .with_state(app_state)
+        .split_for_parts();
+    let app = api.merge(openapi::docs(spec)).merge(graphql::graphiql());
 
     let lstn = TcpListener::bind("0.0.0.0:3000").await?;
 
     tracing::info!("🚀 Server listening on http://localhost:3000/api/datas");
 
-    axum::serve(lstn, app).await?;
+    axum::serve(lstn, app.into_make_service_with_connect_info::<SocketAddr>()).await?;
 
     Ok(())
 }
 
 
+/// REST only, GraphQL and gRPC are served by the `redis` and `sqlx` modes
 pub async fn tok_postgres() -> Result<()> {
-    use tokio_postgres::NoTls;
     use bb8_postgres::PostgresConnectionManager;
     use crate::api::tok_postgres::*;
     use crate::prelude::tok_postgres::AppState;
@@ -90,43 +149,68 @@ pub async fn tok_postgres() -> Result<()> {
         .init();
 
     let database_url = std::env::var("DATABASE_URL").expect("DATABASE_URL must be set");
-    let manager = PostgresConnectionManager::new(database_url.parse()?, NoTls);
+    let (config, tls) = tls::postgres(&database_url)?;
+    let manager = PostgresConnectionManager::new(config, tls);
     let pool = bb8::Pool::builder().build(manager).await?;
 
     let conn = pool.get().await?;
-    let gds = conn.prepare("SELECT * FROM items.datas").await?;
-    let gd  = conn.prepare("SELECT * FROM items.datas WHERE id = $1").await?;
-    let cds = conn.prepare("INSERT INTO items.datas (name, flags, sys) VALUES ($1, $2, $3) RETURNING id").await?;
-    let eds = conn.prepare("UPDATE items.datas SET name = $1, flags = $2, sys = $3 WHERE id = $4").await?;
-    let dds = conn.prepare("DELETE FROM items.datas WHERE id = $1").await?;
+    let gds = conn.prepare(SELECT_DATAS).await?;
+    let gd  = conn.prepare(SELECT_DATA).await?;
 
     drop(conn);
 
+    let purge_pool = pool.clone();
+    tokio::spawn(trash::purge_job(trash::retention_from_env(), move |retention| {
+        let pool = purge_pool.clone();
+        async move { purge_trash(&pool, retention).await }
+    }));
+
+    // API keys and idempotency records are read through sqlx
+    let sqlx_pool = sqlx::PgPool::connect_lazy(&database_url)?;
+    let auth_state = Auth::from_env(KeyStore::Postgres(sqlx_pool.clone())).await?;
+
+    let replicas = Replicas::from_env(|url| {
+        let (config, tls) = tls::postgres(url)?;
+        let manager = PostgresConnectionManager::new(config, tls);
+        Ok(bb8::Pool::builder().connection_timeout(replica::CONNECT_TIMEOUT).build_unchecked(manager))
+    })?;
+    let read_your_writes = replicas.read_your_writes();
+
+    let datas_feed = Feed::new();
+    tokio::spawn(events::listen_postgres(database_url, DATAS_CHANNEL, datas_feed.clone()));
+
     let state = AppState {
         pg_pool: pool,
+        replicas,
         get_datas: gds,
         get_data: gd,
-        create_datas: cds,
-        edit_datas: eds,
-        destroy_datas: dds
+        datas_feed,
+        cache: Cache::from_env().await?
     };
 
     let listener = TcpListener::bind("0.0.0.0:3000").await?;
-    let app = Router::new()
-        .route("/api/datas", get(get_datas).post(create_datas))
-        .route("/api/datas/{id}", get(get_data).put(edit_datas).delete(destroy_datas))
-        .with_state(state);
+    let idempotency = Idempotency::from_env(idempotency::Store::Postgres(sqlx_pool)).await?;
+    let rate_limiter = RateLimiter::from_env().await?;
+    let (api, spec) = tok_postgres_routes()
+        .layer(middleware::from_fn_with_state(read_your_writes, replica::mark_writes))
+        .layer(middleware::from_fn_with_state(idempotency, idempotency::replay))
+        .layer(middleware::from_fn_with_state(rate_limiter.clone(), rate_limit::limit_caller))
+        .layer(middleware::from_fn_with_state(auth_state, auth::require))
+        .layer(middleware::from_fn_with_state(rate_limiter, rate_limit::limit))
+        .with_state(state)
+        .split_for_parts();
+    let app = api.merge(openapi::docs(spec));
 
     tracing::info!("🚀 Server listening on http://localhost:3000/api/datas");
 
-    axum::serve(listener, app).await?;
+    axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>()).await?;
 
     Ok(())
 }
 
 
+/// REST only on a single shared connection, GraphQL and gRPC are served by the `redis` and `sqlx` modes
 pub async fn single_tok_postgres() -> Result<()> {
-    use tokio_postgres::NoTls;
     use crate::api::single_tp::*;
     use crate::prelude::tok_postgres::*;
 
@@ -140,7 +224,8 @@ pub async fn single_tok_postgres() -> Result<()> {
         .init();
 
     let database_url = std::env::var("DATABASE_URL").expect("DATABASE_URL must be set");
-    let (client, connection) = tokio_postgres::connect(&database_url, NoTls).await?;
+    let (config, tls) = tls::postgres(&database_url)?;
+    let (client, connection) = config.connect(tls.clone()).await?;
 
     tokio::spawn(async move {
         if let Err(e) = connection.await {
@@ -148,11 +233,74 @@ pub async fn single_tok_postgres() -> Result<()> {
         }
     });
     
-    let gds = client.prepare("SELECT * FROM items.datas").await?;
-    let gd  = client.prepare("SELECT * FROM items.datas WHERE id = $1").await?;
-    let cds = client.prepare("INSERT INTO items.datas (name, flags, sys) VALUES ($1, $2, $3) RETURNING id").await?;
-    let eds = client.prepare("UPDATE items.datas SET name = $1, flags = $2, sys = $3 WHERE id = $4").await?;
-    let dds = client.prepare("DELETE FROM items.datas WHERE id = $1").await?;
+    // One connection serves every tenant, so row level security is lifted for it and each statement
+    // filters on the tenant it is given instead
+    client.batch_execute("SET app.all_tenants = 'on'").await?;
+
+    let gds = client.prepare("SELECT id, name, flags, sys FROM items.datas WHERE tenant_id = $1 AND deleted_at IS NULL").await?;
+    let gd  = client.prepare("SELECT id, name, flags, sys FROM items.datas WHERE id = $1 AND tenant_id = $2 AND deleted_at IS NULL").await?;
+    // The owner parameter of the writes is the subject that has to own the row, NULL when anyone may change it.
+    // Without a transaction per request, each write records its history row in the same statement.
+    let cds = client.prepare(
+        "WITH created AS (
+             INSERT INTO items.datas (name, flags, sys, owner, tenant_id) VALUES ($1, $2, $3, $4, $5) RETURNING id, name, flags, sys
+         ),
+         history AS (
+             INSERT INTO items.datas_history (datas_id, operation, before, after, actor, tenant_id)
+             SELECT id, 'insert', NULL, to_jsonb(created), $6, $5 FROM created
+         )
+         SELECT id FROM created"
+    ).await?;
+    let eds = client.prepare(
+        "WITH before AS (
+             SELECT id, name, flags, sys FROM items.datas
+             WHERE id = $4 AND tenant_id = $6 AND deleted_at IS NULL AND ($5::text IS NULL OR owner IS NULL OR owner = $5)
+             FOR UPDATE
+         ),
+         edited AS (
+             UPDATE items.datas d SET name = $1, flags = $2, sys = $3 FROM before WHERE d.id = before.id
+             RETURNING d.id, d.name, d.flags, d.sys
+         ),
+         history AS (
+             INSERT INTO items.datas_history (datas_id, operation, before, after, actor, tenant_id)
+             SELECT edited.id, 'update', to_jsonb(before), to_jsonb(edited), $7, $6 FROM edited JOIN before USING (id)
+         )
+         SELECT id FROM edited"
+    ).await?;
+    let dds = client.prepare(
+        "WITH deleted AS (
+             UPDATE items.datas SET deleted_at = now()
+             WHERE id = $1 AND tenant_id = $3 AND deleted_at IS NULL AND ($2::text IS NULL OR owner IS NULL OR owner = $2)
+             RETURNING id, name, flags, sys
+         ),
+         history AS (
+             INSERT INTO items.datas_history (datas_id, operation, before, after, actor, tenant_id)
+             SELECT id, 'delete', to_jsonb(deleted), NULL, $4, $3 FROM deleted
+         )
+         SELECT id FROM deleted"
+    ).await?;
+    let tds = client.prepare(
+        "SELECT id, name, flags, sys, deleted_at FROM items.datas WHERE tenant_id = $1 AND deleted_at IS NOT NULL ORDER BY deleted_at DESC"
+    ).await?;
+    let rds = client.prepare(
+        "WITH restored AS (
+             UPDATE items.datas SET deleted_at = NULL
+             WHERE id = $1 AND tenant_id = $3 AND deleted_at IS NOT NULL AND ($2::text IS NULL OR owner IS NULL OR owner = $2)
+             RETURNING id, name, flags, sys
+         ),
+         history AS (
+             INSERT INTO items.datas_history (datas_id, operation, before, after, actor, tenant_id)
+             SELECT id, 'restore', NULL, to_jsonb(restored), $4, $3 FROM restored
+         )
+         SELECT id, name, flags, sys FROM restored"
+    ).await?;
+
+    // API keys and idempotency records are read through sqlx
+    let sqlx_pool = sqlx::PgPool::connect_lazy(&database_url)?;
+    let auth_state = Auth::from_env(KeyStore::Postgres(sqlx_pool.clone())).await?;
+
+    let datas_feed = Feed::new();
+    tokio::spawn(events::listen_postgres(database_url, DATAS_CHANNEL, datas_feed.clone()));
 
     let state = std::sync::Arc::new(PgClient {
         client,
@@ -160,16 +308,208 @@ pub async fn single_tok_postgres() -> Result<()> {
         get_data: gd,
         create_datas: cds,
         edit_datas: eds,
-        destroy_datas: dds
+        destroy_datas: dds,
+        trash_datas: tds,
+        restore_datas: rds,
+        datas_feed,
+        cache: Cache::from_env().await?,
+        config,
+        tls
     });
 
-    let listener = TcpListener::bind("0.0.0.0:3000").await?;
-    let app = Router::new()
-        .route("/api/datas", get(get_datas).post(create_datas))
-        .route("/api/datas/{id}", get(get_data).put(edit_datas).delete(destroy_datas))
-        .with_state(state);
+    let purge_state = state.clone();
+    tokio::spawn(trash::purge_job(trash::retention_from_env(), move |retention| {
+        let state = purge_state.clone();
+        async move { purge_trash(&state, retention).await }
+    }));
 
-    axum::serve(listener, app).await?;
+    let listener = TcpListener::bind("0.0.0.0:3000").await?;
+    let idempotency = Idempotency::from_env(idempotency::Store::Postgres(sqlx_pool)).await?;
+    let rate_limiter = RateLimiter::from_env().await?;
+    let (api, spec) = single_tok_postgres_routes()
+        .layer(middleware::from_fn_with_state(idempotency, idempotency::replay))
+        .layer(middleware::from_fn_with_state(rate_limiter.clone(), rate_limit::limit_caller))
+        .layer(middleware::from_fn_with_state(auth_state, auth::require))
+        .layer(middleware::from_fn_with_state(rate_limiter, rate_limit::limit))
+        .with_state(state)
+        .split_for_parts();
+    let app = api.merge(openapi::docs(spec));
+
+    axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>()).await?;
 
     Ok(())
 }
+
+
+/// REST routes of the `redis` mode, each `routes!` registers a handler and its specification together
+fn redis_routes() -> OpenApiRouter<crate::prelude::redis::AppState> {
+    use crate::api::redis::*;
+
+    OpenApiRouter::with_openapi(ApiDoc::openapi())
+        .routes(routes!(get_items, create_item))
+        .routes(routes!(export_items))
+        .routes(routes!(item_events))
+        .routes(routes!(get_event_history))
+        .routes(routes!(create_event_group))
+        .routes(routes!(read_event_group))
+        .routes(routes!(ack_event_group))
+        .routes(routes!(item_socket))
+        .routes(routes!(get_trash))
+        .routes(routes!(get_item, update_item, patch_item, delete_item))
+        .routes(routes!(restore_item))
+}
+
+/// REST routes of the `sqlx` mode
+fn sqlx_routes() -> OpenApiRouter<crate::prelude::sqlx::AppState> {
+    use crate::api::sqlx::*;
+
+    OpenApiRouter::with_openapi(ApiDoc::openapi().merge_from(NicetiesDoc::openapi()))
+        .routes(routes!(get_datas, create_datas))
+        .routes(routes!(export_datas))
+        .routes(routes!(datas_events))
+        .routes(routes!(datas_socket))
+        .routes(routes!(search))
+        .routes(routes!(get_trash))
+        .routes(routes!(cache::stats))
+        .routes(routes!(get_data, edit_datas, destroy_datas))
+        .routes(routes!(upsert_datas))
+        .routes(routes!(restore_trashed))
+        .routes(routes!(get_history))
+        .routes(routes!(restore_datas))
+}
+
+/// REST routes of the `tok_postgres` mode
+fn tok_postgres_routes() -> OpenApiRouter<crate::prelude::tok_postgres::AppState> {
+    use crate::api::tok_postgres::*;
+
+    OpenApiRouter::with_openapi(ApiDoc::openapi().merge_from(NicetiesDoc::openapi()))
+        .routes(routes!(get_datas, create_datas))
+        .routes(routes!(import_datas))
+        .routes(routes!(export_datas))
+        .routes(routes!(datas_events))
+        .routes(routes!(datas_socket))
+        .routes(routes!(search))
+        .routes(routes!(get_trash))
+        .routes(routes!(cache::stats))
+        .routes(routes!(get_data, edit_datas, destroy_datas))
+        .routes(routes!(upsert_datas))
+        .routes(routes!(restore_trashed))
+        .routes(routes!(get_history))
+        .routes(routes!(restore_datas))
+}
+
+/// REST routes of the `single_tok_postgres` mode
+fn single_tok_postgres_routes() -> OpenApiRouter<std::sync::Arc<crate::prelude::tok_postgres::PgClient>> {
+    use crate::api::{single_tp::*, tok_postgres};
+
+    OpenApiRouter::with_openapi(ApiDoc::openapi().merge_from(NicetiesDoc::openapi()))
+        .routes(routes!(get_datas, create_datas))
+        .routes(routes!(import_datas))
+        .routes(routes!(export_datas))
+        .routes(routes!(tok_postgres::datas_events))
+        .routes(routes!(tok_postgres::datas_socket))
+        .routes(routes!(get_trash))
+        .routes(routes!(cache::stats))
+        .routes(routes!(get_data, edit_datas, destroy_datas))
+        .routes(routes!(restore_trashed))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{BTreeSet, HashMap};
+    use axum::Router;
+    use utoipa::openapi::OpenApi as Spec;
+    use super::*;
+
+    type Routes = BTreeSet<(String, String)>;
+
+    /// Method and path of every route `router` serves.
+    ///
+    /// axum has no route listing, so they are read from its `Debug` output: the path node maps route ids to
+    /// paths and each route's `allow_header` lists its methods. `HEAD` comes with every `GET` and is left out.
+    fn served<S>(router: &Router<S>) -> Routes {
+        let debug = format!("{:?}", router);
+        let path_router = &debug[..debug.find("fallback_router").expect("no fallback router in the Debug output")];
+        let (routes, node) = path_router.split_once("node: Node").expect("no path node in the Debug output");
+
+        let paths: HashMap<&str, &str> = node
+            .split("RouteId(")
+            .skip(1)
+            .filter_map(|entry| {
+                let (id, rest) = entry.split_once("): \"")?;
+                Some((id, rest.split('"').next()?))
+            })
+            .collect();
+
+        routes
+            .split("RouteId(")
+            .skip(1)
+            .flat_map(|entry| {
+                let (id, rest) = entry.split_once(')').expect("unterminated route id");
+                let (_, allowed) = rest.split_once("allow_header: Bytes(b\"").expect("route without allowed methods");
+                let path = paths[id];
+
+                allowed
+                    .split('"')
+                    .next()
+                    .unwrap_or_default()
+                    .split(',')
+                    .filter(|method| *method != "HEAD")
+                    .map(move |method| (method.to_string(), path.to_string()))
+            })
+            .collect()
+    }
+
+    fn documented(spec: &Spec) -> Routes {
+        spec.paths
+            .paths
+            .iter()
+            .flat_map(|(path, item)| {
+                [
+                    ("GET", &item.get),
+                    ("PUT", &item.put),
+                    ("POST", &item.post),
+                    ("DELETE", &item.delete),
+                    ("OPTIONS", &item.options),
+                    ("HEAD", &item.head),
+                    ("PATCH", &item.patch),
+                    ("TRACE", &item.trace)
+                ]
+                .into_iter()
+                .filter(|(_, operation)| operation.is_some())
+                .map(|(method, _)| (method.to_string(), path.clone()))
+            })
+            .collect()
+    }
+
+    fn assert_in_sync<S: Clone + Send + Sync + 'static>(routes: OpenApiRouter<S>) {
+        let (router, spec) = routes.split_for_parts();
+        let served = served(&router);
+        let documented = documented(&spec);
+
+        assert!(!served.is_empty(), "no route read from the router");
+        assert_eq!(served.difference(&documented).collect::<Vec<_>>(), Vec::<&(String, String)>::new(), "served but not documented");
+        assert_eq!(documented.difference(&served).collect::<Vec<_>>(), Vec::<&(String, String)>::new(), "documented but not served");
+    }
+
+    #[test]
+    fn redis_spec_matches_routes() {
+        assert_in_sync(redis_routes());
+    }
+
+    #[test]
+    fn sqlx_spec_matches_routes() {
+        assert_in_sync(sqlx_routes());
+    }
+
+    #[test]
+    fn tok_postgres_spec_matches_routes() {
+        assert_in_sync(tok_postgres_routes());
+    }
+
+    #[test]
+    fn single_tok_postgres_spec_matches_routes() {
+        assert_in_sync(single_tok_postgres_routes());
+    }
+}
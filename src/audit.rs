@@ -0,0 +1,103 @@
+use std::convert::Infallible;
+use axum::{extract::FromRequestParts, http::request::Parts};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use utoipa::{
+    openapi::{path::{Parameter, ParameterBuilder, ParameterIn}, ObjectBuilder, Type},
+    IntoParams, ToSchema
+};
+use async_graphql::SimpleObject;
+use crate::auth::{Principal, Unverified};
+
+/// Header naming who made a change, recorded in `items.datas_history`
+pub const ACTOR_HEADER: &str = "x-actor";
+
+/// Recorded as the actor of rows removed by the trash purge job
+pub const PURGE_ACTOR: &str = "system:purge";
+
+const ANONYMOUS: &str = "anonymous";
+
+/// Put in front of actors taken from `X-Actor`, anyone can send any name in it
+const UNVERIFIED_PREFIX: &str = "unverified:";
+const MAX_ACTOR_LEN: usize = 200;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Operation {
+    Insert,
+    Update,
+    Delete,
+    Restore,
+    Purge
+}
+
+impl Operation {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Insert => "insert",
+            Self::Update => "update",
+            Self::Delete => "delete",
+            Self::Restore => "restore",
+            Self::Purge => "purge"
+        }
+    }
+}
+
+/// One row of `items.datas_history`, `version` is what `restore` takes
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema, SimpleObject)]
+pub struct HistoryEntry {
+    pub version: i64,
+    pub datas_id: i32,
+    pub operation: String,
+    /// The row before the change, empty for inserts
+    pub before: Option<Value>,
+    /// The row after the change, empty for deletes
+    pub after: Option<Value>,
+    pub actor: String,
+    pub changed_at: DateTime<Utc>
+}
+
+/// Who is making the request: the authenticated [`Principal`] when there is one.
+///
+/// Only with authentication disabled is `X-Actor` read, recorded as `unverified:<name>`. Everything else is `anonymous`.
+pub struct Actor(pub String);
+
+impl<S: Send + Sync> FromRequestParts<S> for Actor {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _: &S) -> Result<Self, Self::Rejection> {
+        if let Some(principal) = parts.extensions.get::<Principal>() {
+            return Ok(Self(principal.subject.clone()));
+        }
+
+        // With authentication on, a request without a principal reached a public route and names nobody
+        if parts.extensions.get::<Unverified>().is_none() {
+            return Ok(Self(ANONYMOUS.to_string()));
+        }
+
+        let actor = parts.headers
+            .get(ACTOR_HEADER)
+            .and_then(|v| v.to_str().ok())
+            .map(str::trim)
+            .filter(|v| !v.is_empty())
+            .map(|v| format!("{}{}", UNVERIFIED_PREFIX, v.chars().take(MAX_ACTOR_LEN).collect::<String>()))
+            .unwrap_or_else(|| ANONYMOUS.to_string());
+
+        Ok(Self(actor))
+    }
+}
+
+impl IntoParams for Actor {
+    fn into_params(_: impl Fn() -> Option<ParameterIn>) -> Vec<Parameter> {
+        vec![
+            ParameterBuilder::new()
+                .name(ACTOR_HEADER)
+                .parameter_in(ParameterIn::Header)
+                .description(Some("Recorded as `unverified:<name>`, the author of the change, when authentication is disabled. \
+                                   Ignored otherwise, the credentials name the author"))
+                .schema(Some(ObjectBuilder::new().schema_type(Type::String).max_length(Some(MAX_ACTOR_LEN))))
+                .build()
+        ]
+    }
+}
@@ -0,0 +1,369 @@
+use std::{collections::HashMap, convert::Infallible, fmt, str::FromStr};
+use axum::{
+    extract::{OptionalFromRequestParts, Request, State},
+    http::{HeaderMap, HeaderValue, Method, StatusCode, header::{AUTHORIZATION, WWW_AUTHENTICATE}, request::Parts},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use chrono::{DateTime, Utc};
+use redis::AsyncCommands;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sqlx::{query, query_as, PgPool};
+use crate::{grpc, jwt::{Claims, Jwks}, prelude::redis::RedisPool};
+
+/// Every generated key starts with this, so leaked keys are easy to grep for
+pub const KEY_PREFIX: &str = "hak_";
+
+/// Hash of key hash to the JSON of its [`ApiKey`], used by the redis mode
+const REDIS_KEYS: &str = "api_keys";
+const REDIS_KEY_SEQ: &str = "api_keys:next_id";
+
+/// Characters of a key kept in clear so it can be recognized in listings
+const SHOWN_PREFIX_LEN: usize = 12;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum Scope {
+    #[serde(rename = "items:read")]
+    ItemsRead,
+    #[serde(rename = "items:write")]
+    ItemsWrite,
+    /// Grants everything else too
+    #[serde(rename = "admin")]
+    Admin
+}
+
+impl Scope {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::ItemsRead => "items:read",
+            Self::ItemsWrite => "items:write",
+            Self::Admin => "admin"
+        }
+    }
+
+    /// The scope a request needs, by method and path
+    pub fn required(method: &Method, path: &str) -> Self {
+        if path.starts_with("/api/cache") || (path.ends_with("/events/groups") && method == Method::POST) {
+            Self::Admin
+        }
+        else if method == Method::GET || method == Method::HEAD || path.contains("/events/groups/") || path == "/graphql" || grpc::is_read(path) {
+            // Reading and acknowledging events from an existing group only consumes them,
+            // GraphQL mutations check for `items:write` themselves, and gRPC `Get`, `List` and `Watch` only read
+            Self::ItemsRead
+        }
+        else {
+            Self::ItemsWrite
+        }
+    }
+}
+
+impl fmt::Display for Scope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Scope {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "items:read" => Ok(Self::ItemsRead),
+            "items:write" => Ok(Self::ItemsWrite),
+            "admin" => Ok(Self::Admin),
+            other => Err(format!("Unknown scope: {}. Use 'items:read', 'items:write' or 'admin'.", other))
+        }
+    }
+}
+
+/// A stored key, the key itself is never kept
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ApiKey {
+    pub id: i64,
+    pub name: String,
+    /// The first characters of the key
+    pub prefix: String,
+    pub scopes: Vec<String>,
+    /// The only tenant the key works in, any tenant when empty
+    #[serde(default)]
+    pub tenant_id: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub revoked_at: Option<DateTime<Utc>>
+}
+
+/// A freshly created key, `key` is shown once and can't be recovered afterwards
+pub struct NewKey {
+    pub key: String,
+    pub record: ApiKey
+}
+
+pub fn hash_key(key: &str) -> String {
+    Sha256::digest(key.as_bytes()).iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn generate_key() -> String {
+    let bytes: [u8; 24] = rand::random();
+    let random: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+
+    format!("{}{}", KEY_PREFIX, random)
+}
+
+/// The token of an `Authorization: Bearer <token>` header
+pub fn bearer_token(headers: &HeaderMap) -> Option<&str> {
+    let value = headers.get(AUTHORIZATION)?.to_str().ok()?;
+    let (scheme, token) = value.split_once(' ')?;
+
+    let token = token.trim();
+    (scheme.eq_ignore_ascii_case("bearer") && !token.is_empty()).then_some(token)
+}
+
+/// Where keys are kept, `items.api_keys` for the Postgres modes and the `api_keys` hash for redis
+#[derive(Clone)]
+pub enum KeyStore {
+    Postgres(PgPool),
+    Redis(RedisPool)
+}
+
+impl KeyStore {
+    /// The live key matching `key`, revoked keys are never returned
+    pub async fn find(&self, key: &str) -> anyhow::Result<Option<ApiKey>> {
+        let hash = hash_key(key);
+
+        match self {
+            Self::Postgres(pool) => Ok(query_as!(
+                ApiKey,
+                "SELECT id, name, prefix, scopes, tenant_id, created_at, revoked_at FROM items.api_keys WHERE key_hash = $1 AND revoked_at IS NULL",
+                hash
+            ).fetch_optional(pool).await?),
+            Self::Redis(pool) => {
+                let mut con = pool.get().await?;
+                let json: Option<String> = con.hget(REDIS_KEYS, &hash).await?;
+
+                match json {
+                    Some(json) => {
+                        let record: ApiKey = serde_json::from_str(&json)?;
+                        Ok(record.revoked_at.is_none().then_some(record))
+                    }
+                    None => Ok(None)
+                }
+            }
+        }
+    }
+
+    pub async fn create(&self, name: &str, scopes: &[Scope], tenant: Option<&str>) -> anyhow::Result<NewKey> {
+        let key = generate_key();
+        let hash = hash_key(&key);
+        let prefix: String = key.chars().take(SHOWN_PREFIX_LEN).collect();
+        let scopes: Vec<String> = scopes.iter().map(|s| s.as_str().to_string()).collect();
+
+        let record = match self {
+            Self::Postgres(pool) => query_as!(
+                ApiKey,
+                "INSERT INTO items.api_keys (name, prefix, key_hash, scopes, tenant_id) VALUES ($1, $2, $3, $4, $5)
+                 RETURNING id, name, prefix, scopes, tenant_id, created_at, revoked_at",
+                name, prefix, hash, &scopes[..], tenant
+            ).fetch_one(pool).await?,
+            Self::Redis(pool) => {
+                let mut con = pool.get().await?;
+                let id: i64 = con.incr(REDIS_KEY_SEQ, 1).await?;
+
+                let record = ApiKey {
+                    id,
+                    name: name.to_string(),
+                    prefix,
+                    scopes,
+                    tenant_id: tenant.map(str::to_string),
+                    created_at: Utc::now(),
+                    revoked_at: None
+                };
+                con.hset::<_, _, _, ()>(REDIS_KEYS, &hash, serde_json::to_string(&record)?).await?;
+                record
+            }
+        };
+
+        Ok(NewKey { key, record })
+    }
+
+    /// Every key, revoked ones included, oldest first
+    pub async fn list(&self) -> anyhow::Result<Vec<ApiKey>> {
+        match self {
+            Self::Postgres(pool) => Ok(query_as!(
+                ApiKey,
+                "SELECT id, name, prefix, scopes, tenant_id, created_at, revoked_at FROM items.api_keys ORDER BY id"
+            ).fetch_all(pool).await?),
+            Self::Redis(pool) => {
+                let mut keys: Vec<ApiKey> = redis_records(pool).await?.into_values().collect();
+                keys.sort_by_key(|k| k.id);
+                Ok(keys)
+            }
+        }
+    }
+
+    /// Returns false when no live key has this id
+    pub async fn revoke(&self, id: i64) -> anyhow::Result<bool> {
+        match self {
+            Self::Postgres(pool) => {
+                let revoked = query!("UPDATE items.api_keys SET revoked_at = now() WHERE id = $1 AND revoked_at IS NULL", id)
+                    .execute(pool)
+                    .await?;
+
+                Ok(revoked.rows_affected() > 0)
+            }
+            Self::Redis(pool) => {
+                let records = redis_records(pool).await?;
+                let Some((hash, mut record)) = records.into_iter().find(|(_, k)| k.id == id && k.revoked_at.is_none()) else {
+                    return Ok(false);
+                };
+
+                record.revoked_at = Some(Utc::now());
+                let mut con = pool.get().await?;
+                con.hset::<_, _, _, ()>(REDIS_KEYS, hash, serde_json::to_string(&record)?).await?;
+
+                Ok(true)
+            }
+        }
+    }
+}
+
+async fn redis_records(pool: &RedisPool) -> anyhow::Result<HashMap<String, ApiKey>> {
+    let mut con = pool.get().await?;
+    let raw: HashMap<String, String> = con.hgetall(REDIS_KEYS).await?;
+
+    raw.into_iter()
+        .map(|(hash, json)| Ok::<_, anyhow::Error>((hash, serde_json::from_str::<ApiKey>(&json)?)))
+        .collect()
+}
+
+/// Whoever a request was authenticated as, an API key or the subject of a JWT
+#[derive(Debug, Clone)]
+pub struct Principal {
+    /// `key:<name>` for API keys, the `sub` claim for JWTs
+    pub subject: String,
+    pub scopes: Vec<Scope>,
+    /// Set when the credentials are bound to one tenant
+    pub tenant: Option<String>
+}
+
+impl Principal {
+    pub fn allows(&self, scope: Scope) -> bool {
+        self.scopes.iter().any(|s| *s == scope || *s == Scope::Admin)
+    }
+
+    pub fn is_admin(&self) -> bool {
+        self.scopes.contains(&Scope::Admin)
+    }
+
+    /// Rows without an owner predate authentication and stay open to every writer
+    pub fn may_modify(&self, owner: Option<&str>) -> bool {
+        self.is_admin() || owner.is_none_or(|owner| owner == self.subject)
+    }
+}
+
+impl From<&ApiKey> for Principal {
+    fn from(key: &ApiKey) -> Self {
+        Self {
+            subject: format!("key:{}", key.name),
+            scopes: key.scopes.iter().filter_map(|s| s.parse().ok()).collect(),
+            tenant: key.tenant_id.clone()
+        }
+    }
+}
+
+impl From<&Claims> for Principal {
+    fn from(claims: &Claims) -> Self {
+        Self { subject: claims.sub.clone(), scopes: claims.scopes(), tenant: claims.tenant.clone() }
+    }
+}
+
+/// `None` when authentication is disabled
+impl<S: Send + Sync> OptionalFromRequestParts<S> for Principal {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _: &S) -> Result<Option<Self>, Self::Rejection> {
+        Ok(parts.extensions.get::<Principal>().cloned())
+    }
+}
+
+/// Whether `principal` may change a row owned by `owner`, always true without authentication
+pub fn may_modify(principal: Option<&Principal>, owner: Option<&str>) -> bool {
+    principal.is_none_or(|p| p.may_modify(owner))
+}
+
+/// Request extension set by [`require`] when authentication is disabled, so nobody could be verified
+#[derive(Clone, Copy, Debug)]
+pub struct Unverified;
+
+/// Checks the bearer key or JWT of every request, unless `AUTH_DISABLED=true` is set
+#[derive(Clone)]
+pub struct Auth {
+    store: Option<KeyStore>,
+    jwks: Option<Jwks>
+}
+
+impl Auth {
+    pub async fn from_env(store: KeyStore) -> anyhow::Result<Self> {
+        if std::env::var("AUTH_DISABLED").is_ok_and(|v| v == "true" || v == "1") {
+            tracing::warn!("AUTH_DISABLED is set, every request is let through without a key");
+            return Ok(Self { store: None, jwks: None });
+        }
+
+        Ok(Self { store: Some(store), jwks: Jwks::from_env().await? })
+    }
+}
+
+fn unauthorized(message: &str) -> Response {
+    let mut response = (StatusCode::UNAUTHORIZED, message.to_string()).into_response();
+    response.headers_mut().insert(WWW_AUTHENTICATE, HeaderValue::from_static("Bearer"));
+    response
+}
+
+/// Middleware rejecting requests without a live API key or a valid JWT holding the scope of the route.
+/// The [`Principal`], along with the [`ApiKey`] or the [`Claims`], is handed to handlers as request extensions.
+pub async fn require(State(auth): State<Auth>, mut req: Request, next: Next) -> Response {
+    let Some(store) = &auth.store else {
+        req.extensions_mut().insert(Unverified);
+        return next.run(req).await;
+    };
+
+    let Some(token) = bearer_token(req.headers()) else {
+        return unauthorized("Missing credentials, send an API key or a JWT as 'Authorization: Bearer <token>'");
+    };
+
+    let principal = if token.starts_with(KEY_PREFIX) {
+        let key = match store.find(token).await {
+            Ok(Some(key)) => key,
+            Ok(None) => return unauthorized("Invalid or revoked API key"),
+            Err(e) => {
+                tracing::error!("API key lookup failed: {:?}", e);
+                return (StatusCode::SERVICE_UNAVAILABLE, "Could not verify the API key").into_response();
+            }
+        };
+
+        let principal = Principal::from(&key);
+        req.extensions_mut().insert(key);
+        principal
+    }
+    else {
+        let Some(jwks) = &auth.jwks else {
+            return unauthorized("Invalid API key");
+        };
+
+        let claims = match jwks.verify(token).await {
+            Ok(claims) => claims,
+            Err(e) => return unauthorized(&e)
+        };
+
+        let principal = Principal::from(&claims);
+        req.extensions_mut().insert(claims);
+        principal
+    };
+
+    let scope = Scope::required(req.method(), req.uri().path());
+    if !principal.allows(scope) {
+        return (StatusCode::FORBIDDEN, format!("'{}' lacks the '{}' scope", principal.subject, scope)).into_response();
+    }
+
+    req.extensions_mut().insert(principal);
+    next.run(req).await
+}
@@ -0,0 +1,103 @@
+use anyhow::{anyhow, Result};
+use colored::*;
+use hello_axum::{auth::{KeyStore, Scope}, tenant, tls};
+
+const USAGE: &str = "Usage: api-keys [--redis] create [--tenant <id>] <name> <scope>... | list | revoke <id>";
+
+/// Manages the keys the server accepts in `Authorization: Bearer <key>`.
+///
+/// Keys live in `items.api_keys` through `DATABASE_URL`, or in Redis through `REDIS_URL` with `--redis`.
+/// Scopes are `items:read`, `items:write` and `admin`. A created key is printed once and can't be shown again.
+/// With `--tenant` the key only works in that tenant, otherwise requests pick theirs with `X-Tenant-Id`.
+#[tokio::main]
+async fn main() -> Result<()> {
+    dotenvy::dotenv().ok();
+    let mut args: Vec<String> = std::env::args().skip(1).collect();
+
+    let redis = args.iter().any(|a| a == "--redis");
+    args.retain(|a| a != "--redis");
+
+    let store = if redis {
+        let redis_url = std::env::var("REDIS_URL").expect("REDIS_URL must be set");
+        let manager = bb8_redis::RedisConnectionManager::new(tls::redis(&redis_url)?)?;
+        KeyStore::Redis(bb8::Pool::builder().build(manager).await?)
+    }
+    else {
+        let database_url = std::env::var("DATABASE_URL").expect("DATABASE_URL must be set");
+        KeyStore::Postgres(sqlx::PgPool::connect(&database_url).await?)
+    };
+
+    match args.first().map(String::as_str) {
+        Some("create") => {
+            let tenant = match args.iter().position(|a| a == "--tenant") {
+                Some(pos) => {
+                    let tenant = args.get(pos + 1).cloned().ok_or_else(|| anyhow!(USAGE))?;
+                    tenant::validate(&tenant).map_err(|e| anyhow!(e))?;
+                    args.drain(pos..=pos + 1);
+                    Some(tenant)
+                }
+                None => None
+            };
+
+            let name = args.get(1).ok_or_else(|| anyhow!(USAGE))?;
+            let scopes = args[2..]
+                .iter()
+                .map(|s| s.parse::<Scope>().map_err(|e| anyhow!(e)))
+                .collect::<Result<Vec<_>>>()?;
+
+            if scopes.is_empty() {
+                return Err(anyhow!("At least one scope is required. {}", USAGE));
+            }
+
+            let created = store.create(name, &scopes, tenant.as_deref()).await?;
+            println!("{} Created key {} ({}) with {}.", "✅".green(), created.record.id, name.bold(), created.record.scopes.join(", "));
+            println!("{}", "Store it now, it won't be shown again:".yellow());
+            println!("{}", created.key);
+        }
+        Some("list") => {
+            let keys = store.list().await?;
+
+            if keys.is_empty() {
+                println!("{}", "No API keys yet.".dimmed());
+            }
+
+            for key in keys {
+                let status = match key.revoked_at {
+                    Some(at) => format!("revoked {}", at.to_rfc3339()).red(),
+                    None => "active".green()
+                };
+
+                println!(
+                    "{:>4}  {:<24} {}…  {:<30} {:<16} created {}  {}",
+                    key.id,
+                    key.name.bold(),
+                    key.prefix,
+                    key.scopes.join(","),
+                    key.tenant_id.as_deref().unwrap_or("any tenant"),
+                    key.created_at.to_rfc3339(),
+                    status
+                );
+            }
+        }
+        Some("revoke") => {
+            let id: i64 = args
+                .get(1)
+                .and_then(|v| v.parse().ok())
+                .ok_or_else(|| anyhow!(USAGE))?;
+
+            if store.revoke(id).await? {
+                println!("{} Key {} revoked.", "✅".green(), id);
+            }
+            else {
+                eprintln!("{} No active key with id {}.", "❌".red(), id);
+                std::process::exit(1);
+            }
+        }
+        _ => {
+            eprintln!("{}", USAGE);
+            std::process::exit(2);
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,99 @@
+use anyhow::{anyhow, Result};
+use colored::*;
+use hello_axum::grpc::pb::{
+    datas::{datas_client::DatasClient, DeleteDatasRequest, GetDatasRequest, ListDatasRequest, WatchDatasRequest},
+    items::{items_client::ItemsClient, DeleteItemRequest, GetItemRequest, ListItemsRequest, WatchItemsRequest}
+};
+use hello_axum::tenant::TENANT_HEADER;
+use tonic::{metadata::MetadataValue, transport::Channel, Request, Status};
+
+const USAGE: &str = "Usage: grpc-client items|datas list | get <id> | delete <id> | watch";
+const ENDPOINT: &str = "http://127.0.0.1:3000";
+
+/// Adds `API_KEY` as a bearer token and `TENANT` as `x-tenant-id` to every call, like the HTTP clients
+#[allow(clippy::result_large_err)] // tonic fixes the interceptor signature
+fn credentials(mut req: Request<()>) -> Result<Request<()>, Status> {
+    if let Ok(key) = std::env::var("API_KEY") {
+        let value = MetadataValue::try_from(format!("Bearer {}", key)).map_err(|_| Status::invalid_argument("Invalid API_KEY"))?;
+        req.metadata_mut().insert("authorization", value);
+    }
+
+    if let Ok(tenant) = std::env::var("TENANT") {
+        let value = MetadataValue::try_from(tenant).map_err(|_| Status::invalid_argument("Invalid TENANT"))?;
+        req.metadata_mut().insert(TENANT_HEADER, value);
+    }
+
+    Ok(req)
+}
+
+fn id_arg<T: std::str::FromStr>(args: &[String]) -> Result<T> {
+    args.get(2).and_then(|id| id.parse().ok()).ok_or_else(|| anyhow!(USAGE))
+}
+
+async fn items(channel: Channel, args: &[String]) -> Result<()> {
+    let mut client = ItemsClient::with_interceptor(channel, credentials);
+
+    match args.get(1).map(String::as_str) {
+        Some("list") => {
+            for item in client.list(ListItemsRequest::default()).await?.into_inner().items {
+                println!("{:?}", item);
+            }
+        }
+        Some("get") => println!("{:?}", client.get(GetItemRequest { id: id_arg(args)? }).await?.into_inner()),
+        Some("delete") => {
+            let id = id_arg(args)?;
+            client.delete(DeleteItemRequest { id }).await?;
+            println!("{} Moved item {} to the trash", "✅".green(), id);
+        }
+        Some("watch") => {
+            let mut changes = client.watch(WatchItemsRequest {}).await?.into_inner();
+            while let Some(change) = changes.message().await? {
+                println!("{:?}", change);
+            }
+        }
+        _ => return Err(anyhow!(USAGE))
+    }
+
+    Ok(())
+}
+
+async fn datas(channel: Channel, args: &[String]) -> Result<()> {
+    let mut client = DatasClient::with_interceptor(channel, credentials);
+
+    match args.get(1).map(String::as_str) {
+        Some("list") => {
+            for datas in client.list(ListDatasRequest {}).await?.into_inner().datas {
+                println!("{:?}", datas);
+            }
+        }
+        Some("get") => println!("{:?}", client.get(GetDatasRequest { id: id_arg(args)? }).await?.into_inner()),
+        Some("delete") => {
+            let id = id_arg(args)?;
+            client.delete(DeleteDatasRequest { id }).await?;
+            println!("{} Moved datas {} to the trash", "✅".green(), id);
+        }
+        Some("watch") => {
+            let mut changes = client.watch(WatchDatasRequest {}).await?.into_inner();
+            while let Some(change) = changes.message().await? {
+                println!("{:?}", change);
+            }
+        }
+        _ => return Err(anyhow!(USAGE))
+    }
+
+    Ok(())
+}
+
+/// Calls the gRPC services the `redis` (items) and `sqlx` (datas) modes serve on the HTTP port
+#[tokio::main]
+async fn main() -> Result<()> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let endpoint = std::env::var("GRPC_ENDPOINT").unwrap_or_else(|_| ENDPOINT.to_string());
+    let channel = Channel::from_shared(endpoint)?.connect().await?;
+
+    match args.first().map(String::as_str) {
+        Some("items") => items(channel, &args).await,
+        Some("datas") => datas(channel, &args).await,
+        _ => Err(anyhow!(USAGE))
+    }
+}
@@ -0,0 +1,85 @@
+use anyhow::{anyhow, Result};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use colored::*;
+use hello_axum::jwt::Claims;
+use jsonwebtoken::{encode, Algorithm, EncodingKey, Header};
+use serde_json::{json, Value};
+
+const USAGE: &str = "Usage: jwt-dev keygen <jwks.json> | sign <jwks.json> <sub> [--scope \"items:read items:write\"] [--admin] [--tenant <id>] [--ttl <secs>]";
+const KID: &str = "local-hs256";
+const DEFAULT_TTL: i64 = 60 * 60;
+
+/// Issues HS256 tokens from a locally generated key, for trying out JWT auth without an identity provider.
+///
+/// `keygen` writes a JWKS holding a fresh secret, point the server at it with `JWKS_PATH`.
+/// `sign` prints a token for `sub` signed with that secret.
+fn main() -> Result<()> {
+    let mut args: Vec<String> = std::env::args().skip(1).collect();
+
+    match args.first().map(String::as_str) {
+        Some("keygen") => {
+            let path = args.get(1).ok_or_else(|| anyhow!(USAGE))?;
+            let secret: [u8; 32] = rand::random();
+
+            let jwks = json!({
+                "keys": [{ "kty": "oct", "kid": KID, "alg": "HS256", "k": URL_SAFE_NO_PAD.encode(secret) }]
+            });
+            std::fs::write(path, serde_json::to_string_pretty(&jwks)?)?;
+
+            println!("{} Wrote an HS256 key to {}, start the server with JWKS_PATH={}", "✅".green(), path.bold(), path);
+        }
+        Some("sign") => {
+            let admin = args.iter().any(|a| a == "--admin");
+            args.retain(|a| a != "--admin");
+
+            let scope = take_flag(&mut args, "--scope");
+            let tenant = take_flag(&mut args, "--tenant");
+            let ttl: i64 = match take_flag(&mut args, "--ttl") {
+                Some(ttl) => ttl.parse().map_err(|_| anyhow!("--ttl takes seconds"))?,
+                None => DEFAULT_TTL
+            };
+
+            let (Some(path), Some(sub)) = (args.get(1), args.get(2)) else {
+                return Err(anyhow!(USAGE));
+            };
+
+            let jwks: Value = serde_json::from_slice(&std::fs::read(path)?)?;
+            let key = jwks["keys"]
+                .as_array()
+                .and_then(|keys| keys.iter().find(|k| k["kty"] == "oct"))
+                .ok_or_else(|| anyhow!("{} holds no HS256 key, create one with `jwt-dev keygen`", path))?;
+
+            let secret = URL_SAFE_NO_PAD.decode(key["k"].as_str().unwrap_or_default())?;
+            let claims = Claims {
+                sub: sub.clone(),
+                exp: chrono::Utc::now().timestamp() + ttl,
+                iss: std::env::var("JWT_ISSUER").ok(),
+                scope,
+                roles: if admin { vec!["admin".to_string()] } else { Vec::new() },
+                tenant
+            };
+
+            let header = Header {
+                kid: key["kid"].as_str().map(str::to_string),
+                ..Header::new(Algorithm::HS256)
+            };
+
+            println!("{}", encode(&header, &claims, &EncodingKey::from_secret(&secret))?);
+        }
+        _ => {
+            eprintln!("{}", USAGE);
+            std::process::exit(2);
+        }
+    }
+
+    Ok(())
+}
+
+/// Removes `--flag <value>` from `args` and returns the value
+fn take_flag(args: &mut Vec<String>, flag: &str) -> Option<String> {
+    let pos = args.iter().position(|a| a == flag)?;
+    let value = args.get(pos + 1)?.clone();
+    args.drain(pos..=pos + 1);
+
+    Some(value)
+}
@@ -1,13 +1,31 @@
 use anyhow::{anyhow, Result};
 use colored::*;
-use reqwest::{Client, Method, StatusCode};
+use reqwest::{header::{HeaderMap, HeaderValue, ACCEPT, AUTHORIZATION, CONTENT_TYPE}, Client, Method, RequestBuilder, Response, StatusCode};
+use hello_axum::{codec::Format, tenant::TENANT_HEADER};
+use serde::{de::DeserializeOwned, Serialize};
 use std::io::{self, Write};
+use std::sync::OnceLock;
 use std::time::{Duration, Instant};
 use tokio::io::{AsyncBufReadExt, BufReader};
 use hello_axum::prelude::redis::{CreateItemPayload, Item};
 
 const BASE_URL: &str = "http://127.0.0.1:3000/api";
 
+/// Body format picked with `--format json|msgpack|cbor`, JSON by default
+static FORMAT: OnceLock<Format> = OnceLock::new();
+
+fn format() -> Format {
+    FORMAT.get().copied().unwrap_or_default()
+}
+
+fn with_body<T: Serialize>(rq: RequestBuilder, payload: &T) -> Result<RequestBuilder> {
+    Ok(rq.header(CONTENT_TYPE, format().content_type()).body(format().encode(payload)?))
+}
+
+async fn read_body<T: DeserializeOwned>(res: Response) -> Result<T> {
+    Ok(format().decode(&res.bytes().await?)?)
+}
+
 async fn read_line_prompt(prompt: &str) -> Result<String> {
     print!("{} {}", prompt.cyan(), "> ".cyan());
     io::stdout().flush()?;
@@ -36,6 +54,20 @@ async fn read_usize_prompt(prompt: &str) -> Result<usize> {
     }
 }
 
+async fn read_optional_u64_prompt(prompt: &str) -> Result<Option<u64>> {
+    loop {
+        let line = read_line_prompt(prompt).await?;
+        if line.is_empty() {
+            return Ok(None);
+        }
+
+        match line.parse::<u64>() {
+            Ok(num) => return Ok(Some(num)),
+            Err(_) => eprintln!("{}", "Invalid input. Please enter a number or leave it empty.".red())
+        }
+    }
+}
+
 async fn read_confirmation(prompt: &str) -> Result<bool> {
     loop {
         let line = read_line_prompt(&format!("{} (yes/no)", prompt)).await?;
@@ -88,22 +120,22 @@ async fn handle_get(client: &Client) -> Result<()> {
             println!("{} {} (took {})", "Received status:".dimmed(), status, format_duration(elapsed)); 
 
             if status.is_success() {
-                let text = res.text().await?;
-                if text.is_empty() {
+                let bytes = res.bytes().await?;
+                if bytes.is_empty() {
                     println!("{}", "<empty response>".dimmed());
                 }
                 else {
-                    if let Ok(items) = serde_json::from_str::<Vec<Item>>(&text) {
+                    if let Ok(items) = format().decode::<Vec<Item>>(&bytes) {
                         println!("{} Items received:", "✅ Success!".green());
                         println!("{}", serde_json::to_string_pretty(&items)?);
                     }
-                    else if let Ok(item) = serde_json::from_str::<Item>(&text) {
+                    else if let Ok(item) = format().decode::<Item>(&bytes) {
                         println!("{} Item received:", "✅ Success!".green());
                         println!("{}", serde_json::to_string_pretty(&item)?);
                     }
                     else {
                         println!("{} Response body:", "✅ Success!".green());
-                        println!("{}", text);
+                        println!("{}", String::from_utf8_lossy(&bytes));
                     }
                 }
             }
@@ -127,7 +159,9 @@ async fn handle_post(client: &Client) -> Result<()> {
     let height = read_usize_prompt("Height").await?;
     let weight = read_usize_prompt("Weight").await?;
 
-    let payload = CreateItemPayload { name, description, count, height, weight };
+    let ttl_seconds = read_optional_u64_prompt("TTL in seconds (empty for none)").await?;
+
+    let payload = CreateItemPayload { name, description, count, height, weight, ttl_seconds };
 
     println!("\n{}", "Payload to be sent:".yellow());
     println!("{}", serde_json::to_string_pretty(&payload)?);
@@ -141,13 +175,13 @@ async fn handle_post(client: &Client) -> Result<()> {
     println!("{} {} {}...", "Sending".dimmed(), "POST".green(), url);
 
     let start_time = Instant::now();
-    match client.post(&url).json(&payload).send().await {
+    match with_body(client.post(&url), &payload)?.send().await {
         Ok(res) => {
             let elapsed = start_time.elapsed();
             let status = res.status();
             println!("{} {} (took {})", "Received status:".dimmed(), status, format_duration(elapsed)); 
             if status == StatusCode::CREATED {
-                match res.json::<Item>().await {
+                match read_body::<Item>(res).await {
                     Ok(item) => {
                         println!("{} Item created:", "✅ Success!".green());
                         println!("{}", serde_json::to_string_pretty(&item)?);
@@ -191,7 +225,7 @@ async fn handle_put(client: &Client) -> Result<()> {
         return Ok(());
     }
 
-    let mut item = match get_response.json::<Item>().await {
+    let mut item = match read_body::<Item>(get_response).await {
         Ok(i) => i,
         Err(e) => {
             eprintln!("{}: Failed to parse current item data: {}", "❌ Error".red(), e);
@@ -204,8 +238,9 @@ async fn handle_put(client: &Client) -> Result<()> {
 
     println!("{}", "Step 3: Select fields to update (enter field name or 'done'):".dimmed());
     let mut updated = false;
+    let mut ttl_seconds = None;
     loop {
-        let field = read_line_prompt("Field (name, description, count, height, weight, done)").await?;
+        let field = read_line_prompt("Field (name, description, count, height, weight, ttl, done)").await?;
 
         match field.to_lowercase().as_str() {
             "name" | "n" => {
@@ -228,6 +263,10 @@ async fn handle_put(client: &Client) -> Result<()> {
                 item.weight = read_usize_prompt("New weight").await?;
                 updated = true;
             }
+            "ttl" | "t" => {
+                ttl_seconds = Some(read_optional_u64_prompt("New TTL in seconds (empty or 0 to never expire)").await?.unwrap_or(0));
+                updated = true;
+            }
             "done" | "quit" | "q" => break,
             _ => eprintln!("{}", "Invalid field name.".red()),
         }
@@ -243,7 +282,8 @@ async fn handle_put(client: &Client) -> Result<()> {
         description: item.description,
         count: item.count,
         height: item.height,
-        weight: item.weight
+        weight: item.weight,
+        ttl_seconds
     };
 
     println!("\n{}", "Updated payload to be sent:".yellow());
@@ -257,14 +297,14 @@ async fn handle_put(client: &Client) -> Result<()> {
     println!("{} {} {}...", "Sending".dimmed(), "PUT".yellow(), url);
     let start_time = Instant::now();
     
-    match client.put(&url).json(&payload).send().await {
+    match with_body(client.put(&url), &payload)?.send().await {
         Ok(res) => {
             let elapsed = start_time.elapsed();
             let status = res.status();
             println!("{} {} (took {})", "Received status:".dimmed(), status, format_duration(elapsed)); 
 
             if status.is_success() {
-                match res.json::<Item>().await {
+                match read_body::<Item>(res).await {
                     Ok(updated_item) => {
                         println!("{} Item updated:", "✅ Success!".green());
                         println!("{}", serde_json::to_string_pretty(&updated_item)?);
@@ -348,11 +388,42 @@ async fn prompt_for_method() -> Result<Option<Method>> {
     }
 }
 
+/// Pulls `--format <json|msgpack|cbor>` out of the arguments and builds a client that asks for it
+fn build_client(args: &mut Vec<String>) -> Result<Client> {
+    if let Some(pos) = args.iter().position(|a| a == "--format") {
+        let value = args.get(pos + 1).ok_or_else(|| anyhow!("--format expects json, msgpack or cbor"))?;
+        let format = match value.as_str() {
+            "json" => Format::Json,
+            "msgpack" => Format::MsgPack,
+            "cbor" => Format::Cbor,
+            other => return Err(anyhow!("Unknown format: {}. Use 'json', 'msgpack' or 'cbor'.", other))
+        };
+
+        FORMAT.set(format).ok();
+        args.drain(pos..=pos + 1);
+    }
+
+    let mut headers = HeaderMap::new();
+    headers.insert(ACCEPT, HeaderValue::from_static(format().content_type()));
+
+    if let Ok(key) = std::env::var("API_KEY") {
+        headers.insert(AUTHORIZATION, HeaderValue::from_str(&format!("Bearer {}", key))?);
+    }
+
+    if let Ok(tenant) = std::env::var("TENANT") {
+        headers.insert(TENANT_HEADER, HeaderValue::from_str(&tenant)?);
+    }
+
+    Ok(Client::builder().default_headers(headers).build()?)
+}
+
 #[tokio::main]
 pub async fn main() -> Result<()> {
-    let client = Client::new();
+    let mut args: Vec<String> = std::env::args().skip(1).collect();
+    let client = build_client(&mut args)?;
     println!("{}", "Client Started".bold().cyan());
     println!("{} {}", "Base URL:".dimmed(), BASE_URL);
+    println!("{} {}", "Format:".dimmed(), format().content_type());
 
     loop {
         match prompt_for_method().await? {
@@ -0,0 +1,301 @@
+use anyhow::{anyhow, Result};
+use colored::*;
+use std::collections::{HashMap, HashSet};
+use hello_axum::{api::redis::{item_fields, item_from_hash, queue_index, Keyspace, TENANTS_KEY}, prelude::redis::Item, tenant::{self, DEFAULT_TENANT}, tls};
+
+const ITEM_INDEX_KEY: &str = "items_index";
+const TRASH_KEY: &str = "items_trash";
+const SCAN_BATCH: usize = 500;
+
+/// Keys from before tenants that keep their content when they move into a tenant
+const MOVED_KEYS: [&str; 3] = ["next_item_id", "items_owners", "items:events"];
+
+/// Indexes from before tenants, their members name unprefixed item keys so they are rebuilt instead of moved
+const REBUILT_KEYS: [&str; 6] = ["items_index", "items_by_count", "items_by_height", "items_by_weight", "items_by_name", "items_name_members"];
+
+/// Converts a tenant's `item:{id}` and `trash:item:{id}` JSON blobs into hashes so the server can run with `REDIS_LAYOUT=hash`.
+///
+/// Usage: `redis-migrate [--dry-run] [--tenant <id>] [--reindex | --into-tenant]`. The tenant defaults to `default`.
+/// Blobs that can't be decoded are left untouched and listed at the end, and the process exits with a non-zero
+/// code when any were found.
+///
+/// With `--reindex` nothing is converted, instead the secondary indexes behind the `GET /api/items`
+/// filters are rebuilt for every live item, in whichever layout it is stored.
+///
+/// With `--into-tenant` the unprefixed keys written before tenants existed are moved under the tenant's prefix
+/// and its indexes are rebuilt.
+#[tokio::main]
+async fn main() -> Result<()> {
+    dotenvy::dotenv().ok();
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let dry_run = args.iter().any(|a| a == "--dry-run");
+
+    let tenant = match args.iter().position(|a| a == "--tenant") {
+        Some(pos) => args.get(pos + 1).cloned().ok_or_else(|| anyhow!("--tenant takes a tenant id"))?,
+        None => DEFAULT_TENANT.to_string()
+    };
+    tenant::validate(&tenant).map_err(|e| anyhow!(e))?;
+    let space = Keyspace::new(&tenant);
+
+    let redis_url = std::env::var("REDIS_URL").expect("REDIS_URL must be set");
+    let client = redis::Client::open(tls::redis(&redis_url)?)?;
+    let mut con = client.get_multiplexed_async_connection().await?;
+
+    if args.iter().any(|a| a == "--into-tenant") {
+        into_tenant(&mut con, &space, dry_run).await?;
+        return reindex(&mut con, &space, dry_run).await;
+    }
+
+    if args.iter().any(|a| a == "--reindex") {
+        return reindex(&mut con, &space, dry_run).await;
+    }
+
+    let mut converted = 0usize;
+    let mut already_hashed = 0usize;
+    let mut undecodable: Vec<(String, String)> = Vec::new();
+
+    println!("{}", if dry_run { "Dry run, nothing will be written.".yellow() } else { "Migrating items to hashes...".cyan() });
+
+    // Trashed items are converted too, so they still decode once restored
+    for (pattern, trashed) in [(space.key("item:*"), false), (space.key("trash:item:*"), true)] {
+        let mut cursor = 0u64;
+
+        loop {
+            let (next, keys): (u64, Vec<String>) = redis::cmd("SCAN")
+                .arg(cursor)
+                .arg("MATCH")
+                .arg(&pattern)
+                .arg("COUNT")
+                .arg(SCAN_BATCH)
+                .query_async(&mut con)
+                .await?;
+
+            for key in keys {
+                let kind: String = redis::cmd("TYPE").arg(&key).query_async(&mut con).await?;
+
+                match kind.as_str() {
+                    "hash" => already_hashed += 1,
+                    "string" => {
+                        // The expiry is read with the blob so the hash expires when the blob would have
+                        let (json_str, pttl): (Option<String>, i64) = redis::pipe()
+                            .get(&key)
+                            .pttl(&key)
+                            .query_async(&mut con)
+                            .await?;
+                        let Some(json_str) = json_str else { continue };
+
+                        let item = match serde_json::from_str::<Item>(&json_str) {
+                            Ok(item) => item,
+                            Err(e) => {
+                                undecodable.push((key, e.to_string()));
+                                continue;
+                            }
+                        };
+
+                        let expected_key = if trashed { space.trash_item(item.id) } else { space.item(item.id) };
+                        if expected_key != key {
+                            undecodable.push((key, format!("blob holds id {}", item.id)));
+                            continue;
+                        }
+
+                        if !dry_run {
+                            // Replace the blob, keep its expiry and make sure a live item is indexed, all at once
+                            let mut pipe = redis::pipe();
+                            pipe.atomic()
+                                .del(&key).ignore()
+                                .hset_multiple(&key, &item_fields(&item)).ignore();
+
+                            if pttl > 0 {
+                                pipe.pexpire(&key, pttl).ignore();
+                            }
+
+                            if !trashed {
+                                pipe.sadd(space.key(ITEM_INDEX_KEY), &key).ignore();
+                            }
+
+                            pipe.query_async::<()>(&mut con).await?;
+                        }
+                        converted += 1;
+                    }
+                    other => undecodable.push((key, format!("unexpected type {}", other)))
+                }
+            }
+
+            if next == 0 {
+                break;
+            }
+            cursor = next;
+        }
+    }
+
+    println!("{} {} converted, {} already hashes.", "✅ Done.".green(), converted, already_hashed);
+
+    if !undecodable.is_empty() {
+        eprintln!("{} {} entries could not be decoded and were left as-is:", "❌".red(), undecodable.len());
+        for (key, reason) in &undecodable {
+            eprintln!("  {}: {}", key.bold(), reason);
+        }
+
+        std::process::exit(1);
+    }
+
+    Ok(())
+}
+
+async fn reindex(con: &mut redis::aio::MultiplexedConnection, space: &Keyspace, dry_run: bool) -> Result<()> {
+    let mut cursor = 0u64;
+    let mut indexed = 0usize;
+    let mut undecodable: Vec<(String, String)> = Vec::new();
+
+    println!("{}", if dry_run { "Dry run, nothing will be written.".yellow() } else { "Rebuilding item indexes...".cyan() });
+
+    loop {
+        let (next, keys): (u64, Vec<String>) = redis::cmd("SCAN")
+            .arg(cursor)
+            .arg("MATCH")
+            .arg(space.key("item:*"))
+            .arg("COUNT")
+            .arg(SCAN_BATCH)
+            .query_async(&mut *con)
+            .await?;
+
+        for key in keys {
+            let kind: String = redis::cmd("TYPE").arg(&key).query_async(&mut *con).await?;
+
+            let item = match kind.as_str() {
+                "string" => {
+                    let json_str: Option<String> = redis::cmd("GET").arg(&key).query_async(&mut *con).await?;
+                    let Some(json_str) = json_str else { continue };
+
+                    serde_json::from_str::<Item>(&json_str).map_err(|e| e.to_string())
+                }
+                "hash" => {
+                    let fields: HashMap<String, String> = redis::cmd("HGETALL").arg(&key).query_async(&mut *con).await?;
+                    item_from_hash(&fields)
+                }
+                "none" => continue,
+                other => Err(format!("unexpected type {}", other))
+            };
+
+            match item {
+                Ok(item) if space.item(item.id) == key => {
+                    if !dry_run {
+                        let mut pipe = redis::pipe();
+                        pipe.atomic().sadd(space.key(ITEM_INDEX_KEY), &key).ignore();
+                        queue_index(&mut pipe, space, &item, None);
+                        pipe.query_async::<()>(&mut *con).await?;
+                    }
+                    indexed += 1;
+                }
+                Ok(item) => undecodable.push((key, format!("holds id {}", item.id))),
+                Err(reason) => undecodable.push((key, reason))
+            }
+        }
+
+        if next == 0 {
+            break;
+        }
+        cursor = next;
+    }
+
+    println!("{} {} items of tenant {} indexed.", "✅ Done.".green(), indexed, space.tenant().bold());
+
+    if !undecodable.is_empty() {
+        eprintln!("{} {} entries could not be decoded and were not indexed:", "❌".red(), undecodable.len());
+        for (key, reason) in &undecodable {
+            eprintln!("  {}: {}", key.bold(), reason);
+        }
+
+        std::process::exit(1);
+    }
+
+    Ok(())
+}
+
+/// Moves the keys written before tenants existed under the prefix of `space`.
+/// Keys whose new name is already taken are left where they are and reported.
+async fn into_tenant(con: &mut redis::aio::MultiplexedConnection, space: &Keyspace, dry_run: bool) -> Result<()> {
+    let mut moved = 0usize;
+    let mut taken: Vec<String> = Vec::new();
+
+    println!("{}", if dry_run { "Dry run, nothing will be written.".yellow() } else { format!("Moving items into tenant {}...", space.tenant()).as_str().cyan() });
+
+    // Collected before renaming anything, SCAN may hand out a key twice and a renamed key can't be renamed again
+    let mut legacy: HashSet<String> = HashSet::new();
+
+    for pattern in ["item:*", "trash:item:*"] {
+        let mut cursor = 0u64;
+
+        loop {
+            let (next, keys): (u64, Vec<String>) = redis::cmd("SCAN")
+                .arg(cursor)
+                .arg("MATCH")
+                .arg(pattern)
+                .arg("COUNT")
+                .arg(SCAN_BATCH)
+                .query_async(&mut *con)
+                .await?;
+
+            legacy.extend(keys);
+
+            if next == 0 {
+                break;
+            }
+            cursor = next;
+        }
+    }
+
+    for key in legacy {
+        if dry_run {
+            moved += 1;
+            continue;
+        }
+
+        let renamed: bool = redis::cmd("RENAMENX").arg(&key).arg(space.key(&key)).query_async(&mut *con).await?;
+        if renamed {
+            moved += 1;
+        }
+        else {
+            taken.push(key);
+        }
+    }
+
+    if !dry_run {
+        // Trash members name the trashed keys, so they are rewritten with their deletion time kept
+        let trashed: Vec<(String, f64)> = redis::cmd("ZRANGE").arg(TRASH_KEY).arg(0).arg(-1).arg("WITHSCORES").query_async(&mut *con).await?;
+
+        let mut pipe = redis::pipe();
+        pipe.atomic();
+        for (member, deleted_at) in &trashed {
+            pipe.zadd(space.key(TRASH_KEY), space.key(member), *deleted_at).ignore();
+        }
+        pipe.del(TRASH_KEY).ignore()
+            .del(&REBUILT_KEYS[..]).ignore()
+            .sadd(TENANTS_KEY, space.tenant()).ignore();
+        pipe.query_async::<()>(&mut *con).await?;
+
+        for key in MOVED_KEYS {
+            let exists: bool = redis::cmd("EXISTS").arg(key).query_async(&mut *con).await?;
+
+            if exists {
+                let renamed: bool = redis::cmd("RENAMENX").arg(key).arg(space.key(key)).query_async(&mut *con).await?;
+                if !renamed {
+                    taken.push(key.to_string());
+                }
+            }
+        }
+    }
+
+    println!("{} {} items moved into tenant {}.", "✅ Done.".green(), moved, space.tenant().bold());
+
+    if !taken.is_empty() {
+        eprintln!("{} {} keys were left in place because the tenant already has them:", "❌".red(), taken.len());
+        for key in &taken {
+            eprintln!("  {}", key.bold());
+        }
+
+        std::process::exit(1);
+    }
+
+    Ok(())
+}
@@ -1,13 +1,33 @@
 use anyhow::{anyhow, Result};
 use colored::*;
 use hello_axum::prelude::sqlx::{Datas, DatasPayload};
-use reqwest::{Client, Method, StatusCode};
+use hello_axum::prelude::tok_postgres::{ImportFormat, ImportReport};
+use reqwest::{header::{HeaderMap, HeaderValue, ACCEPT, AUTHORIZATION, CONTENT_TYPE}, Body, Client, Method, RequestBuilder, Response, StatusCode};
+use hello_axum::{codec::Format, tenant::TENANT_HEADER};
+use serde::{de::DeserializeOwned, Serialize};
 use std::io::{self, Write};
+use std::sync::OnceLock;
 use std::time::{Duration, Instant};
 use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio_util::io::ReaderStream;
 
 const BASE_URL: &str = "http://127.0.0.1:3000/api";
 
+/// Body format picked with `--format json|msgpack|cbor`, JSON by default
+static FORMAT: OnceLock<Format> = OnceLock::new();
+
+fn format() -> Format {
+    FORMAT.get().copied().unwrap_or_default()
+}
+
+fn with_body<T: Serialize>(rq: RequestBuilder, payload: &T) -> Result<RequestBuilder> {
+    Ok(rq.header(CONTENT_TYPE, format().content_type()).body(format().encode(payload)?))
+}
+
+async fn read_body<T: DeserializeOwned>(res: Response) -> Result<T> {
+    Ok(format().decode(&res.bytes().await?)?)
+}
+
 async fn read_line_prompt(prompt: &str) -> Result<String> {
     print!("{} {}", prompt.cyan(), "> ".cyan());
     io::stdout().flush()?;
@@ -103,22 +123,22 @@ async fn handle_get(client: &Client) -> Result<()> {
             println!("{} {} (took {})", "Received status:".dimmed(), status, format_duration(elapsed)); 
 
             if status.is_success() {
-                let text = res.text().await?;
-                if text.is_empty() {
+                let bytes = res.bytes().await?;
+                if bytes.is_empty() {
                     println!("{}", "<empty response>".dimmed());
                 }
                 else {
-                    if let Ok(datas) = serde_json::from_str::<Vec<Datas>>(&text) {
+                    if let Ok(datas) = format().decode::<Vec<Datas>>(&bytes) {
                         println!("{} Datas received:", "✅ Success!".green());
                         println!("{}", serde_json::to_string_pretty(&datas)?);
                     }
-                    else if let Ok(item) = serde_json::from_str::<Datas>(&text) {
+                    else if let Ok(item) = format().decode::<Datas>(&bytes) {
                         println!("{} Data received:", "✅ Success!".green());
                         println!("{}", serde_json::to_string_pretty(&item)?);
                     }
                     else {
                         println!("{} Response body:", "✅ Success!".green());
-                        println!("{}", text);
+                        println!("{}", String::from_utf8_lossy(&bytes));
                     }
                 }
             }
@@ -152,7 +172,7 @@ async fn handle_post(client: &Client) -> Result<()> {
 
     let url = format!("{}/datas", BASE_URL);
     println!("{} {} {}...", "Sending".dimmed(), "POST".green(), url);
-    let rq = client.post(&url).json(&payload);
+    let rq = with_body(client.post(&url), &payload)?;
     
     let start_time = Instant::now();
     let res = rq.send().await;
@@ -164,7 +184,7 @@ async fn handle_post(client: &Client) -> Result<()> {
             println!("{} {} (took {})", "Received status:".dimmed(), status, format_duration(elapsed)); 
 
             if status == StatusCode::CREATED {
-                match res.json::<Datas>().await {
+                match read_body::<Datas>(res).await {
                     Ok(item) => {
                         println!("{} Item created:", "✅ Success!".green());
                         println!("{}", serde_json::to_string_pretty(&item)?);
@@ -208,7 +228,7 @@ async fn handle_put(client: &Client) -> Result<()> {
         return Ok(());
     }
 
-    let mut item = match get_response.json::<Datas>().await {
+    let mut item = match read_body::<Datas>(get_response).await {
         Ok(i) => i,
         Err(e) => {
             eprintln!("{}: Failed to parse current item data: {}", "❌ Error".red(), e);
@@ -262,7 +282,7 @@ async fn handle_put(client: &Client) -> Result<()> {
     }
 
     println!("{} {} {}...", "Sending".dimmed(), "PUT".yellow(), url);
-    let rq = client.put(&url).json(&payload);
+    let rq = with_body(client.put(&url), &payload)?;
 
     let start_time = Instant::now();
     let res = rq.send().await;
@@ -274,7 +294,7 @@ async fn handle_put(client: &Client) -> Result<()> {
             println!("{} {} (took {})", "Received status:".dimmed(), status, format_duration(elapsed)); 
 
             if status.is_success() {
-                match res.json::<Datas>().await {
+                match read_body::<Datas>(res).await {
                     Ok(updated_item) => {
                         println!("{} Item updated:", "✅ Success!".green());
                         println!("{}", serde_json::to_string_pretty(&updated_item)?);
@@ -358,11 +378,93 @@ async fn prompt_for_method() -> Result<Option<Method>> {
     }
 }
 
+/// `sqlx-client import <file> [csv|ndjson]` - streams a file into POST /api/datas/import
+async fn handle_import(client: &Client, args: &[String]) -> Result<()> {
+    let path = args.first().ok_or_else(|| anyhow!("Usage: sqlx-client import <file> [csv|ndjson]"))?;
+    let format = match args.get(1).map(String::as_str) {
+        Some("csv") => ImportFormat::Csv,
+        Some("ndjson") => ImportFormat::Ndjson,
+        Some(other) => return Err(anyhow!("Unknown format: {}. Use 'csv' or 'ndjson'.", other)),
+        None if path.ends_with(".ndjson") || path.ends_with(".jsonl") => ImportFormat::Ndjson,
+        None => ImportFormat::Csv
+    };
+    let content_type = match format {
+        ImportFormat::Csv => "text/csv",
+        ImportFormat::Ndjson => "application/x-ndjson"
+    };
+
+    let file = tokio::fs::File::open(path).await?;
+    let url = format!("{}/datas/import", BASE_URL);
+    println!("{} {} {} ({})...", "Streaming".dimmed(), path, url, content_type);
+
+    let start_time = Instant::now();
+    let res = client
+        .post(&url)
+        .header(CONTENT_TYPE, content_type)
+        .body(Body::wrap_stream(ReaderStream::new(file)))
+        .send()
+        .await?;
+    let elapsed = start_time.elapsed();
+
+    let status = res.status();
+    println!("{} {} (took {})", "Received status:".dimmed(), status, format_duration(elapsed));
+
+    if status.is_success() {
+        let report = read_body::<ImportReport>(res).await?;
+        println!("{} Imported {} rows.", "✅ Success!".green(), report.imported);
+
+        if report.rejected > 0 {
+            eprintln!("{} {} rows rejected, lines: {:?}", "⚠️".yellow(), report.rejected, report.rejected_lines);
+        }
+    }
+    else {
+        eprintln!("{}: {}", "❌ Error".red(), res.text().await.unwrap_or_else(|_| "Failed to read error body".to_string()));
+    }
+
+    Ok(())
+}
+
+/// Pulls `--format <json|msgpack|cbor>` out of the arguments and builds a client that asks for it
+fn build_client(args: &mut Vec<String>) -> Result<Client> {
+    if let Some(pos) = args.iter().position(|a| a == "--format") {
+        let value = args.get(pos + 1).ok_or_else(|| anyhow!("--format expects json, msgpack or cbor"))?;
+        let format = match value.as_str() {
+            "json" => Format::Json,
+            "msgpack" => Format::MsgPack,
+            "cbor" => Format::Cbor,
+            other => return Err(anyhow!("Unknown format: {}. Use 'json', 'msgpack' or 'cbor'.", other))
+        };
+
+        FORMAT.set(format).ok();
+        args.drain(pos..=pos + 1);
+    }
+
+    let mut headers = HeaderMap::new();
+    headers.insert(ACCEPT, HeaderValue::from_static(format().content_type()));
+
+    if let Ok(key) = std::env::var("API_KEY") {
+        headers.insert(AUTHORIZATION, HeaderValue::from_str(&format!("Bearer {}", key))?);
+    }
+
+    if let Ok(tenant) = std::env::var("TENANT") {
+        headers.insert(TENANT_HEADER, HeaderValue::from_str(&tenant)?);
+    }
+
+    Ok(Client::builder().default_headers(headers).build()?)
+}
+
 #[tokio::main]
 pub async fn main() -> Result<()> {
-    let client = Client::new();
+    let mut args: Vec<String> = std::env::args().skip(1).collect();
+    let client = build_client(&mut args)?;
+
+    if args.first().map(String::as_str) == Some("import") {
+        return handle_import(&client, &args[1..]).await;
+    }
+
     println!("{}", "Client Started".bold().cyan());
     println!("{} {}", "Base URL:".dimmed(), BASE_URL);
+    println!("{} {}", "Format:".dimmed(), format().content_type());
 
     loop {
         match prompt_for_method().await? {
@@ -0,0 +1,269 @@
+use std::{
+    collections::HashMap,
+    future::Future,
+    sync::{atomic::{AtomicU64, Ordering}, Arc, LazyLock, Mutex},
+};
+use axum::extract::State;
+use bb8_redis::{bb8, RedisConnectionManager};
+use redis::{AsyncCommands, Script};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use utoipa::ToSchema;
+use crate::{codec::{Accept, Negotiated}, openapi::ApiErrors, tls};
+
+const DEFAULT_TTL_SECONDS: u64 = 30;
+
+/// How long the generation of a key outlives its last invalidation, far longer than any load takes
+const GENERATION_TTL_SECONDS: i64 = 3600;
+
+/// SET EX the entry only if its generation is still the one read before loading, so a load that raced an
+/// invalidation never puts back what was just dropped
+static STORE_IF_CURRENT: LazyLock<Script> = LazyLock::new(|| Script::new(r"
+    if (redis.call('GET', KEYS[2]) or '0') ~= ARGV[1] then
+        return 0
+    end
+    redis.call('SET', KEYS[1], ARGV[2], 'EX', ARGV[3])
+    return 1
+"));
+
+/// Counter bumped by every invalidation of `key`
+fn generation_key(key: &str) -> String {
+    format!("{}:generation", key)
+}
+
+/// Cache entry holding a tenant's whole `GET /api/datas` listing
+pub fn datas_list_key(tenant: &str) -> String {
+    format!("{}:cache:datas:all", tenant)
+}
+
+pub fn datas_key(tenant: &str, id: i32) -> String {
+    format!("{}:cache:datas:{}", tenant, id)
+}
+
+type RedisPool = bb8::Pool<RedisConnectionManager>;
+
+#[derive(Default)]
+struct Counters {
+    hits: AtomicU64,
+    misses: AtomicU64,
+    coalesced: AtomicU64,
+    errors: AtomicU64
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
+pub struct CacheStats {
+    pub enabled: bool,
+    pub ttl_seconds: u64,
+    pub hits: u64,
+    /// Lookups that went to Postgres
+    pub misses: u64,
+    /// Misses that waited for another request's load instead of querying Postgres themselves
+    pub coalesced: u64,
+    /// Redis failures, the request fell back to Postgres each time
+    pub errors: u64
+}
+
+/// Read-through Redis cache for the Postgres backends, enabled by setting `CACHE_REDIS_URL`.
+///
+/// Concurrent misses on the same key are collapsed into a single load. Redis trouble never fails
+/// a request, it only turns the lookup into a miss.
+#[derive(Clone)]
+pub struct Cache {
+    redis: Option<RedisPool>,
+    ttl: u64,
+    inflight: Arc<Mutex<HashMap<String, Arc<tokio::sync::Mutex<()>>>>>,
+    counters: Arc<Counters>
+}
+
+impl Cache {
+    pub fn disabled() -> Self {
+        Self {
+            redis: None,
+            ttl: DEFAULT_TTL_SECONDS,
+            inflight: Default::default(),
+            counters: Default::default()
+        }
+    }
+
+    /// Connects to `CACHE_REDIS_URL` with entries living `CACHE_TTL_SECONDS`, or stays disabled without it
+    pub async fn from_env() -> anyhow::Result<Self> {
+        let Ok(url) = std::env::var("CACHE_REDIS_URL") else {
+            return Ok(Self::disabled());
+        };
+
+        let ttl = std::env::var("CACHE_TTL_SECONDS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(DEFAULT_TTL_SECONDS);
+
+        let pool = bb8::Pool::builder().build(RedisConnectionManager::new(tls::redis(&url)?)?).await?;
+        tracing::info!("Caching datas reads in Redis for {}s", ttl);
+
+        Ok(Self { redis: Some(pool), ttl, ..Self::disabled() })
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.redis.is_some()
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            enabled: self.redis.is_some(),
+            ttl_seconds: self.ttl,
+            hits: self.counters.hits.load(Ordering::Relaxed),
+            misses: self.counters.misses.load(Ordering::Relaxed),
+            coalesced: self.counters.coalesced.load(Ordering::Relaxed),
+            errors: self.counters.errors.load(Ordering::Relaxed)
+        }
+    }
+
+    /// Returns the cached value under `key`, or runs `load` and caches what it returns
+    pub async fn get_or_load<T, E, F, Fut>(&self, key: &str, load: F) -> Result<T, E>
+    where
+        T: Serialize + DeserializeOwned,
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<T, E>>
+    {
+        let Some(pool) = &self.redis else {
+            return load().await;
+        };
+
+        if let Some(value) = self.lookup(pool, key).await {
+            self.counters.hits.fetch_add(1, Ordering::Relaxed);
+            return Ok(value);
+        }
+
+        let flight = self.inflight.lock().unwrap().entry(key.to_string()).or_default().clone();
+        let guard = flight.lock().await;
+
+        // Whoever held the flight before us has most likely filled the entry
+        let result = match self.lookup(pool, key).await {
+            Some(value) => {
+                self.counters.coalesced.fetch_add(1, Ordering::Relaxed);
+                Ok(value)
+            }
+            None => {
+                self.counters.misses.fetch_add(1, Ordering::Relaxed);
+
+                // Read before loading, an invalidation landing during the load bumps it and the result isn't kept
+                let generation = self.generation(pool, key).await;
+                let loaded = load().await;
+                if let (Ok(value), Some(generation)) = (&loaded, generation) {
+                    self.store(pool, key, value, generation).await;
+                }
+                loaded
+            }
+        };
+
+        drop(guard);
+        self.land(key, flight);
+
+        result
+    }
+
+    /// Drops the tenant's listing and the entry of one row, call it once the change is committed
+    pub async fn invalidate_datas(&self, tenant: &str, id: i32) {
+        self.invalidate(&[datas_list_key(tenant), datas_key(tenant, id)]).await;
+    }
+
+    /// Drops `keys` and bumps their generations, so loads already running don't cache what they read
+    pub async fn invalidate(&self, keys: &[String]) {
+        let Some(pool) = &self.redis else { return };
+
+        let mut pipe = redis::pipe();
+        pipe.atomic().del(keys).ignore();
+        for key in keys {
+            let generation = generation_key(key);
+            pipe.incr(&generation, 1).ignore()
+                .expire(&generation, GENERATION_TTL_SECONDS).ignore();
+        }
+
+        let deleted = match pool.get().await {
+            Ok(mut con) => pipe.query_async::<()>(&mut *con).await.map_err(|e| e.to_string()),
+            Err(e) => Err(e.to_string())
+        };
+
+        // A failed invalidation leaves stale entries behind until their TTL runs out
+        if let Err(e) = deleted {
+            self.counters.errors.fetch_add(1, Ordering::Relaxed);
+            tracing::error!("Failed to invalidate {:?}: {}", keys, e);
+        }
+    }
+
+    async fn lookup<T: DeserializeOwned>(&self, pool: &RedisPool, key: &str) -> Option<T> {
+        let cached = match pool.get().await {
+            Ok(mut con) => con.get::<_, Option<String>>(key).await.map_err(|e| e.to_string()),
+            Err(e) => Err(e.to_string())
+        };
+
+        match cached {
+            Ok(Some(json)) => match serde_json::from_str(&json) {
+                Ok(value) => Some(value),
+                Err(e) => {
+                    tracing::warn!("Ignoring undecodable cache entry {}: {:?}", key, e);
+                    None
+                }
+            },
+            Ok(None) => None,
+            Err(e) => {
+                self.counters.errors.fetch_add(1, Ordering::Relaxed);
+                tracing::warn!("Cache lookup of {} failed: {}", key, e);
+                None
+            }
+        }
+    }
+
+    /// Current generation of `key`, `None` when Redis can't tell and the load shouldn't be cached
+    async fn generation(&self, pool: &RedisPool, key: &str) -> Option<i64> {
+        let generation = match pool.get().await {
+            Ok(mut con) => con.get::<_, Option<i64>>(generation_key(key)).await.map_err(|e| e.to_string()),
+            Err(e) => Err(e.to_string())
+        };
+
+        match generation {
+            Ok(generation) => Some(generation.unwrap_or(0)),
+            Err(e) => {
+                self.counters.errors.fetch_add(1, Ordering::Relaxed);
+                tracing::warn!("Cache generation lookup of {} failed: {}", key, e);
+                None
+            }
+        }
+    }
+
+    async fn store<T: Serialize>(&self, pool: &RedisPool, key: &str, value: &T, generation: i64) {
+        let stored = match (serde_json::to_string(value), pool.get().await) {
+            (Ok(json), Ok(mut con)) => STORE_IF_CURRENT
+                .key(key)
+                .key(generation_key(key))
+                .arg(generation)
+                .arg(json)
+                .arg(self.ttl)
+                .invoke_async::<()>(&mut *con)
+                .await
+                .map_err(|e| e.to_string()),
+            (Err(e), _) => Err(e.to_string()),
+            (_, Err(e)) => Err(e.to_string())
+        };
+
+        if let Err(e) = stored {
+            self.counters.errors.fetch_add(1, Ordering::Relaxed);
+            tracing::warn!("Failed to cache {}: {}", key, e);
+        }
+    }
+
+    /// Forgets the flight for `key` once nobody else is waiting on it
+    fn land(&self, key: &str, flight: Arc<tokio::sync::Mutex<()>>) {
+        let mut inflight = self.inflight.lock().unwrap();
+
+        // One reference in the map, one held here
+        let ours = inflight.get(key).is_some_and(|current| Arc::ptr_eq(current, &flight));
+        if ours && Arc::strong_count(&flight) == 2 {
+            inflight.remove(key);
+        }
+    }
+}
+
+/// GET /api/cache/stats - Hit and miss counters of the datas cache
+#[utoipa::path(get, path = "/api/cache/stats", tag = "cache", responses((status = OK, body = CacheStats), ApiErrors))]
+pub async fn stats(State(cache): State<Cache>, Accept(format): Accept) -> Negotiated<CacheStats> {
+    Negotiated(format, cache.stats())
+}
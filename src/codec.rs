@@ -0,0 +1,150 @@
+use axum::{
+    body::Bytes,
+    extract::{FromRequest, FromRequestParts, Request},
+    http::{header::{ACCEPT, CONTENT_TYPE}, request::Parts, HeaderValue},
+    response::{IntoResponse, Response}
+};
+use serde::{de::DeserializeOwned, Serialize};
+use crate::error::codec::Error;
+
+/// Body formats the API can speak, picked from `Content-Type` on the way in and `Accept` on the way out
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Format {
+    #[default]
+    Json,
+    MsgPack,
+    Cbor
+}
+
+impl Format {
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Self::Json => "application/json",
+            Self::MsgPack => "application/msgpack",
+            Self::Cbor => "application/cbor"
+        }
+    }
+
+    /// Matches a single media type, ignoring parameters like `charset`
+    pub fn from_mime(mime: &str) -> Option<Self> {
+        let essence = mime.split(';').next()?.trim().to_ascii_lowercase();
+
+        match essence.as_str() {
+            "application/json" => Some(Self::Json),
+            "application/msgpack" | "application/x-msgpack" | "application/vnd.msgpack" => Some(Self::MsgPack),
+            "application/cbor" => Some(Self::Cbor),
+            s if s.starts_with("application/") && s.ends_with("+json") => Some(Self::Json),
+            _ => None
+        }
+    }
+
+    /// Picks the supported format with the highest `q` from an `Accept` header.
+    /// Wildcards resolve to JSON, and `None` means nothing acceptable was offered.
+    pub fn from_accept(accept: &str) -> Option<Self> {
+        let mut best: Option<(Self, f32)> = None;
+
+        for range in accept.split(',') {
+            let mut parts = range.split(';');
+            let mime = parts.next().unwrap_or_default().trim();
+            let q = parts
+                .filter_map(|p| p.trim().strip_prefix("q="))
+                .find_map(|q| q.parse::<f32>().ok())
+                .unwrap_or(1.0);
+
+            if q <= 0.0 {
+                continue;
+            }
+
+            let format = match mime {
+                "*/*" | "application/*" => Some(Self::Json),
+                _ => Self::from_mime(mime)
+            };
+
+            if let Some(format) = format
+                && best.is_none_or(|(_, best_q)| q > best_q)
+            {
+                best = Some((format, q));
+            }
+        }
+
+        best.map(|(format, _)| format)
+    }
+
+    pub fn encode<T: Serialize>(self, value: &T) -> Result<Vec<u8>, Error> {
+        match self {
+            Self::Json => serde_json::to_vec(value).map_err(|e| Error::Encode(e.to_string())),
+            Self::MsgPack => rmp_serde::to_vec_named(value).map_err(|e| Error::Encode(e.to_string())),
+            Self::Cbor => {
+                let mut buf = Vec::new();
+                ciborium::into_writer(value, &mut buf).map_err(|e| Error::Encode(e.to_string()))?;
+                Ok(buf)
+            }
+        }
+    }
+
+    pub fn decode<T: DeserializeOwned>(self, bytes: &[u8]) -> Result<T, Error> {
+        match self {
+            Self::Json => serde_json::from_slice(bytes).map_err(|e| Error::Decode(e.to_string())),
+            Self::MsgPack => rmp_serde::from_slice(bytes).map_err(|e| Error::Decode(e.to_string())),
+            Self::Cbor => ciborium::from_reader(bytes).map_err(|e| Error::Decode(e.to_string()))
+        }
+    }
+}
+
+/// Response format negotiated from the `Accept` header, JSON when the header is missing
+pub struct Accept(pub Format);
+
+impl<S: Send + Sync> FromRequestParts<S> for Accept {
+    type Rejection = Error;
+
+    async fn from_request_parts(parts: &mut Parts, _: &S) -> Result<Self, Self::Rejection> {
+        let Some(accept) = parts.headers.get(ACCEPT) else {
+            return Ok(Self(Format::Json));
+        };
+
+        let accept = accept.to_str().map_err(|_| Error::NotAcceptable("Malformed Accept header".to_string()))?;
+
+        match Format::from_accept(accept) {
+            Some(format) => Ok(Self(format)),
+            None => Err(Error::NotAcceptable(accept.to_string()))
+        }
+    }
+}
+
+/// Request body decoded according to its `Content-Type`, the negotiated counterpart of `Json<T>`
+pub struct Payload<T>(pub T);
+
+impl<T, S> FromRequest<S> for Payload<T>
+where
+    T: DeserializeOwned,
+    S: Send + Sync
+{
+    type Rejection = Error;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        let content_type = req.headers()
+            .get(CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or_default()
+            .to_string();
+
+        let format = Format::from_mime(&content_type).ok_or(Error::UnsupportedMediaType(content_type))?;
+        let bytes = Bytes::from_request(req, state).await.map_err(|e| Error::BodyRead(e.body_text()))?;
+
+        Ok(Self(format.decode(&bytes)?))
+    }
+}
+
+/// Response body encoded in the format picked by [`Accept`]
+pub struct Negotiated<T>(pub Format, pub T);
+
+impl<T: Serialize> IntoResponse for Negotiated<T> {
+    fn into_response(self) -> Response {
+        let Self(format, value) = self;
+
+        match format.encode(&value) {
+            Ok(body) => ([(CONTENT_TYPE, HeaderValue::from_static(format.content_type()))], body).into_response(),
+            Err(e) => e.into_response()
+        }
+    }
+}
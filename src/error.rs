@@ -8,6 +8,9 @@ pub mod redis {
         JsonError(serde_json::Error),
         NotFound(String),
         BadRequest(String),
+        Forbidden(String),
+        Conflict(String),
+        Undecodable(String),
         PoolError(String)
     }
     impl std::error::Error for Error {}
@@ -46,6 +49,12 @@ pub mod redis {
                 }
                 Error::NotFound(resource) => (StatusCode::NOT_FOUND, format!("Resource not found: {}", resource)),
                 Error::BadRequest(msg) => (StatusCode::BAD_REQUEST, msg),
+                Error::Forbidden(msg) => (StatusCode::FORBIDDEN, msg),
+                Error::Conflict(msg) => (StatusCode::CONFLICT, msg),
+                Error::Undecodable(key) => {
+                    tracing::error!("Undecodable item: {}", key);
+                    (StatusCode::INTERNAL_SERVER_ERROR, format!("Internal Server Error: Stored item could not be decoded: {}", key))
+                }
                 Error::PoolError(e) => {
                     tracing::error!("Redis Pool error: {:?}", e);
                     (StatusCode::INTERNAL_SERVER_ERROR, "Internal Server Error: Failed to get connection".to_string())
@@ -56,6 +65,22 @@ pub mod redis {
         }
     }
     
+    impl From<Error> for tonic::Status {
+        fn from(err: Error) -> Self {
+            match err {
+                Error::NotFound(resource) => Self::not_found(format!("Resource not found: {}", resource)),
+                Error::BadRequest(msg) => Self::invalid_argument(msg),
+                Error::JsonError(e) => Self::invalid_argument(format!("JSON processing error: {}", e)),
+                Error::Forbidden(msg) => Self::permission_denied(msg),
+                Error::Conflict(msg) => Self::already_exists(msg),
+                other => {
+                    tracing::error!("gRPC call failed: {:?}", other);
+                    Self::internal("Internal Server Error")
+                }
+            }
+        }
+    }
+    
     pub fn map_pool_error<E: std::error::Error + 'static>(e: bb8::RunError<E>) -> Error {
         Error::PoolError(e.to_string())
     }
@@ -72,6 +97,9 @@ pub mod sqlx {
         JsonError(serde_json::Error),
         NotFound(String),
         BadRequest(String),
+        Forbidden(String),
+        /// A write hit a unique constraint, such as a datas name already taken in the tenant
+        Conflict(String),
         PoolError(String)
     }
     impl std::error::Error for Error {}
@@ -87,7 +115,10 @@ pub mod sqlx {
 
     impl From<sqlx::Error> for Error {
         fn from(err: sqlx::Error) -> Self {
-            Error::PostgresError(err)
+            match err.as_database_error() {
+                Some(db) if db.is_unique_violation() => Error::Conflict(db.message().to_string()),
+                _ => Error::PostgresError(err)
+            }
         }
     }
 
@@ -110,6 +141,8 @@ pub mod sqlx {
                 }
                 Error::NotFound(resource) => (StatusCode::NOT_FOUND, format!("Resource not found: {}", resource)),
                 Error::BadRequest(msg) => (StatusCode::BAD_REQUEST, msg),
+                Error::Forbidden(msg) => (StatusCode::FORBIDDEN, msg),
+                Error::Conflict(msg) => (StatusCode::CONFLICT, msg),
                 Error::PoolError(e) => {
                     tracing::error!("Postgres Pool error: {:?}", e);
                     (StatusCode::INTERNAL_SERVER_ERROR, "Internal Server Error: Failed to get connection".to_string())
@@ -119,11 +152,28 @@ pub mod sqlx {
             (status, error_message).into_response()
         }
     }
+
+    impl From<Error> for tonic::Status {
+        fn from(err: Error) -> Self {
+            match err {
+                Error::NotFound(resource) => Self::not_found(format!("Resource not found: {}", resource)),
+                Error::BadRequest(msg) => Self::invalid_argument(msg),
+                Error::JsonError(e) => Self::invalid_argument(format!("JSON processing error: {}", e)),
+                Error::Forbidden(msg) => Self::permission_denied(msg),
+                Error::Conflict(msg) => Self::already_exists(msg),
+                other => {
+                    tracing::error!("gRPC call failed: {:?}", other);
+                    Self::internal("Internal Server Error")
+                }
+            }
+        }
+    }
 }
 
 pub mod tok_postgres {
     use std::fmt;
     use axum::{http::StatusCode, response::{IntoResponse, Response}};
+    use tokio_postgres::error::SqlState;
 
     #[derive(Debug)]
     pub enum Error {
@@ -131,6 +181,9 @@ pub mod tok_postgres {
         JsonError(serde_json::Error),
         NotFound(String),
         BadRequest(String),
+        Forbidden(String),
+        /// A write hit a unique constraint, such as a datas name already taken in the tenant
+        Conflict(String),
         PoolError(String)
     }
     impl std::error::Error for Error {}
@@ -146,7 +199,10 @@ pub mod tok_postgres {
 
     impl From<tokio_postgres::Error> for Error {
         fn from(err: tokio_postgres::Error) -> Self {
-            Error::PostgresError(err)
+            match err.as_db_error() {
+                Some(db) if *db.code() == SqlState::UNIQUE_VIOLATION => Error::Conflict(db.message().to_string()),
+                _ => Error::PostgresError(err)
+            }
         }
     }
 
@@ -169,6 +225,8 @@ pub mod tok_postgres {
                 }
                 Error::NotFound(resource) => (StatusCode::NOT_FOUND, format!("Resource not found: {}", resource)),
                 Error::BadRequest(msg) => (StatusCode::BAD_REQUEST, msg),
+                Error::Forbidden(msg) => (StatusCode::FORBIDDEN, msg),
+                Error::Conflict(msg) => (StatusCode::CONFLICT, msg),
                 Error::PoolError(e) => {
                     tracing::error!("Postgres Pool error: {:?}", e);
                     (StatusCode::INTERNAL_SERVER_ERROR, "Internal Server Error: Failed to get connection".to_string())
@@ -183,3 +241,48 @@ pub mod tok_postgres {
         Error::PoolError(e.to_string())
     }
 }
+
+
+pub mod codec {
+    use std::fmt;
+    use axum::{http::StatusCode, response::{IntoResponse, Response}};
+
+    #[derive(Debug)]
+    pub enum Error {
+        UnsupportedMediaType(String),
+        NotAcceptable(String),
+        BodyRead(String),
+        Decode(String),
+        Encode(String)
+    }
+    impl std::error::Error for Error {}
+
+    impl fmt::Display for Error {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "{:?}", self)
+        }
+    }
+
+    impl IntoResponse for Error {
+        fn into_response(self) -> Response {
+            let (status, error_message) = match self {
+                Error::UnsupportedMediaType(content_type) => (
+                    StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                    format!("Unsupported Content-Type '{}', expected application/json, application/msgpack or application/cbor", content_type)
+                ),
+                Error::NotAcceptable(accept) => (
+                    StatusCode::NOT_ACCEPTABLE,
+                    format!("None of '{}' is available, use application/json, application/msgpack or application/cbor", accept)
+                ),
+                Error::BodyRead(msg) => (StatusCode::BAD_REQUEST, msg),
+                Error::Decode(msg) => (StatusCode::BAD_REQUEST, format!("Body decoding error: {}", msg)),
+                Error::Encode(msg) => {
+                    tracing::error!("Encoding error: {:?}", msg);
+                    (StatusCode::INTERNAL_SERVER_ERROR, "Internal Server Error: Failed to encode response".to_string())
+                }
+            };
+
+            (status, error_message).into_response()
+        }
+    }
+}
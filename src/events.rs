@@ -0,0 +1,253 @@
+use std::{convert::Infallible, time::Duration};
+use axum::{extract::State, response::sse::{Event, KeepAlive, Sse}};
+use futures::{Stream, StreamExt};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use tokio::sync::{broadcast::{self, error::RecvError}, mpsc};
+use tokio_postgres::AsyncMessage;
+use utoipa::ToSchema;
+use async_graphql::Enum;
+use crate::{tenant::Tenant, tls};
+
+/// Redis Pub/Sub channel the item handlers publish their changes on
+pub const ITEMS_CHANNEL: &str = "items:events";
+
+/// Capped Redis Stream every item change is appended to, so offline consumers can replay what they missed.
+/// Each tenant has its own, prefixed with the tenant id.
+pub const ITEMS_STREAM: &str = "items:events";
+
+/// Postgres `NOTIFY` channel fed by the trigger on `items.datas`
+pub const DATAS_CHANNEL: &str = "datas_events";
+
+/// How many events a slow subscriber may fall behind before it starts missing some
+const FEED_CAPACITY: usize = 1024;
+
+/// Delay before a dropped Redis or Postgres listener connection is retried
+const RECONNECT_DELAY: Duration = Duration::from_secs(2);
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, ToSchema, Enum)]
+#[serde(rename_all = "lowercase")]
+pub enum ChangeKind {
+    Created,
+    Updated,
+    Deleted
+}
+
+impl ChangeKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Created => "created",
+            Self::Updated => "updated",
+            Self::Deleted => "deleted"
+        }
+    }
+}
+
+/// One mutation of an entity, `data` is the entity after the change and empty for deletes
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
+pub struct ChangeEvent<T> {
+    pub kind: ChangeKind,
+    pub id: i64,
+    pub data: Option<T>,
+    /// Tenant the entity belongs to, subscribers only see their own tenant's changes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tenant: Option<String>
+}
+
+/// In-process fan-out of change events to every connected subscriber
+#[derive(Clone)]
+pub struct Feed<T>(broadcast::Sender<ChangeEvent<T>>);
+
+impl<T: Clone> Feed<T> {
+    pub fn new() -> Self {
+        Self(broadcast::channel(FEED_CAPACITY).0)
+    }
+
+    pub fn publish(&self, event: ChangeEvent<T>) {
+        // No receivers just means nobody is listening right now
+        let _ = self.0.send(event);
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<ChangeEvent<T>> {
+        self.0.subscribe()
+    }
+}
+
+impl<T: Clone> Default for Feed<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The changes published on `feed` that belong to `tenant`, `Err` counts the events a subscriber that fell behind missed
+fn tenant_changes<T: Clone + Send + 'static>(feed: &Feed<T>, tenant: Tenant) -> impl Stream<Item = Result<ChangeEvent<T>, u64>> + use<T> {
+    let mut rx = feed.subscribe();
+
+    async_stream::stream! {
+        loop {
+            match rx.recv().await {
+                Ok(event) if tenant.owns(event.tenant.as_deref()) => yield Ok(event),
+                Ok(_) => {}
+                Err(RecvError::Lagged(missed)) => yield Err(missed),
+                Err(RecvError::Closed) => break
+            }
+        }
+    }
+}
+
+/// The changes published on `feed` that belong to `tenant`, a subscriber that falls behind skips what it missed
+pub fn changes<T: Clone + Send + 'static>(feed: &Feed<T>, tenant: Tenant) -> impl Stream<Item = ChangeEvent<T>> + use<T> {
+    tenant_changes(feed, tenant).filter_map(|change| async move {
+        change.inspect_err(|missed| tracing::warn!("A subscriber missed {} change events", missed)).ok()
+    })
+}
+
+/// GET /api/{items,datas}/events - Server-Sent Events stream of `created`/`updated`/`deleted` changes in the caller's tenant.
+/// A subscriber that falls behind gets a `lagged` event holding how many changes it missed.
+pub async fn sse<T>(State(feed): State<Feed<T>>, tenant: Tenant) -> Sse<impl Stream<Item = Result<Event, Infallible>>>
+where
+    T: Serialize + Clone + Send + Sync + 'static
+{
+    let stream = tenant_changes(&feed, tenant).filter_map(|change| async move {
+        match change {
+            Ok(event) => match Event::default().event(event.kind.as_str()).id(event.id.to_string()).json_data(&event) {
+                Ok(sse_event) => Some(Ok(sse_event)),
+                Err(e) => {
+                    tracing::error!("Failed to encode change event: {:?}", e);
+                    None
+                }
+            },
+            Err(missed) => Some(Ok(Event::default().event("lagged").data(missed.to_string())))
+        }
+    });
+
+    Sse::new(stream).keep_alive(KeepAlive::default())
+}
+
+/// Forwards JSON change events published on a Redis channel into `feed`, reconnecting when the connection drops
+pub async fn listen_redis<T>(client: redis::Client, channel: &'static str, feed: Feed<T>)
+where
+    T: DeserializeOwned + Clone + Send + 'static
+{
+    loop {
+        match client.get_async_pubsub().await {
+            Ok(mut pubsub) => {
+                if let Err(e) = pubsub.subscribe(channel).await {
+                    tracing::error!("Failed to subscribe to {}: {:?}", channel, e);
+                }
+                else {
+                    let mut messages = pubsub.on_message();
+
+                    while let Some(msg) = messages.next().await {
+                        let payload: String = match msg.get_payload() {
+                            Ok(payload) => payload,
+                            Err(e) => {
+                                tracing::warn!("Unreadable message on {}: {:?}", channel, e);
+                                continue;
+                            }
+                        };
+
+                        match serde_json::from_str(&payload) {
+                            Ok(event) => feed.publish(event),
+                            Err(e) => tracing::warn!("Undecodable change event on {}: {:?}", channel, e)
+                        }
+                    }
+
+                    tracing::warn!("Redis subscription to {} ended", channel);
+                }
+            }
+            Err(e) => tracing::error!("Redis pub/sub connection failed: {:?}", e)
+        }
+
+        tokio::time::sleep(RECONNECT_DELAY).await;
+    }
+}
+
+/// Reads back a datas row announced with `refetch`, in the shape the trigger sends
+const REFETCH_DATAS: &str = "SELECT json_build_object('id', id, 'name', name, 'flags', flags, 'sys', sys) FROM items.datas WHERE id = $1";
+
+/// A `NOTIFY` payload, `refetch` replaces `data` when the row didn't fit in one
+#[derive(Deserialize)]
+struct Notification<T> {
+    #[serde(flatten)]
+    event: ChangeEvent<T>,
+    #[serde(default)]
+    refetch: bool
+}
+
+/// Fills in the row of a notification sent without it, a row gone in the meantime is announced as is
+async fn complete<T: DeserializeOwned>(client: &tokio_postgres::Client, payload: &str) -> anyhow::Result<ChangeEvent<T>> {
+    let Notification { mut event, refetch } = serde_json::from_str::<Notification<T>>(payload)?;
+
+    if refetch && event.kind != ChangeKind::Deleted {
+        let id = i32::try_from(event.id)?;
+        if let Some(row) = client.query_opt(REFETCH_DATAS, &[&id]).await? {
+            event.data = Some(serde_json::from_value(row.get(0))?);
+        }
+    }
+
+    Ok(event)
+}
+
+/// Forwards `NOTIFY` payloads sent on `channel` into `feed`, reconnecting when the connection drops.
+///
+/// Payloads are the JSON built by the `items.notify_datas_change()` trigger, rows too large for one are
+/// read back by id.
+pub async fn listen_postgres<T>(database_url: String, channel: &'static str, feed: Feed<T>)
+where
+    T: DeserializeOwned + Clone + Send + 'static
+{
+    let (config, tls) = match tls::postgres(&database_url) {
+        Ok(settings) => settings,
+        Err(e) => {
+            tracing::error!("Postgres listener on {} can't start: {:?}", channel, e);
+            return;
+        }
+    };
+
+    loop {
+        match config.connect(tls.clone()).await {
+            Ok((client, mut connection)) => {
+                let (notifications, mut payloads) = mpsc::unbounded_channel();
+                let mut messages = futures::stream::poll_fn(move |cx| connection.poll_message(cx));
+
+                // The connection only makes progress while its messages are polled, so this has to run alongside
+                // LISTEN and the reads of rows sent by id
+                let driver = tokio::spawn(async move {
+                    while let Some(message) = messages.next().await {
+                        match message {
+                            Ok(AsyncMessage::Notification(n)) => {
+                                let _ = notifications.send(n.payload().to_string());
+                            }
+                            Ok(_) => {}
+                            Err(e) => {
+                                tracing::error!("Postgres listener connection error: {:?}", e);
+                                break;
+                            }
+                        }
+                    }
+                });
+
+                // Rows sent by id are read back whatever their tenant
+                let listen = format!("SET app.all_tenants = 'on'; LISTEN {}", channel);
+                match client.batch_execute(&listen).await {
+                    Ok(()) => {
+                        while let Some(payload) = payloads.recv().await {
+                            match complete(&client, &payload).await {
+                                Ok(event) => feed.publish(event),
+                                Err(e) => tracing::warn!("Undecodable change event on {}: {:?}", channel, e)
+                            }
+                        }
+                        tracing::warn!("Postgres listener on {} disconnected", channel);
+                    }
+                    Err(e) => {
+                        tracing::error!("Failed to LISTEN on {}: {:?}", channel, e);
+                        driver.abort();
+                    }
+                }
+            }
+            Err(e) => tracing::error!("Postgres listener connection failed: {:?}", e)
+        }
+
+        tokio::time::sleep(RECONNECT_DELAY).await;
+    }
+}
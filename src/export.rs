@@ -0,0 +1,155 @@
+use std::io;
+use axum::{body::Body, http::header::CONTENT_TYPE, response::{IntoResponse, Response}};
+use bytes::{BufMut, BytesMut};
+use futures::{pin_mut, Stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+
+/// Encoded rows are flushed to the response once this many bytes are buffered
+const CHUNK_SIZE: usize = 32 * 1024;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    #[default]
+    Ndjson,
+    Csv,
+    Json
+}
+
+impl ExportFormat {
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Self::Ndjson => "application/x-ndjson",
+            Self::Csv => "text/csv",
+            Self::Json => "application/json"
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ExportQuery {
+    #[serde(default)]
+    #[param(inline)]
+    pub format: ExportFormat
+}
+
+/// Rows that can be written as a CSV record
+pub trait CsvRecord {
+    const HEADER: &'static str;
+
+    fn write_csv(&self, out: &mut BytesMut);
+}
+
+/// Turns a stream of rows into a chunked response body.
+///
+/// Rows are encoded as they are pulled from `rows`, so memory stays bounded by `CHUNK_SIZE`
+/// no matter how many rows the source yields. A source error aborts the body mid-stream.
+pub fn stream_response<T, S, E>(format: ExportFormat, rows: S) -> Response
+where
+    T: Serialize + CsvRecord + Send + 'static,
+    S: Stream<Item = Result<T, E>> + Send + 'static,
+    E: std::error::Error + Send + Sync + 'static
+{
+    let body = async_stream::stream! {
+        pin_mut!(rows);
+
+        let mut buf = BytesMut::with_capacity(CHUNK_SIZE);
+        let mut first = true;
+
+        match format {
+            ExportFormat::Csv => buf.put_slice(T::HEADER.as_bytes()),
+            ExportFormat::Json => buf.put_u8(b'['),
+            ExportFormat::Ndjson => {}
+        }
+
+        while let Some(row) = rows.next().await {
+            let row = match row {
+                Ok(row) => row,
+                Err(e) => {
+                    tracing::error!("Export aborted: {:?}", e);
+                    yield Err(io::Error::other(e));
+                    return;
+                }
+            };
+
+            match format {
+                ExportFormat::Csv => row.write_csv(&mut buf),
+                ExportFormat::Ndjson | ExportFormat::Json => {
+                    if format == ExportFormat::Json && !first {
+                        buf.put_u8(b',');
+                    }
+
+                    if let Err(e) = serde_json::to_writer((&mut buf).writer(), &row) {
+                        yield Err(io::Error::other(e));
+                        return;
+                    }
+
+                    if format == ExportFormat::Ndjson {
+                        buf.put_u8(b'\n');
+                    }
+                }
+            }
+            first = false;
+
+            if buf.len() >= CHUNK_SIZE {
+                yield Ok::<_, io::Error>(buf.split().freeze());
+            }
+        }
+
+        if format == ExportFormat::Json {
+            buf.put_u8(b']');
+        }
+
+        if !buf.is_empty() {
+            yield Ok(buf.freeze());
+        }
+    };
+
+    ([(CONTENT_TYPE, format.content_type())], Body::from_stream(body)).into_response()
+}
+
+/// Writes one CSV field, quoting it only when needed
+pub fn write_csv_field(out: &mut BytesMut, field: &str) {
+    if field.contains([',', '"', '\n', '\r']) {
+        out.put_u8(b'"');
+        out.put_slice(field.replace('"', "\"\"").as_bytes());
+        out.put_u8(b'"');
+    }
+    else {
+        out.put_slice(field.as_bytes());
+    }
+}
+
+impl CsvRecord for crate::prelude::sqlx::Datas {
+    const HEADER: &'static str = "id,name,flags,sys\n";
+
+    fn write_csv(&self, out: &mut BytesMut) {
+        out.put_slice(format!("{},", self.id).as_bytes());
+        write_csv_field(out, &self.name);
+        out.put_slice(format!(",{},{}\n", self.flags, self.sys).as_bytes());
+    }
+}
+
+impl CsvRecord for crate::prelude::tok_postgres::Datas {
+    const HEADER: &'static str = "id,name,flags,sys\n";
+
+    fn write_csv(&self, out: &mut BytesMut) {
+        out.put_slice(format!("{},", self.id).as_bytes());
+        write_csv_field(out, &self.name);
+        out.put_slice(format!(",{},{}\n", self.flags, self.sys).as_bytes());
+    }
+}
+
+impl CsvRecord for crate::prelude::redis::Item {
+    const HEADER: &'static str = "id,name,description,count,height,weight\n";
+
+    fn write_csv(&self, out: &mut BytesMut) {
+        out.put_slice(format!("{},", self.id).as_bytes());
+        write_csv_field(out, &self.name);
+        out.put_u8(b',');
+        write_csv_field(out, &self.description);
+        out.put_slice(format!(",{},{},{}\n", self.count, self.height, self.weight).as_bytes());
+    }
+}
@@ -0,0 +1,188 @@
+use std::{collections::HashMap, sync::Arc};
+use async_graphql::{
+    dataloader::{DataLoader, Loader},
+    ComplexObject, Context, Object, Result, Schema, Subscription
+};
+use axum::extract::{Path, State};
+use futures::{Stream, StreamExt};
+use sqlx::{query_as, PgPool};
+use crate::{
+    api::sqlx::{self as api, begin},
+    audit::HistoryEntry,
+    auth::Scope,
+    codec::{Accept, Format, Negotiated},
+    error::sqlx::Error,
+    events::changes,
+    prelude::sqlx::{AppState, CreateDatasPayload, Datas, DatasPayload, Niceties, TrashedDatas},
+    tenant::Tenant
+};
+use super::{actor, consistency, principal, tenant, Change, RequireScope};
+
+// Resolvers go through the REST handlers, so both APIs share auditing, ownership checks and the cache
+
+pub type DatasSchema = Schema<DatasQuery, DatasMutation, DatasSubscription>;
+
+pub fn schema(state: AppState) -> DatasSchema {
+    let niceties = DataLoader::new(NicetiesLoader(state.pg_pool.clone()), tokio::spawn);
+
+    Schema::build(DatasQuery, DatasMutation, DatasSubscription)
+        .data(state)
+        .data(niceties)
+        .finish()
+}
+
+fn state(ctx: &Context<'_>) -> State<AppState> {
+    State(ctx.data_unchecked::<AppState>().clone())
+}
+
+/// The niceties of one datas row, which are only visible inside its tenant
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct NicetiesKey {
+    tenant: String,
+    datas_id: i32
+}
+
+/// Collects the niceties lookups resolvers make concurrently into one `datas_id = ANY($1)` query per tenant
+pub struct NicetiesLoader(PgPool);
+
+impl NicetiesLoader {
+    async fn load_niceties(&self, keys: &[NicetiesKey]) -> crate::prelude::sqlx::Result<HashMap<NicetiesKey, Vec<Niceties>>> {
+        let mut by_tenant: HashMap<&str, Vec<i32>> = HashMap::new();
+        for key in keys {
+            by_tenant.entry(key.tenant.as_str()).or_default().push(key.datas_id);
+        }
+
+        let mut found: HashMap<NicetiesKey, Vec<Niceties>> = HashMap::new();
+        for (tenant, ids) in by_tenant {
+            let mut tx = begin(&self.0, &Tenant(tenant.to_string())).await?;
+            let rows = query_as!(
+                Niceties,
+                "SELECT id, datas_id, mem, stack, info FROM items.niceties WHERE datas_id = ANY($1) ORDER BY id",
+                &ids
+            ).fetch_all(&mut *tx).await?;
+            tx.commit().await?;
+
+            for row in rows {
+                let key = NicetiesKey { tenant: tenant.to_string(), datas_id: row.datas_id };
+                found.entry(key).or_default().push(row);
+            }
+        }
+
+        Ok(found)
+    }
+}
+
+impl Loader<NicetiesKey> for NicetiesLoader {
+    type Value = Vec<Niceties>;
+    type Error = Arc<Error>;
+
+    async fn load(&self, keys: &[NicetiesKey]) -> std::result::Result<HashMap<NicetiesKey, Self::Value>, Self::Error> {
+        self.load_niceties(keys).await.map_err(Arc::new)
+    }
+}
+
+async fn niceties_of(ctx: &Context<'_>, datas_id: i32) -> Result<Vec<Niceties>> {
+    let key = NicetiesKey { tenant: tenant(ctx).0, datas_id };
+    let niceties = ctx.data_unchecked::<DataLoader<NicetiesLoader>>().load_one(key).await?;
+
+    Ok(niceties.unwrap_or_default())
+}
+
+#[ComplexObject]
+impl Datas {
+    /// Loaded in one query together with the niceties of every other row in the response
+    async fn niceties(&self, ctx: &Context<'_>) -> Result<Vec<Niceties>> {
+        niceties_of(ctx, self.id).await
+    }
+}
+
+pub struct DatasQuery;
+
+#[Object]
+impl DatasQuery {
+    async fn datas(&self, ctx: &Context<'_>) -> Result<Vec<Datas>> {
+        let Negotiated(_, datas) = api::get_datas(state(ctx), tenant(ctx), consistency(ctx), Accept(Format::Json)).await?;
+
+        Ok(datas)
+    }
+
+    async fn data(&self, ctx: &Context<'_>, id: i32) -> Result<Option<Datas>> {
+        match api::get_data(state(ctx), Path(id), tenant(ctx), consistency(ctx), Accept(Format::Json)).await {
+            Ok(Negotiated(_, data)) => Ok(Some(data)),
+            Err(Error::NotFound(_)) => Ok(None),
+            Err(e) => Err(e.into())
+        }
+    }
+
+    async fn niceties(&self, ctx: &Context<'_>, datas_id: i32) -> Result<Vec<Niceties>> {
+        niceties_of(ctx, datas_id).await
+    }
+
+    /// Deleted rows that can still be restored, most recent first
+    async fn trash(&self, ctx: &Context<'_>) -> Result<Vec<TrashedDatas>> {
+        let Negotiated(_, trashed) = api::get_trash(state(ctx), tenant(ctx), consistency(ctx), Accept(Format::Json)).await?;
+
+        Ok(trashed)
+    }
+
+    /// Every recorded change of a row, newest first
+    async fn history(&self, ctx: &Context<'_>, id: i32) -> Result<Vec<HistoryEntry>> {
+        let Negotiated(_, entries) = api::get_history(state(ctx), Path(id), tenant(ctx), consistency(ctx), Accept(Format::Json)).await?;
+
+        Ok(entries)
+    }
+}
+
+pub struct DatasMutation;
+
+#[Object]
+impl DatasMutation {
+    /// Creates the row and the niceties in `input` in one transaction
+    #[graphql(guard = "RequireScope(Scope::ItemsWrite)")]
+    async fn create_datas(&self, ctx: &Context<'_>, input: CreateDatasPayload) -> Result<Datas> {
+        let created = api::insert_datas(&state(ctx).0, &tenant(ctx), &actor(ctx).0, principal(ctx).as_ref(), input).await?;
+
+        Ok(created.datas)
+    }
+
+    #[graphql(guard = "RequireScope(Scope::ItemsWrite)")]
+    async fn edit_datas(&self, ctx: &Context<'_>, id: i32, input: DatasPayload) -> Result<Datas> {
+        let updated = api::update_datas(&state(ctx).0, &tenant(ctx), &actor(ctx).0, principal(ctx).as_ref(), id, input).await?;
+
+        Ok(updated)
+    }
+
+    /// Moves a row to the trash
+    #[graphql(guard = "RequireScope(Scope::ItemsWrite)")]
+    async fn destroy_datas(&self, ctx: &Context<'_>, id: i32) -> Result<bool> {
+        api::destroy_datas(state(ctx), Path(id), actor(ctx), principal(ctx), tenant(ctx)).await?;
+
+        Ok(true)
+    }
+
+    /// Takes a row back out of the trash
+    #[graphql(guard = "RequireScope(Scope::ItemsWrite)")]
+    async fn restore_trashed(&self, ctx: &Context<'_>, id: i32) -> Result<Datas> {
+        let Negotiated(_, restored) = api::restore_trashed(state(ctx), Path(id), actor(ctx), principal(ctx), tenant(ctx), Accept(Format::Json)).await?;
+
+        Ok(restored)
+    }
+
+    /// Puts a row back the way history entry `version` left it
+    #[graphql(guard = "RequireScope(Scope::ItemsWrite)")]
+    async fn restore_version(&self, ctx: &Context<'_>, id: i32, version: i64) -> Result<Datas> {
+        let Negotiated(_, restored) = api::restore_datas(state(ctx), Path((id, version)), actor(ctx), principal(ctx), tenant(ctx), Accept(Format::Json)).await?;
+
+        Ok(restored)
+    }
+}
+
+pub struct DatasSubscription;
+
+#[Subscription]
+impl DatasSubscription {
+    /// Datas changes in the caller's tenant as they happen
+    async fn datas_changes(&self, ctx: &Context<'_>) -> impl Stream<Item = Change<Datas>> {
+        changes(&ctx.data_unchecked::<AppState>().datas_feed, tenant(ctx)).map(Change::from)
+    }
+}
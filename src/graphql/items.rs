@@ -0,0 +1,105 @@
+use async_graphql::{Context, Object, Result, Schema, Subscription};
+use axum::extract::{Path, Query, State};
+use futures::{Stream, StreamExt};
+use crate::{
+    api::redis as api,
+    auth::Scope,
+    codec::{Accept, Format, Negotiated, Payload},
+    error::redis::Error,
+    events::changes,
+    prelude::redis::{AppState, CreateItemPayload, Item, ItemFilter, TrashedItem, UpdateItemPayload}
+};
+use super::{principal, tenant, Change, RequireScope};
+
+// Resolvers go through the REST handlers, so both APIs share ownership checks, indexes and change events
+
+pub type ItemsSchema = Schema<ItemsQuery, ItemsMutation, ItemsSubscription>;
+
+pub fn schema(state: AppState) -> ItemsSchema {
+    Schema::build(ItemsQuery, ItemsMutation, ItemsSubscription)
+        .data(state)
+        .finish()
+}
+
+fn state(ctx: &Context<'_>) -> State<AppState> {
+    State(ctx.data_unchecked::<AppState>().clone())
+}
+
+pub struct ItemsQuery;
+
+#[Object]
+impl ItemsQuery {
+    /// Every item, or only those matching `filter`
+    async fn items(&self, ctx: &Context<'_>, filter: Option<ItemFilter>) -> Result<Vec<Item>> {
+        let (_, Negotiated(_, items)) = api::get_items(state(ctx), tenant(ctx), Query(filter.unwrap_or_default()), Accept(Format::Json)).await?;
+
+        Ok(items)
+    }
+
+    async fn item(&self, ctx: &Context<'_>, id: usize) -> Result<Option<Item>> {
+        match api::get_item(state(ctx), tenant(ctx), Path(id), Accept(Format::Json)).await {
+            Ok(Negotiated(_, item)) => Ok(Some(item)),
+            Err(Error::NotFound(_)) => Ok(None),
+            Err(e) => Err(e.into())
+        }
+    }
+
+    /// Deleted items that can still be restored, most recent first
+    async fn trash(&self, ctx: &Context<'_>) -> Result<Vec<TrashedItem>> {
+        let Negotiated(_, trashed) = api::get_trash(state(ctx), tenant(ctx), Accept(Format::Json)).await?;
+
+        Ok(trashed)
+    }
+}
+
+pub struct ItemsMutation;
+
+#[Object]
+impl ItemsMutation {
+    #[graphql(guard = "RequireScope(Scope::ItemsWrite)")]
+    async fn create_item(&self, ctx: &Context<'_>, input: CreateItemPayload) -> Result<Item> {
+        let (_, Negotiated(_, item)) = api::create_item(state(ctx), tenant(ctx), principal(ctx), Accept(Format::Json), Payload(input)).await?;
+
+        Ok(item)
+    }
+
+    #[graphql(guard = "RequireScope(Scope::ItemsWrite)")]
+    async fn update_item(&self, ctx: &Context<'_>, id: usize, input: CreateItemPayload) -> Result<Item> {
+        let Negotiated(_, item) = api::update_item(state(ctx), tenant(ctx), Path(id), principal(ctx), Accept(Format::Json), Payload(input)).await?;
+
+        Ok(item)
+    }
+
+    /// Writes only the fields set in `input`
+    #[graphql(guard = "RequireScope(Scope::ItemsWrite)")]
+    async fn patch_item(&self, ctx: &Context<'_>, id: usize, input: UpdateItemPayload) -> Result<Item> {
+        let Negotiated(_, item) = api::patch_item(state(ctx), tenant(ctx), Path(id), principal(ctx), Accept(Format::Json), Payload(input)).await?;
+
+        Ok(item)
+    }
+
+    /// Moves an item to the trash
+    #[graphql(guard = "RequireScope(Scope::ItemsWrite)")]
+    async fn delete_item(&self, ctx: &Context<'_>, id: usize) -> Result<bool> {
+        api::delete_item(state(ctx), tenant(ctx), Path(id), principal(ctx)).await?;
+
+        Ok(true)
+    }
+
+    #[graphql(guard = "RequireScope(Scope::ItemsWrite)")]
+    async fn restore_item(&self, ctx: &Context<'_>, id: usize) -> Result<Item> {
+        let Negotiated(_, item) = api::restore_item(state(ctx), tenant(ctx), Path(id), principal(ctx), Accept(Format::Json)).await?;
+
+        Ok(item)
+    }
+}
+
+pub struct ItemsSubscription;
+
+#[Subscription]
+impl ItemsSubscription {
+    /// Item changes in the caller's tenant as they happen
+    async fn item_changes(&self, ctx: &Context<'_>) -> impl Stream<Item = Change<Item>> {
+        changes(&ctx.data_unchecked::<AppState>().items_feed, tenant(ctx)).map(Change::from)
+    }
+}
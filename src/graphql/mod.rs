@@ -0,0 +1,156 @@
+use async_graphql::{
+    http::{GraphiQLSource, ALL_WEBSOCKET_PROTOCOLS},
+    Context, Data, Guard, ObjectType, OutputType, Schema, SimpleObject, SubscriptionType
+};
+use async_graphql_axum::{GraphQLProtocol, GraphQLRequest, GraphQLResponse, GraphQLWebSocket};
+use axum::{
+    extract::{State, WebSocketUpgrade},
+    response::{Html, Response},
+    routing::{get, post},
+    Router
+};
+use crate::{
+    audit::Actor,
+    auth::{Principal, Scope},
+    events::{ChangeEvent, ChangeKind},
+    prelude::{redis::Item, sqlx::Datas},
+    replica::Consistency,
+    tenant::Tenant
+};
+
+pub mod datas;
+pub mod items;
+
+/// Where queries and mutations are posted
+pub const GRAPHQL_PATH: &str = "/graphql";
+
+/// Where subscriptions are served, over `graphql-transport-ws` or the older `graphql-ws`
+pub const GRAPHQL_WS_PATH: &str = "/graphql/ws";
+
+const GRAPHIQL_PATH: &str = "/graphiql";
+
+/// Refuses a field unless the caller holds the scope.
+///
+/// [`crate::auth::require`] only asks `items:read` of `/graphql`, so every mutation carries this for `items:write`.
+pub struct RequireScope(pub Scope);
+
+impl Guard for RequireScope {
+    async fn check(&self, ctx: &Context<'_>) -> async_graphql::Result<()> {
+        match ctx.data_opt::<Principal>() {
+            Some(principal) if !principal.allows(self.0) => {
+                Err(format!("'{}' lacks the '{}' scope", principal.subject, self.0).into())
+            }
+            _ => Ok(())
+        }
+    }
+}
+
+/// A [`ChangeEvent`] as subscriptions yield it, `data` is the entity after the change and null for deletes
+#[derive(SimpleObject)]
+#[graphql(concrete(name = "ItemChange", params(Item)), concrete(name = "DatasChange", params(Datas)))]
+pub struct Change<T: OutputType> {
+    pub kind: ChangeKind,
+    pub id: i64,
+    pub data: Option<T>
+}
+
+impl<T: OutputType> From<ChangeEvent<T>> for Change<T> {
+    fn from(event: ChangeEvent<T>) -> Self {
+        Self { kind: event.kind, id: event.id, data: event.data }
+    }
+}
+
+/// What the REST handlers extract about the caller, handed to resolvers as request data
+fn caller_data(tenant: Tenant, principal: Option<Principal>, Actor(actor): Actor, consistency: Consistency) -> Data {
+    let mut data = Data::default();
+    data.insert(tenant);
+    data.insert(Actor(actor));
+    data.insert(consistency);
+    if let Some(principal) = principal {
+        data.insert(principal);
+    }
+
+    data
+}
+
+fn tenant(ctx: &Context<'_>) -> Tenant {
+    ctx.data_unchecked::<Tenant>().clone()
+}
+
+fn principal(ctx: &Context<'_>) -> Option<Principal> {
+    ctx.data_opt::<Principal>().cloned()
+}
+
+fn actor(ctx: &Context<'_>) -> Actor {
+    Actor(ctx.data_unchecked::<Actor>().0.clone())
+}
+
+fn consistency(ctx: &Context<'_>) -> Consistency {
+    *ctx.data_unchecked::<Consistency>()
+}
+
+/// POST /graphql - Runs a query or mutation as the caller, in the caller's tenant
+pub async fn execute<Q, M, S>(
+    State(schema): State<Schema<Q, M, S>>,
+    tenant: Tenant,
+    principal: Option<Principal>,
+    actor: Actor,
+    consistency: Consistency,
+    req: GraphQLRequest
+) -> GraphQLResponse
+where
+    Q: ObjectType + 'static,
+    M: ObjectType + 'static,
+    S: SubscriptionType + 'static
+{
+    let mut request = req.into_inner();
+    request.data = caller_data(tenant, principal, actor, consistency);
+
+    schema.execute(request).await.into()
+}
+
+/// GET /graphql/ws - Subscriptions to change events, limited to the caller's tenant
+pub async fn subscribe<Q, M, S>(
+    State(schema): State<Schema<Q, M, S>>,
+    protocol: GraphQLProtocol,
+    tenant: Tenant,
+    principal: Option<Principal>,
+    actor: Actor,
+    consistency: Consistency,
+    upgrade: WebSocketUpgrade
+) -> Response
+where
+    Q: ObjectType + 'static,
+    M: ObjectType + 'static,
+    S: SubscriptionType + 'static
+{
+    let data = caller_data(tenant, principal, actor, consistency);
+
+    upgrade
+        .protocols(ALL_WEBSOCKET_PROTOCOLS)
+        .on_upgrade(move |stream| GraphQLWebSocket::new(stream, schema, protocol).with_data(data).serve())
+}
+
+/// Serves `schema` at [`GRAPHQL_PATH`] and [`GRAPHQL_WS_PATH`], to be merged before the auth and rate limit layers
+pub fn routes<Q, M, S, T>(schema: Schema<Q, M, S>) -> Router<T>
+where
+    Q: ObjectType + 'static,
+    M: ObjectType + 'static,
+    S: SubscriptionType + 'static,
+    T: Clone + Send + Sync + 'static
+{
+    Router::new()
+        .route(GRAPHQL_PATH, post(execute::<Q, M, S>))
+        .route(GRAPHQL_WS_PATH, get(subscribe::<Q, M, S>))
+        .with_state(schema)
+}
+
+/// GET /graphiql - GraphiQL pointed at this server, served without credentials like the OpenAPI docs
+pub fn graphiql() -> Router {
+    let page = GraphiQLSource::build()
+        .endpoint(GRAPHQL_PATH)
+        .subscription_endpoint(GRAPHQL_WS_PATH)
+        .finish();
+
+    Router::new().route(GRAPHIQL_PATH, get(Html(page)))
+}
@@ -0,0 +1,114 @@
+use axum::{extract::{Path, State}, Router};
+use futures::StreamExt;
+use tonic::{Request, Response, Status};
+use crate::{
+    api::sqlx as api,
+    codec::{Accept, Format, Negotiated},
+    events::{changes, ChangeEvent, ChangeKind},
+    prelude::sqlx::{AppState, CreateDatasPayload, Datas, DatasPayload}
+};
+use super::{caller, pb::datas::{self as pb, datas_server::{self, DatasServer}}, Watch};
+
+// Methods go through the REST handlers, so every API shares auditing, ownership checks and the cache
+
+/// Serves the Datas service next to the HTTP routes, to be merged before the auth and rate limit layers.
+///
+/// gRPC runs over HTTP/2, which `axum::serve` speaks on the same port to clients that open with it.
+pub fn routes<T: Clone + Send + Sync + 'static>(state: AppState) -> Router<T> {
+    Router::new().route_service(&format!("/{}/{{*method}}", datas_server::SERVICE_NAME), DatasServer::new(DatasService(state)))
+}
+
+pub struct DatasService(AppState);
+
+impl DatasService {
+    fn state(&self) -> State<AppState> {
+        State(self.0.clone())
+    }
+}
+
+impl From<Datas> for pb::DatasRow {
+    fn from(datas: Datas) -> Self {
+        Self { id: datas.id, name: datas.name, flags: datas.flags, sys: datas.sys.into() }
+    }
+}
+
+impl TryFrom<pb::DatasFields> for DatasPayload {
+    type Error = Status;
+
+    fn try_from(fields: pb::DatasFields) -> Result<Self, Self::Error> {
+        let sys = i16::try_from(fields.sys).map_err(|_| Status::invalid_argument(format!("sys {} does not fit in a smallint", fields.sys)))?;
+
+        Ok(Self { name: fields.name, flags: fields.flags, sys })
+    }
+}
+
+impl From<ChangeEvent<Datas>> for pb::DatasChange {
+    fn from(event: ChangeEvent<Datas>) -> Self {
+        let kind = match event.kind {
+            ChangeKind::Created => pb::ChangeKind::Created,
+            ChangeKind::Updated => pb::ChangeKind::Updated,
+            ChangeKind::Deleted => pb::ChangeKind::Deleted
+        };
+
+        Self { kind: kind.into(), id: event.id, datas: event.data.map(Into::into) }
+    }
+}
+
+#[tonic::async_trait]
+impl pb::datas_server::Datas for DatasService {
+    type WatchStream = Watch<pb::DatasChange>;
+
+    async fn get(&self, req: Request<pb::GetDatasRequest>) -> Result<Response<pb::DatasRow>, Status> {
+        let caller = caller(&req).await?;
+        let id = req.into_inner().id;
+
+        let Negotiated(_, datas) = api::get_data(self.state(), Path(id), caller.tenant, caller.consistency, Accept(Format::Json)).await?;
+
+        Ok(Response::new(datas.into()))
+    }
+
+    async fn list(&self, req: Request<pb::ListDatasRequest>) -> Result<Response<pb::ListDatasResponse>, Status> {
+        let caller = caller(&req).await?;
+
+        let Negotiated(_, datas) = api::get_datas(self.state(), caller.tenant, caller.consistency, Accept(Format::Json)).await?;
+
+        Ok(Response::new(pb::ListDatasResponse { datas: datas.into_iter().map(Into::into).collect() }))
+    }
+
+    async fn create(&self, req: Request<pb::DatasFields>) -> Result<Response<pb::DatasRow>, Status> {
+        let caller = caller(&req).await?;
+        let DatasPayload { name, flags, sys } = DatasPayload::try_from(req.into_inner())?;
+        let payload = CreateDatasPayload { name, flags, sys, niceties: Vec::new() };
+
+        let created = api::insert_datas(&self.0, &caller.tenant, &caller.actor.0, caller.principal.as_ref(), payload).await?;
+
+        Ok(Response::new(created.datas.into()))
+    }
+
+    async fn update(&self, req: Request<pb::UpdateDatasRequest>) -> Result<Response<pb::DatasRow>, Status> {
+        let caller = caller(&req).await?;
+        let req = req.into_inner();
+        let fields = req.fields.ok_or_else(|| Status::invalid_argument("fields are required"))?;
+        let payload = DatasPayload::try_from(fields)?;
+
+        let updated = api::update_datas(&self.0, &caller.tenant, &caller.actor.0, caller.principal.as_ref(), req.id, payload).await?;
+
+        Ok(Response::new(updated.into()))
+    }
+
+    async fn delete(&self, req: Request<pb::DeleteDatasRequest>) -> Result<Response<()>, Status> {
+        let caller = caller(&req).await?;
+        let id = req.into_inner().id;
+
+        api::destroy_datas(self.state(), Path(id), caller.actor, caller.principal, caller.tenant).await?;
+
+        Ok(Response::new(()))
+    }
+
+    async fn watch(&self, req: Request<pb::WatchDatasRequest>) -> Result<Response<Self::WatchStream>, Status> {
+        let caller = caller(&req).await?;
+        let stream = changes(&self.0.datas_feed, caller.tenant).map(pb::DatasChange::from).map(Ok);
+
+        Ok(Response::new(Box::pin(stream)))
+    }
+}
@@ -0,0 +1,151 @@
+use axum::{extract::{Path, Query, State}, Router};
+use futures::StreamExt;
+use tonic::{Request, Response, Status};
+use crate::{
+    api::redis as api,
+    codec::{Accept, Format, Negotiated, Payload},
+    events::{changes, ChangeEvent, ChangeKind},
+    prelude::redis::{AppState, CreateItemPayload, Item, ItemFilter, UpdateItemPayload}
+};
+use super::{caller, pb::items::{self as pb, items_server::{self, Items, ItemsServer}}, Watch};
+
+// Methods go through the REST handlers, so every API shares ownership checks, indexes and change events
+
+/// Serves the Items service next to the HTTP routes, to be merged before the auth and rate limit layers.
+///
+/// gRPC runs over HTTP/2, which `axum::serve` speaks on the same port to clients that open with it.
+pub fn routes<T: Clone + Send + Sync + 'static>(state: AppState) -> Router<T> {
+    Router::new().route_service(&format!("/{}/{{*method}}", items_server::SERVICE_NAME), ItemsServer::new(ItemsService(state)))
+}
+
+pub struct ItemsService(AppState);
+
+impl ItemsService {
+    fn state(&self) -> State<AppState> {
+        State(self.0.clone())
+    }
+}
+
+impl From<Item> for pb::Item {
+    fn from(item: Item) -> Self {
+        Self {
+            id: item.id as u64,
+            name: item.name,
+            description: item.description,
+            count: item.count as u64,
+            height: item.height as u64,
+            weight: item.weight as u64,
+            ttl_seconds: item.ttl_seconds
+        }
+    }
+}
+
+impl From<pb::ListItemsRequest> for ItemFilter {
+    fn from(req: pb::ListItemsRequest) -> Self {
+        Self {
+            min_count: req.min_count.map(|v| v as usize),
+            max_count: req.max_count.map(|v| v as usize),
+            min_height: req.min_height.map(|v| v as usize),
+            max_height: req.max_height.map(|v| v as usize),
+            min_weight: req.min_weight.map(|v| v as usize),
+            max_weight: req.max_weight.map(|v| v as usize),
+            name_prefix: req.name_prefix
+        }
+    }
+}
+
+impl From<pb::CreateItemRequest> for CreateItemPayload {
+    fn from(req: pb::CreateItemRequest) -> Self {
+        Self {
+            name: req.name,
+            description: req.description,
+            count: req.count as usize,
+            height: req.height as usize,
+            weight: req.weight as usize,
+            ttl_seconds: req.ttl_seconds
+        }
+    }
+}
+
+impl From<pb::UpdateItemRequest> for UpdateItemPayload {
+    fn from(req: pb::UpdateItemRequest) -> Self {
+        Self {
+            name: req.name,
+            description: req.description,
+            count: req.count.map(|v| v as usize),
+            height: req.height.map(|v| v as usize),
+            weight: req.weight.map(|v| v as usize),
+            ttl_seconds: req.ttl_seconds
+        }
+    }
+}
+
+impl From<ChangeEvent<Item>> for pb::ItemChange {
+    fn from(event: ChangeEvent<Item>) -> Self {
+        let kind = match event.kind {
+            ChangeKind::Created => pb::ChangeKind::Created,
+            ChangeKind::Updated => pb::ChangeKind::Updated,
+            ChangeKind::Deleted => pb::ChangeKind::Deleted
+        };
+
+        Self { kind: kind.into(), id: event.id, item: event.data.map(Into::into) }
+    }
+}
+
+#[tonic::async_trait]
+impl Items for ItemsService {
+    type WatchStream = Watch<pb::ItemChange>;
+
+    async fn get(&self, req: Request<pb::GetItemRequest>) -> Result<Response<pb::Item>, Status> {
+        let caller = caller(&req).await?;
+        let id = req.into_inner().id as usize;
+
+        let Negotiated(_, item) = api::get_item(self.state(), caller.tenant, Path(id), Accept(Format::Json)).await?;
+
+        Ok(Response::new(item.into()))
+    }
+
+    async fn list(&self, req: Request<pb::ListItemsRequest>) -> Result<Response<pb::ListItemsResponse>, Status> {
+        let caller = caller(&req).await?;
+        let filter = ItemFilter::from(req.into_inner());
+
+        let (_, Negotiated(_, items)) = api::get_items(self.state(), caller.tenant, Query(filter), Accept(Format::Json)).await?;
+
+        Ok(Response::new(pb::ListItemsResponse { items: items.into_iter().map(Into::into).collect() }))
+    }
+
+    async fn create(&self, req: Request<pb::CreateItemRequest>) -> Result<Response<pb::Item>, Status> {
+        let caller = caller(&req).await?;
+        let payload = CreateItemPayload::from(req.into_inner());
+
+        let (_, Negotiated(_, item)) = api::create_item(self.state(), caller.tenant, caller.principal, Accept(Format::Json), Payload(payload)).await?;
+
+        Ok(Response::new(item.into()))
+    }
+
+    async fn update(&self, req: Request<pb::UpdateItemRequest>) -> Result<Response<pb::Item>, Status> {
+        let caller = caller(&req).await?;
+        let req = req.into_inner();
+        let id = req.id as usize;
+
+        let Negotiated(_, item) = api::patch_item(self.state(), caller.tenant, Path(id), caller.principal, Accept(Format::Json), Payload(req.into())).await?;
+
+        Ok(Response::new(item.into()))
+    }
+
+    async fn delete(&self, req: Request<pb::DeleteItemRequest>) -> Result<Response<()>, Status> {
+        let caller = caller(&req).await?;
+        let id = req.into_inner().id as usize;
+
+        api::delete_item(self.state(), caller.tenant, Path(id), caller.principal).await?;
+
+        Ok(Response::new(()))
+    }
+
+    async fn watch(&self, req: Request<pb::WatchItemsRequest>) -> Result<Response<Self::WatchStream>, Status> {
+        let caller = caller(&req).await?;
+        let stream = changes(&self.0.items_feed, caller.tenant).map(pb::ItemChange::from).map(Ok);
+
+        Ok(Response::new(Box::pin(stream)))
+    }
+}
@@ -0,0 +1,64 @@
+use std::pin::Pin;
+use axum::{extract::FromRequestParts, http::{Request, StatusCode}};
+use futures::Stream;
+use tonic::Status;
+use crate::{audit::Actor, auth::{Principal, Unverified}, replica::Consistency, tenant::Tenant};
+
+pub mod datas;
+pub mod items;
+
+/// Messages, servers and clients generated from `proto/` by `build.rs`
+pub mod pb {
+    pub mod items {
+        tonic::include_proto!("items.v1");
+    }
+
+    pub mod datas {
+        tonic::include_proto!("datas.v1");
+    }
+}
+
+/// Methods that only read, [`crate::auth::require`] asks `items:read` of them and `items:write` of the rest
+const READ_METHODS: [&str; 3] = ["Get", "List", "Watch"];
+
+/// Whether `path` calls one of the read-only methods of the Items or Datas service
+pub fn is_read(path: &str) -> bool {
+    let services = [pb::items::items_server::SERVICE_NAME, pb::datas::datas_server::SERVICE_NAME];
+
+    path.strip_prefix('/')
+        .and_then(|path| path.split_once('/'))
+        .is_some_and(|(service, method)| services.contains(&service) && READ_METHODS.contains(&method))
+}
+
+/// Server-streamed replies of the `Watch` methods
+pub type Watch<T> = Pin<Box<dyn Stream<Item = Result<T, Status>> + Send>>;
+
+/// What the REST handlers extract about the caller, taken from the call's metadata and the extensions auth left on it
+struct Caller {
+    tenant: Tenant,
+    principal: Option<Principal>,
+    actor: Actor,
+    consistency: Consistency
+}
+
+async fn caller<T>(req: &tonic::Request<T>) -> Result<Caller, Status> {
+    let (mut parts, ()) = Request::new(()).into_parts();
+    parts.headers = req.metadata().clone().into_headers();
+
+    let principal = req.extensions().get::<Principal>().cloned();
+    if let Some(principal) = &principal {
+        parts.extensions.insert(principal.clone());
+    }
+    if let Some(unverified) = req.extensions().get::<Unverified>() {
+        parts.extensions.insert(*unverified);
+    }
+
+    let tenant = Tenant::from_request_parts(&mut parts, &()).await.map_err(|(status, msg)| match status {
+        StatusCode::FORBIDDEN => Status::permission_denied(msg),
+        _ => Status::invalid_argument(msg)
+    })?;
+    let Ok(actor) = Actor::from_request_parts(&mut parts, &()).await;
+    let consistency = Consistency::from_headers(&parts.headers);
+
+    Ok(Caller { tenant, principal, actor, consistency })
+}
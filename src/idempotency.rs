@@ -0,0 +1,335 @@
+use std::time::Duration;
+use axum::{
+    body::{to_bytes, Body},
+    extract::{Request, State},
+    http::{header::CONTENT_TYPE, HeaderValue, Method, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response}
+};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use bb8_redis::{bb8, RedisConnectionManager};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sqlx::{query, types::Json, PgPool};
+use utoipa::{
+    openapi::{path::{Parameter, ParameterBuilder, ParameterIn}, ObjectBuilder, Type},
+    IntoParams
+};
+use crate::{auth::Principal, tenant::Tenant, tls};
+
+/// Header naming one logical create, retries carrying the same key get the first response back
+pub const IDEMPOTENCY_HEADER: &str = "idempotency-key";
+
+/// Set on responses replayed from an earlier request
+pub const REPLAYED_HEADER: &str = "idempotent-replayed";
+
+/// Creates whose retries would mint a second row
+const IDEMPOTENT_PATHS: [&str; 2] = ["/api/items", "/api/datas"];
+
+const MAX_KEY_LEN: usize = 255;
+const MAX_BODY_BYTES: usize = 1024 * 1024;
+const DEFAULT_TTL_SECONDS: u64 = 24 * 60 * 60;
+
+/// How long a key stays claimed by a request that never finishes, e.g. because the server went down
+const PENDING_TTL_SECONDS: u64 = 60;
+
+/// How often expired records are deleted from Postgres, Redis expires them by itself
+const CLEANUP_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+type RedisPool = bb8::Pool<RedisConnectionManager>;
+
+/// What is kept under a key, `response` stays empty until the first request completes
+#[derive(Serialize, Deserialize, Debug)]
+struct Record {
+    /// Hash of the method, path and body the key was first used with
+    fingerprint: String,
+    response: Option<StoredResponse>
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct StoredResponse {
+    status: u16,
+    content_type: Option<String>,
+    /// Base64, bodies may be MessagePack or CBOR
+    body: String
+}
+
+impl StoredResponse {
+    fn replay(&self) -> Response {
+        let body = STANDARD.decode(&self.body).unwrap_or_default();
+        let mut response = (StatusCode::from_u16(self.status).unwrap_or(StatusCode::OK), body).into_response();
+
+        if let Some(content_type) = self.content_type.as_deref().and_then(|c| HeaderValue::from_str(c).ok()) {
+            response.headers_mut().insert(CONTENT_TYPE, content_type);
+        }
+        response.headers_mut().insert(REPLAYED_HEADER, HeaderValue::from_static("true"));
+
+        response
+    }
+}
+
+/// Where records are kept, Redis for the redis mode and `items.idempotency_keys` for the Postgres modes
+#[derive(Clone)]
+pub enum Store {
+    Redis(RedisPool),
+    Postgres(PgPool)
+}
+
+/// Remembers the responses of `POST /api/items` and `POST /api/datas` sent with an `Idempotency-Key`.
+///
+/// Records are kept for `IDEMPOTENCY_TTL_SECONDS`. Storage trouble never fails a request, it only runs it
+/// as if no key was sent.
+#[derive(Clone)]
+pub struct Idempotency {
+    store: Store,
+    ttl: u64
+}
+
+impl Idempotency {
+    /// Keeps records in the Redis at `IDEMPOTENCY_REDIS_URL` when set, else in the mode's own `store`
+    pub async fn from_env(store: Store) -> anyhow::Result<Self> {
+        let store = match std::env::var("IDEMPOTENCY_REDIS_URL") {
+            Ok(url) => Store::Redis(bb8::Pool::builder().build(RedisConnectionManager::new(tls::redis(&url)?)?).await?),
+            Err(_) => store
+        };
+
+        if let Store::Postgres(pool) = &store {
+            tracing::info!("Keeping Idempotency-Key records in Postgres");
+            tokio::spawn(delete_expired(pool.clone()));
+        }
+
+        let ttl = std::env::var("IDEMPOTENCY_TTL_SECONDS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(DEFAULT_TTL_SECONDS);
+
+        Ok(Self { store, ttl })
+    }
+
+    /// Claims `key` for a request with `fingerprint`, or returns what an earlier request left there
+    async fn claim(&self, key: &str, fingerprint: &str) -> anyhow::Result<Option<Record>> {
+        match &self.store {
+            Store::Redis(pool) => {
+                let mut con = pool.get().await.map_err(pool_error)?;
+                let pending = serde_json::to_string(&Record { fingerprint: fingerprint.to_string(), response: None })
+                    .expect("a record always serializes");
+
+                let claimed: Option<String> = redis::cmd("SET")
+                    .arg(key)
+                    .arg(pending)
+                    .arg("NX")
+                    .arg("EX")
+                    .arg(PENDING_TTL_SECONDS)
+                    .query_async(&mut *con)
+                    .await?;
+
+                if claimed.is_some() {
+                    return Ok(None);
+                }
+
+                let existing: Option<String> = redis::cmd("GET").arg(key).query_async(&mut *con).await?;
+
+                Ok(existing.and_then(|record| serde_json::from_str(&record).ok()))
+            }
+            Store::Postgres(pool) => {
+                // An expired record is taken over as if the key was new
+                let claimed = query!(
+                    "INSERT INTO items.idempotency_keys (key, fingerprint, expires_at) VALUES ($1, $2, now() + make_interval(secs => $3))
+                     ON CONFLICT (key) DO UPDATE SET fingerprint = EXCLUDED.fingerprint, response = NULL, expires_at = EXCLUDED.expires_at
+                     WHERE idempotency_keys.expires_at <= now()
+                     RETURNING key",
+                    key,
+                    fingerprint,
+                    PENDING_TTL_SECONDS as f64
+                ).fetch_optional(pool).await?;
+
+                if claimed.is_some() {
+                    return Ok(None);
+                }
+
+                let existing = query!(
+                    r#"SELECT fingerprint, response AS "response: Json<StoredResponse>" FROM items.idempotency_keys WHERE key = $1"#,
+                    key
+                ).fetch_optional(pool).await?;
+
+                Ok(existing.map(|record| Record { fingerprint: record.fingerprint, response: record.response.map(|Json(r)| r) }))
+            }
+        }
+    }
+
+    /// Keeps the response for replays, or frees the key when the request failed on our side so a retry runs again
+    async fn finish(&self, key: &str, record: Option<Record>) -> anyhow::Result<()> {
+        match (&self.store, record) {
+            (Store::Redis(pool), Some(record)) => {
+                let mut con = pool.get().await.map_err(pool_error)?;
+                let record = serde_json::to_string(&record).expect("a record always serializes");
+                redis::cmd("SET").arg(key).arg(record).arg("EX").arg(self.ttl).query_async::<()>(&mut *con).await?;
+            }
+            (Store::Redis(pool), None) => {
+                let mut con = pool.get().await.map_err(pool_error)?;
+                redis::cmd("DEL").arg(key).query_async::<()>(&mut *con).await?;
+            }
+            (Store::Postgres(pool), Some(record)) => {
+                query!(
+                    "UPDATE items.idempotency_keys SET response = $2, expires_at = now() + make_interval(secs => $3) WHERE key = $1",
+                    key,
+                    record.response.map(Json) as _,
+                    self.ttl as f64
+                ).execute(pool).await?;
+            }
+            (Store::Postgres(pool), None) => {
+                query!("DELETE FROM items.idempotency_keys WHERE key = $1 AND response IS NULL", key).execute(pool).await?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// A claimed key, freed again when the request is dropped before it finishes, e.g. because the client went away
+struct Claim {
+    store: Idempotency,
+    key: String,
+    finished: bool
+}
+
+impl Claim {
+    async fn finish(mut self, record: Option<Record>) -> anyhow::Result<()> {
+        self.finished = true;
+        self.store.finish(&self.key, record).await
+    }
+}
+
+impl Drop for Claim {
+    fn drop(&mut self) {
+        if self.finished {
+            return;
+        }
+
+        let (store, key) = (self.store.clone(), std::mem::take(&mut self.key));
+        tokio::spawn(async move {
+            if let Err(e) = store.finish(&key, None).await {
+                tracing::warn!("Failed to free {} after its request was dropped: {}", key, e);
+            }
+        });
+    }
+}
+
+async fn delete_expired(pool: PgPool) {
+    let mut interval = tokio::time::interval(CLEANUP_INTERVAL);
+
+    loop {
+        interval.tick().await;
+
+        if let Err(e) = query!("DELETE FROM items.idempotency_keys WHERE expires_at <= now()").execute(&pool).await {
+            tracing::error!("Failed to delete expired Idempotency-Key records: {:?}", e);
+        }
+    }
+}
+
+fn pool_error(e: bb8::RunError<redis::RedisError>) -> redis::RedisError {
+    match e {
+        bb8::RunError::User(e) => e,
+        bb8::RunError::TimedOut => (redis::ErrorKind::IoError, "Timed out waiting for a Redis connection").into()
+    }
+}
+
+fn fingerprint(req: &Request, body: &[u8]) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(req.method().as_str());
+    hasher.update(req.uri().path());
+    hasher.update(body);
+
+    hasher.finalize().iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Middleware replaying the first response to a create retried with the same `Idempotency-Key`.
+///
+/// Keys are scoped to the tenant and the caller. Reusing a key with another body is refused with 422,
+/// and a retry arriving while the first request still runs gets 409.
+pub async fn replay(State(store): State<Idempotency>, tenant: Tenant, principal: Option<Principal>, req: Request, next: Next) -> Response {
+    if req.method() != Method::POST || !IDEMPOTENT_PATHS.contains(&req.uri().path()) {
+        return next.run(req).await;
+    }
+
+    let key = match req.headers().get(IDEMPOTENCY_HEADER).map(|v| v.to_str()) {
+        None => return next.run(req).await,
+        Some(Ok(key)) if !key.is_empty() && key.len() <= MAX_KEY_LEN => key.to_string(),
+        Some(_) => {
+            let msg = format!("Idempotency-Key must be 1 to {} visible ASCII characters", MAX_KEY_LEN);
+            return (StatusCode::BAD_REQUEST, msg).into_response();
+        }
+    };
+
+    let (parts, body) = req.into_parts();
+    let body = match to_bytes(body, MAX_BODY_BYTES).await {
+        Ok(body) => body,
+        Err(e) => return (StatusCode::BAD_REQUEST, format!("Failed to read body: {}", e)).into_response()
+    };
+    let req = Request::from_parts(parts, Body::from(body.clone()));
+
+    let subject = principal.map(|p| p.subject).unwrap_or_else(|| "anonymous".to_string());
+    let record_key = format!("{}:idempotency:{}:{}", tenant.0, subject, key);
+    let fingerprint = fingerprint(&req, &body);
+
+    let claim = match store.claim(&record_key, &fingerprint).await {
+        Ok(None) => Claim { store, key: record_key, finished: false },
+        Ok(Some(record)) if record.fingerprint != fingerprint => {
+            return (StatusCode::UNPROCESSABLE_ENTITY, "Idempotency-Key was already used with a different request").into_response();
+        }
+        Ok(Some(Record { response: Some(stored), .. })) => return stored.replay(),
+        Ok(Some(_)) => {
+            return (StatusCode::CONFLICT, "A request with this Idempotency-Key is still being processed").into_response();
+        }
+        Err(e) => {
+            tracing::warn!("Idempotency lookup of {} failed, running the request without it: {}", record_key, e);
+            return next.run(req).await;
+        }
+    };
+
+    let response = next.run(req).await;
+    let (parts, body) = response.into_parts();
+    let body = match to_bytes(body, usize::MAX).await {
+        Ok(body) => body,
+        Err(e) => {
+            tracing::error!("Failed to buffer the response for {}: {}", claim.key, e);
+            let _ = claim.finish(None).await;
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+
+    let record = (!parts.status.is_server_error()).then(|| Record {
+        fingerprint,
+        response: Some(StoredResponse {
+            status: parts.status.as_u16(),
+            content_type: parts.headers.get(CONTENT_TYPE).and_then(|c| c.to_str().ok()).map(str::to_string),
+            body: STANDARD.encode(&body)
+        })
+    });
+
+    let key = claim.key.clone();
+    if let Err(e) = claim.finish(record).await {
+        tracing::warn!("Failed to store the response for {}: {}", key, e);
+    }
+
+    Response::from_parts(parts, Body::from(body))
+}
+
+/// Documents the optional `Idempotency-Key` header of the create routes
+pub struct IdempotencyKey;
+
+impl IntoParams for IdempotencyKey {
+    fn into_params(_: impl Fn() -> Option<ParameterIn>) -> Vec<Parameter> {
+        vec![
+            ParameterBuilder::new()
+                .name(IDEMPOTENCY_HEADER)
+                .parameter_in(ParameterIn::Header)
+                .description(Some(
+                    "Retries sent with the same key get the first response back, marked `Idempotent-Replayed: true`. \
+                     Reusing a key with another body answers 422, and 409 while the first request is still running"
+                ))
+                .schema(Some(ObjectBuilder::new().schema_type(Type::String).max_length(Some(MAX_KEY_LEN))))
+                .build()
+        ]
+    }
+}
@@ -0,0 +1,224 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use axum::{body::Body, http::{header::CONTENT_TYPE, HeaderMap}};
+use bytes::{BufMut, Bytes, BytesMut};
+use futures::{pin_mut, SinkExt, StreamExt};
+use tokio_postgres::Client;
+use crate::{error::tok_postgres::Error, prelude::tok_postgres::{DatasPayload, ImportFormat, ImportQuery, ImportReport, Result}};
+
+/// Names the staging table of each import, the single connection mode runs several on one session
+static STAGING_SEQ: AtomicU64 = AtomicU64::new(0);
+
+/// Rows are handed to the COPY sink once this many bytes are buffered
+const FLUSH_THRESHOLD: usize = 64 * 1024;
+
+/// A single line longer than this is treated as a broken upload instead of being buffered forever
+const MAX_LINE_LEN: usize = 1024 * 1024;
+
+/// Only the first rejected line numbers are listed, the rest are just counted
+const MAX_REPORTED_LINES: usize = 1000;
+
+/// Picks the import format from `?format=` first, then from the `Content-Type` header
+pub fn resolve_format(query: &ImportQuery, headers: &HeaderMap) -> Result<ImportFormat> {
+    query.format
+        .or_else(|| {
+            headers.get(CONTENT_TYPE)
+                .and_then(|v| v.to_str().ok())
+                .and_then(ImportFormat::from_content_type)
+        })
+        .ok_or_else(|| Error::BadRequest("Unknown import format, use ?format=csv|ndjson or a text/csv / application/x-ndjson body".to_string()))
+}
+
+/// Streams a CSV or NDJSON body into `tenant`'s `items.datas` rows through `COPY ... FROM STDIN`.
+///
+/// Lines are parsed as they arrive and only valid rows are forwarded, so at most one
+/// chunk of the upload plus one flush buffer is held in memory at a time.
+///
+/// COPY can't write to a table under row level security, so rows are copied into a temporary
+/// table and moved over with a single `INSERT ... SELECT` at the end, which writes their history as `actor`.
+/// Names are unique within a tenant: the first line of each name wins and lines naming an existing
+/// row or an earlier line are rejected.
+pub async fn copy_datas(client: &Client, tenant: &str, actor: &str, format: ImportFormat, body: Body) -> Result<ImportReport> {
+    let staging = format!("datas_import_{}", STAGING_SEQ.fetch_add(1, Ordering::Relaxed));
+    client.batch_execute(&format!("CREATE TEMP TABLE {} AS SELECT 0::bigint AS line, name, flags, sys FROM items.datas WITH NO DATA", staging)).await?;
+
+    let imported = stage_and_insert(client, &staging, tenant, actor, format, body).await;
+
+    if let Err(e) = client.batch_execute(&format!("DROP TABLE IF EXISTS {}", staging)).await {
+        tracing::warn!("Failed to drop import staging table {}: {:?}", staging, e);
+    }
+
+    imported
+}
+
+async fn stage_and_insert(client: &Client, staging: &str, tenant: &str, actor: &str, format: ImportFormat, body: Body) -> Result<ImportReport> {
+    let copy = format!("COPY {} (line, name, flags, sys) FROM STDIN", staging);
+    let sink = client.copy_in::<_, Bytes>(copy.as_str()).await?;
+    pin_mut!(sink);
+
+    let mut report = ImportReport::default();
+    let mut pending = Vec::new();
+    let mut out = BytesMut::with_capacity(FLUSH_THRESHOLD);
+    let mut line_no = 0u64;
+
+    let mut stream = body.into_data_stream();
+    while let Some(chunk) = stream.next().await {
+        let chunk = chunk.map_err(|e| Error::BadRequest(format!("Failed to read upload: {}", e)))?;
+        pending.extend_from_slice(&chunk);
+
+        let mut start = 0;
+        while let Some(pos) = pending[start..].iter().position(|&b| b == b'\n') {
+            line_no += 1;
+            handle_line(format, &pending[start..start + pos], line_no, &mut out, &mut report);
+            start += pos + 1;
+        }
+        pending.drain(..start);
+
+        if pending.len() > MAX_LINE_LEN {
+            return Err(Error::BadRequest(format!("Line {} exceeds {} bytes", line_no + 1, MAX_LINE_LEN)));
+        }
+
+        if out.len() >= FLUSH_THRESHOLD {
+            sink.send(out.split().freeze()).await?;
+        }
+    }
+
+    if !pending.is_empty() {
+        line_no += 1;
+        handle_line(format, &pending, line_no, &mut out, &mut report);
+    }
+
+    if !out.is_empty() {
+        sink.send(out.freeze()).await?;
+    }
+
+    sink.finish().await?;
+
+    let insert = format!(
+        "WITH firsts AS (
+             SELECT DISTINCT ON (name) line, name, flags, sys FROM {staging} ORDER BY name, line
+         ),
+         inserted AS (
+             INSERT INTO items.datas (name, flags, sys, tenant_id)
+             SELECT name, flags, sys, $1 FROM firsts ORDER BY line
+             ON CONFLICT (tenant_id, name) DO NOTHING
+             RETURNING id, name, flags, sys
+         ),
+         history AS (
+             INSERT INTO items.datas_history (datas_id, operation, before, after, actor, tenant_id)
+             SELECT id, 'insert', NULL, jsonb_build_object('id', id, 'name', name, 'flags', flags, 'sys', sys), $2, $1 FROM inserted
+         ),
+         conflicts AS (
+             SELECT line FROM {staging} WHERE line NOT IN (SELECT firsts.line FROM firsts JOIN inserted USING (name))
+         )
+         SELECT (SELECT count(*) FROM inserted),
+                (SELECT count(*) FROM conflicts),
+                ARRAY(SELECT line FROM conflicts ORDER BY line LIMIT {MAX_REPORTED_LINES})"
+    );
+    let row = client.query_one(insert.as_str(), &[&tenant, &actor]).await?;
+
+    report.imported = row.get::<_, i64>(0) as u64;
+    report.rejected += row.get::<_, i64>(1) as u64;
+
+    // Both lists are in line order and hold the first lines of their kind, so the merge keeps the first lines overall
+    report.rejected_lines.extend(row.get::<_, Vec<i64>>(2).into_iter().map(|line| line as u64));
+    report.rejected_lines.sort_unstable();
+    report.rejected_lines.truncate(MAX_REPORTED_LINES);
+
+    Ok(report)
+}
+
+fn handle_line(format: ImportFormat, raw: &[u8], line_no: u64, out: &mut BytesMut, report: &mut ImportReport) {
+    let line = match std::str::from_utf8(raw) {
+        Ok(line) => line.trim_end_matches('\r'),
+        Err(_) => return report.reject(line_no, MAX_REPORTED_LINES)
+    };
+
+    if line.trim().is_empty() {
+        return;
+    }
+
+    let row = match format {
+        ImportFormat::Csv => {
+            if line_no == 1 && is_csv_header(line) {
+                return;
+            }
+            parse_csv_row(line)
+        }
+        ImportFormat::Ndjson => serde_json::from_str::<DatasPayload>(line).ok()
+    };
+
+    match row.filter(is_valid) {
+        Some(row) => encode_copy_row(line_no, &row, out),
+        None => report.reject(line_no, MAX_REPORTED_LINES)
+    }
+}
+
+fn is_valid(row: &DatasPayload) -> bool {
+    !row.name.trim().is_empty() && !row.name.contains('\0')
+}
+
+fn is_csv_header(line: &str) -> bool {
+    split_csv(line).is_some_and(|cols| {
+        cols.len() == 3 && cols[0].trim() == "name" && cols[1].trim() == "flags" && cols[2].trim() == "sys"
+    })
+}
+
+fn parse_csv_row(line: &str) -> Option<DatasPayload> {
+    let cols = split_csv(line)?;
+    if cols.len() != 3 {
+        return None;
+    }
+
+    Some(DatasPayload {
+        name: cols[0].clone(),
+        flags: cols[1].trim().parse().ok()?,
+        sys: cols[2].trim().parse().ok()?
+    })
+}
+
+/// Splits one CSV record, honouring double quotes and `""` escapes. Returns `None` on unbalanced quotes.
+fn split_csv(line: &str) -> Option<Vec<String>> {
+    let mut cols = Vec::with_capacity(3);
+    let mut field = String::new();
+    let mut in_quotes = false;
+    let mut chars = line.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if in_quotes => {
+                if chars.peek() == Some(&'"') {
+                    field.push('"');
+                    chars.next();
+                }
+                else {
+                    in_quotes = false;
+                }
+            }
+            '"' if field.is_empty() => in_quotes = true,
+            ',' if !in_quotes => cols.push(std::mem::take(&mut field)),
+            _ => field.push(c)
+        }
+    }
+
+    if in_quotes {
+        return None;
+    }
+    cols.push(field);
+
+    Some(cols)
+}
+
+/// Writes a row in the COPY text format (tab separated, backslash escaped), after the line it came from
+fn encode_copy_row(line_no: u64, row: &DatasPayload, out: &mut BytesMut) {
+    out.put_slice(format!("{}\t", line_no).as_bytes());
+    for b in row.name.bytes() {
+        match b {
+            b'\\' => out.put_slice(b"\\\\"),
+            b'\t' => out.put_slice(b"\\t"),
+            b'\n' => out.put_slice(b"\\n"),
+            b'\r' => out.put_slice(b"\\r"),
+            _ => out.put_u8(b)
+        }
+    }
+    out.put_slice(format!("\t{}\t{}\n", row.flags, row.sys).as_bytes());
+}
@@ -0,0 +1,394 @@
+use std::{
+    path::PathBuf,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use axum::{
+    extract::{FromRequestParts, OptionalFromRequestParts},
+    http::{StatusCode, request::Parts},
+};
+use jsonwebtoken::{decode, decode_header, jwk::JwkSet, Algorithm, DecodingKey, Validation};
+use serde::{Deserialize, Serialize};
+use tokio::sync::{Mutex, RwLock};
+use crate::auth::Scope;
+
+/// Only these are accepted, whatever a token header says
+const ALGORITHMS: [Algorithm; 3] = [Algorithm::HS256, Algorithm::RS256, Algorithm::ES256];
+
+/// A JWKS served from a URL is fetched again for an unknown `kid`, at most this often
+const REFRESH_COOLDOWN: Duration = Duration::from_secs(60);
+
+/// The verified claims of a bearer JWT.
+///
+/// Usable as an extractor: `Claims` rejects requests that weren't made with a JWT,
+/// `Option<Claims>` lets them through.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Claims {
+    pub sub: String,
+    pub exp: i64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub iss: Option<String>,
+    /// Space separated, as in OAuth 2.0
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scope: Option<String>,
+    /// `admin` here grants the admin scope
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub roles: Vec<String>,
+    /// Binds the token to one tenant
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tenant: Option<String>
+}
+
+impl Claims {
+    /// The known scopes the token grants. A token without a `scope` claim grants none, the issuer has to name them
+    pub fn scopes(&self) -> Vec<Scope> {
+        let mut scopes: Vec<Scope> = self.scope
+            .iter()
+            .flat_map(|scope| scope.split_whitespace())
+            .filter_map(|s| s.parse().ok())
+            .collect();
+
+        if self.roles.iter().any(|r| r == Scope::Admin.as_str()) && !scopes.contains(&Scope::Admin) {
+            scopes.push(Scope::Admin);
+        }
+
+        scopes
+    }
+}
+
+impl<S: Send + Sync> FromRequestParts<S> for Claims {
+    type Rejection = (StatusCode, &'static str);
+
+    async fn from_request_parts(parts: &mut Parts, _: &S) -> Result<Self, Self::Rejection> {
+        parts.extensions
+            .get::<Claims>()
+            .cloned()
+            .ok_or((StatusCode::UNAUTHORIZED, "This endpoint needs a bearer JWT"))
+    }
+}
+
+impl<S: Send + Sync> OptionalFromRequestParts<S> for Claims {
+    type Rejection = std::convert::Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _: &S) -> Result<Option<Self>, Self::Rejection> {
+        Ok(parts.extensions.get::<Claims>().cloned())
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Source {
+    File(PathBuf),
+    Url(String)
+}
+
+impl Source {
+    async fn load(&self) -> anyhow::Result<JwkSet> {
+        match self {
+            Self::File(path) => Ok(serde_json::from_slice(&tokio::fs::read(path).await?)?),
+            Self::Url(url) => Ok(reqwest::get(url).await?.error_for_status()?.json().await?)
+        }
+    }
+}
+
+/// Verifies bearer JWTs against a JWKS read from `JWKS_PATH` or fetched from `JWKS_URL`.
+///
+/// `JWT_ISSUER` and `JWT_AUDIENCE` are checked when set. HS256 secrets go in the set as `oct` keys.
+#[derive(Clone)]
+pub struct Jwks {
+    source: Source,
+    keys: Arc<RwLock<JwkSet>>,
+    refreshed: Arc<Mutex<Instant>>,
+    issuer: Option<String>,
+    audience: Option<String>
+}
+
+impl Jwks {
+    /// Stays `None` when neither `JWKS_PATH` nor `JWKS_URL` is set
+    pub async fn from_env() -> anyhow::Result<Option<Self>> {
+        let source = match (std::env::var("JWKS_PATH"), std::env::var("JWKS_URL")) {
+            (Ok(path), _) => Source::File(path.into()),
+            (_, Ok(url)) => Source::Url(url),
+            _ => return Ok(None)
+        };
+
+        let keys = source.load().await?;
+        tracing::info!("Accepting JWTs signed by {} keys from {:?}", keys.keys.len(), source);
+
+        Ok(Some(Self {
+            source,
+            keys: Arc::new(RwLock::new(keys)),
+            refreshed: Arc::new(Mutex::new(Instant::now())),
+            issuer: std::env::var("JWT_ISSUER").ok(),
+            audience: std::env::var("JWT_AUDIENCE").ok()
+        }))
+    }
+
+    /// Checks the signature, expiry and the configured issuer and audience of `token`
+    pub async fn verify(&self, token: &str) -> Result<Claims, String> {
+        let header = decode_header(token).map_err(|e| format!("Malformed JWT: {}", e))?;
+
+        if !ALGORITHMS.contains(&header.alg) {
+            return Err(format!("JWT algorithm {:?} is not accepted", header.alg));
+        }
+
+        let key = match self.decoding_key(header.kid.as_deref()).await {
+            Some(key) => key,
+            None if self.refresh().await => self.decoding_key(header.kid.as_deref()).await
+                .ok_or_else(|| format!("No key matches kid {:?}", header.kid))?,
+            None => return Err(format!("No key matches kid {:?}", header.kid))
+        };
+
+        let mut validation = Validation::new(header.alg);
+        validation.set_required_spec_claims(&["exp", "sub"]);
+
+        if let Some(issuer) = &self.issuer {
+            validation.set_issuer(&[issuer]);
+        }
+
+        match &self.audience {
+            Some(audience) => validation.set_audience(&[audience]),
+            None => validation.validate_aud = false
+        }
+
+        // The key's family has to match the algorithm, so an RSA key can't be replayed as an HMAC secret
+        decode::<Claims>(token, &key, &validation)
+            .map(|data| data.claims)
+            .map_err(|e| format!("Invalid JWT: {}", e))
+    }
+
+    /// The key named by `kid`, or the only key of the set when the token names none
+    async fn decoding_key(&self, kid: Option<&str>) -> Option<DecodingKey> {
+        let keys = self.keys.read().await;
+
+        let jwk = match kid {
+            Some(kid) => keys.find(kid)?,
+            None if keys.keys.len() == 1 => &keys.keys[0],
+            None => return None
+        };
+
+        DecodingKey::from_jwk(jwk)
+            .inspect_err(|e| tracing::warn!("Skipping unusable JWK {:?}: {}", jwk.common.key_id, e))
+            .ok()
+    }
+
+    /// Fetches a URL source again, returns whether the keys were replaced
+    async fn refresh(&self) -> bool {
+        if matches!(self.source, Source::File(_)) {
+            return false;
+        }
+
+        let mut refreshed = self.refreshed.lock().await;
+        if refreshed.elapsed() < REFRESH_COOLDOWN {
+            return false;
+        }
+        *refreshed = Instant::now();
+
+        match self.source.load().await {
+            Ok(keys) => {
+                *self.keys.write().await = keys;
+                true
+            }
+            Err(e) => {
+                tracing::error!("Failed to refresh the JWKS from {:?}: {:?}", self.source, e);
+                false
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{extract::State, routing::get, Json, Router};
+    use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+    use chrono::Utc;
+    use jsonwebtoken::{encode, EncodingKey, Header};
+    use ring::{
+        rand::SystemRandom,
+        signature::{EcdsaKeyPair, KeyPair, ECDSA_P256_SHA256_FIXED_SIGNING}
+    };
+    use rsa::{pkcs1::EncodeRsaPrivateKey, rand_core::OsRng, traits::PublicKeyParts, RsaPrivateKey};
+    use serde_json::{json, Value};
+    use tokio::net::TcpListener;
+    use super::*;
+
+    /// A signing key along with its public half as a JWK
+    struct Signer {
+        kid: &'static str,
+        alg: Algorithm,
+        key: EncodingKey,
+        jwk: Value
+    }
+
+    impl Signer {
+        fn hs256(kid: &'static str) -> Self {
+            let secret: [u8; 32] = rand::random();
+            Self {
+                kid,
+                alg: Algorithm::HS256,
+                key: EncodingKey::from_secret(&secret),
+                jwk: json!({ "kty": "oct", "kid": kid, "k": URL_SAFE_NO_PAD.encode(secret) })
+            }
+        }
+
+        fn rs256(kid: &'static str) -> Self {
+            let private = RsaPrivateKey::new(&mut OsRng, 2048).unwrap();
+            let der = private.to_pkcs1_der().unwrap();
+            Self {
+                kid,
+                alg: Algorithm::RS256,
+                key: EncodingKey::from_rsa_der(der.as_bytes()),
+                jwk: json!({
+                    "kty": "RSA",
+                    "kid": kid,
+                    "n": URL_SAFE_NO_PAD.encode(private.n().to_bytes_be()),
+                    "e": URL_SAFE_NO_PAD.encode(private.e().to_bytes_be())
+                })
+            }
+        }
+
+        fn es256(kid: &'static str) -> Self {
+            let rng = SystemRandom::new();
+            let pkcs8 = EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, &rng).unwrap();
+            let pair = EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, pkcs8.as_ref(), &rng).unwrap();
+            // Uncompressed point: 0x04, then x and y
+            let point = pair.public_key().as_ref();
+            Self {
+                kid,
+                alg: Algorithm::ES256,
+                key: EncodingKey::from_ec_der(pkcs8.as_ref()),
+                jwk: json!({
+                    "kty": "EC",
+                    "crv": "P-256",
+                    "kid": kid,
+                    "x": URL_SAFE_NO_PAD.encode(&point[1..33]),
+                    "y": URL_SAFE_NO_PAD.encode(&point[33..65])
+                })
+            }
+        }
+
+        fn sign(&self, claims: &Value) -> String {
+            self.sign_as(self.alg, claims)
+        }
+
+        /// Signs with this key's material under another algorithm, or a key of another family
+        fn sign_as(&self, alg: Algorithm, claims: &Value) -> String {
+            let mut header = Header::new(alg);
+            header.kid = Some(self.kid.to_string());
+            encode(&header, claims, &self.key).unwrap()
+        }
+    }
+
+    fn key_set(signers: &[&Signer]) -> JwkSet {
+        serde_json::from_value(json!({ "keys": signers.iter().map(|s| s.jwk.clone()).collect::<Vec<_>>() })).unwrap()
+    }
+
+    fn jwks(source: Source, keys: JwkSet) -> Jwks {
+        Jwks {
+            source,
+            keys: Arc::new(RwLock::new(keys)),
+            // Long enough ago that the first unknown `kid` refreshes
+            refreshed: Arc::new(Mutex::new(Instant::now() - REFRESH_COOLDOWN * 2)),
+            issuer: None,
+            audience: None
+        }
+    }
+
+    fn claims(expires_in: i64) -> Value {
+        json!({ "sub": "alice", "exp": Utc::now().timestamp() + expires_in, "scope": "items:read" })
+    }
+
+    #[tokio::test]
+    async fn accepts_every_algorithm() {
+        let signers = [Signer::hs256("hs"), Signer::rs256("rs"), Signer::es256("es")];
+        let jwks = jwks(Source::File("unused".into()), key_set(&signers.iter().collect::<Vec<_>>()));
+
+        for signer in &signers {
+            let verified = jwks.verify(&signer.sign(&claims(300))).await.unwrap_or_else(|e| panic!("{:?}: {}", signer.alg, e));
+            assert_eq!(verified.sub, "alice");
+            assert_eq!(verified.scopes(), vec![Scope::ItemsRead]);
+        }
+    }
+
+    #[tokio::test]
+    async fn rejects_expired_tokens() {
+        let signer = Signer::es256("es");
+        let jwks = jwks(Source::File("unused".into()), key_set(&[&signer]));
+
+        let err = jwks.verify(&signer.sign(&claims(-3600))).await.unwrap_err();
+        assert!(err.contains("ExpiredSignature"), "{}", err);
+    }
+
+    #[tokio::test]
+    async fn checks_issuer_and_audience() {
+        let signer = Signer::hs256("hs");
+        let mut jwks = jwks(Source::File("unused".into()), key_set(&[&signer]));
+        jwks.issuer = Some("https://issuer.example".to_string());
+        jwks.audience = Some("hello-axum".to_string());
+
+        let mut good = claims(300);
+        good["iss"] = json!("https://issuer.example");
+        good["aud"] = json!("hello-axum");
+        assert!(jwks.verify(&signer.sign(&good)).await.is_ok());
+
+        let mut wrong_issuer = good.clone();
+        wrong_issuer["iss"] = json!("https://other.example");
+        let err = jwks.verify(&signer.sign(&wrong_issuer)).await.unwrap_err();
+        assert!(err.contains("InvalidIssuer"), "{}", err);
+
+        let mut wrong_audience = good.clone();
+        wrong_audience["aud"] = json!("someone-else");
+        let err = jwks.verify(&signer.sign(&wrong_audience)).await.unwrap_err();
+        assert!(err.contains("InvalidAudience"), "{}", err);
+    }
+
+    #[tokio::test]
+    async fn rejects_other_algorithms() {
+        let hs = Signer::hs256("hs");
+        let rs = Signer::rs256("rs");
+        let jwks = jwks(Source::File("unused".into()), key_set(&[&hs, &rs]));
+
+        // Outside the accepted list
+        let err = jwks.verify(&hs.sign_as(Algorithm::HS384, &claims(300))).await.unwrap_err();
+        assert!(err.contains("not accepted"), "{}", err);
+
+        // HMAC signed, claiming the RSA key: the RSA public key must not be usable as a secret
+        let mut header = Header::new(Algorithm::HS256);
+        header.kid = Some("rs".to_string());
+        let forged = encode(&header, &claims(300), &EncodingKey::from_secret(rs.jwk["n"].as_str().unwrap().as_bytes())).unwrap();
+        assert!(jwks.verify(&forged).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn refreshes_for_an_unknown_kid() {
+        let old = Signer::hs256("old");
+        let rotated = Signer::es256("rotated");
+
+        let served = Arc::new(RwLock::new(json!({ "keys": [old.jwk.clone()] })));
+        let app = Router::new()
+            .route("/jwks", get(|State(keys): State<Arc<RwLock<Value>>>| async move { Json(keys.read().await.clone()) }))
+            .with_state(served.clone());
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/jwks", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let jwks = jwks(Source::Url(url), key_set(&[&old]));
+        *served.write().await = json!({ "keys": [old.jwk.clone(), rotated.jwk.clone()] });
+
+        let verified = jwks.verify(&rotated.sign(&claims(300))).await.unwrap();
+        assert_eq!(verified.sub, "alice");
+
+        // The refresh just happened, a kid nobody serves is turned down without another fetch
+        let stranger = Signer::hs256("stranger");
+        let err = jwks.verify(&stranger.sign(&claims(300))).await.unwrap_err();
+        assert!(err.contains("No key matches"), "{}", err);
+    }
+
+    #[test]
+    fn grants_no_scope_without_a_scope_claim() {
+        let claims: Claims = serde_json::from_value(json!({ "sub": "alice", "exp": 0 })).unwrap();
+        assert!(claims.scopes().is_empty());
+
+        let admin: Claims = serde_json::from_value(json!({ "sub": "root", "exp": 0, "roles": ["admin"] })).unwrap();
+        assert_eq!(admin.scopes(), vec![Scope::Admin]);
+    }
+}
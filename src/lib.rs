@@ -1,4 +1,23 @@
 pub mod api;
 pub mod app;
+pub mod audit;
+pub mod auth;
+pub mod cache;
+pub mod codec;
 pub mod error;
+pub mod events;
+pub mod export;
+pub mod graphql;
+pub mod grpc;
+pub mod idempotency;
+pub mod import;
+pub mod jwt;
+pub mod openapi;
 pub mod prelude;
+pub mod rate_limit;
+pub mod replica;
+pub mod search;
+pub mod tenant;
+pub mod tls;
+pub mod trash;
+pub mod ws;
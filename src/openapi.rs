@@ -0,0 +1,103 @@
+use std::collections::BTreeMap;
+use axum::Router;
+use utoipa::{
+    openapi::{
+        path::{ParameterBuilder, ParameterIn},
+        security::{Http, HttpAuthScheme, SecurityScheme},
+        Content, ObjectBuilder, OpenApi as Spec, RefOr, Response, ResponseBuilder, Type
+    },
+    IntoResponses, Modify, OpenApi
+};
+use utoipa_swagger_ui::SwaggerUi;
+use crate::tenant::{DEFAULT_TENANT, TENANT_HEADER};
+
+/// Where the generated specification is served
+pub const SPEC_PATH: &str = "/openapi.json";
+
+/// Where Swagger UI is served
+pub const DOCS_PATH: &str = "/docs";
+
+/// Title, security and tags shared by every mode, the paths come from the routers in [`crate::app`]
+#[derive(OpenApi)]
+#[openapi(
+    info(
+        title = "hello-axum",
+        description = "Items on Redis, datas on Postgres. Bodies are JSON unless `Content-Type` or `Accept` ask for \
+                       MessagePack (`application/msgpack`) or CBOR (`application/cbor`)."
+    ),
+    modifiers(&BearerAuth),
+    security(("bearer" = [])),
+    tags(
+        (name = "items", description = "Items stored in Redis"),
+        (name = "events", description = "Change notifications"),
+        (name = "datas", description = "Datas rows stored in Postgres"),
+        (name = "cache", description = "The datas read cache")
+    )
+)]
+pub struct ApiDoc;
+
+/// Niceties have no routes of their own yet, their schemas are still published by the Postgres modes
+#[derive(OpenApi)]
+#[openapi(components(schemas(crate::prelude::sqlx::Niceties, crate::prelude::sqlx::NicetiesPaylod)))]
+pub struct NicetiesDoc;
+
+/// Declares the `Authorization: Bearer` scheme used by API keys and JWTs alike
+struct BearerAuth;
+
+impl Modify for BearerAuth {
+    fn modify(&self, openapi: &mut Spec) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme("bearer", SecurityScheme::Http(Http::new(HttpAuthScheme::Bearer)));
+    }
+}
+
+/// Error statuses every route can answer with, the body is always a plain text message
+pub struct ApiErrors;
+
+impl IntoResponses for ApiErrors {
+    fn responses() -> BTreeMap<String, RefOr<Response>> {
+        [
+            ("400", "Malformed path, query or body"),
+            ("401", "Missing, invalid or revoked credentials"),
+            ("403", "The credentials lack the route's scope, or the row belongs to another subject or tenant"),
+            ("404", "No such entity"),
+            ("406", "None of the formats in `Accept` can be produced"),
+            ("409", "The entity changed or is taken, or a request with the same `Idempotency-Key` is still running"),
+            ("415", "The body's `Content-Type` is not one the route reads"),
+            ("422", "The `Idempotency-Key` was already used with a different request"),
+            ("429", "Rate limited, retry after `Retry-After` seconds"),
+            ("500", "Storage failure")
+        ]
+        .into_iter()
+        .map(|(status, description)| {
+            let response = ResponseBuilder::new()
+                .description(description)
+                .content("text/plain", Content::new(Some(ObjectBuilder::new().schema_type(Type::String))))
+                .build();
+
+            (status.to_string(), RefOr::T(response))
+        })
+        .collect()
+    }
+}
+
+/// Declares `X-Tenant-Id` on every path, all handlers read it through the [`crate::tenant::Tenant`] extractor
+fn add_tenant_header(spec: &mut Spec) {
+    let header = ParameterBuilder::new()
+        .name(TENANT_HEADER)
+        .parameter_in(ParameterIn::Header)
+        .description(Some(format!("Tenant to work in, `{0}` when left out. Must match the tenant of credentials bound to one, \
+                              and only admin credentials may name another than `{0}`", DEFAULT_TENANT)))
+        .schema(Some(ObjectBuilder::new().schema_type(Type::String).pattern(Some("^[A-Za-z0-9_-]{1,64}$"))))
+        .build();
+
+    for item in spec.paths.paths.values_mut() {
+        item.parameters.get_or_insert_with(Vec::new).push(header.clone());
+    }
+}
+
+/// Serves `spec` at [`SPEC_PATH`] along with Swagger UI at [`DOCS_PATH`], both without credentials
+pub fn docs(mut spec: Spec) -> Router {
+    add_tenant_header(&mut spec);
+    SwaggerUi::new(DOCS_PATH).url(SPEC_PATH, spec).into()
+}
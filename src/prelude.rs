@@ -1,31 +1,189 @@
 pub mod redis {
+    use axum::extract::FromRef;
     use serde::{Deserialize, Serialize};
-    use crate::error::redis::Error;
+    use utoipa::{IntoParams, ToSchema};
+    use async_graphql::{InputObject, SimpleObject};
+    use crate::{error::redis::Error, events::{ChangeEvent, Feed}};
 
-    #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+    #[derive(Serialize, Deserialize, Debug, Clone, PartialEq, ToSchema, SimpleObject)]
     pub struct Item {
         pub id: usize,
         pub name: String,
         pub description: String,
         pub count: usize,
         pub height: usize,
-        pub weight: usize
+        pub weight: usize,
+        /// Seconds left before the item expires, read from Redis and never stored with the item
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub ttl_seconds: Option<i64>
     }
     
-    #[derive(Serialize, Deserialize, Debug, Clone)]
+    #[derive(Serialize, Deserialize, Debug, Clone, ToSchema, InputObject)]
     pub struct CreateItemPayload {
         pub name: String,
         pub description: String,
         pub count: usize,
         pub height: usize,
-        pub weight: usize
+        pub weight: usize,
+        /// Expire the item after this many seconds, `0` removes an existing expiry and
+        /// leaving it out keeps whatever expiry the item already has
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub ttl_seconds: Option<u64>
     }
     
-    type RedisPool = bb8::Pool<bb8_redis::RedisConnectionManager>;
+    /// Partial update for `PATCH /api/items/{id}`, only the given fields are written
+    #[derive(Serialize, Deserialize, Debug, Clone, Default, ToSchema, InputObject)]
+    pub struct UpdateItemPayload {
+        pub name: Option<String>,
+        pub description: Option<String>,
+        pub count: Option<usize>,
+        pub height: Option<usize>,
+        pub weight: Option<usize>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub ttl_seconds: Option<u64>
+    }
+
+    /// Filters for `GET /api/items`, all bounds are inclusive
+    #[derive(Deserialize, Debug, Default, IntoParams, InputObject)]
+    #[into_params(parameter_in = Query)]
+    pub struct ItemFilter {
+        pub min_count: Option<usize>,
+        pub max_count: Option<usize>,
+        pub min_height: Option<usize>,
+        pub max_height: Option<usize>,
+        pub min_weight: Option<usize>,
+        pub max_weight: Option<usize>,
+        /// Case-insensitive prefix of the name
+        pub name_prefix: Option<String>
+    }
+
+    impl ItemFilter {
+        pub fn is_empty(&self) -> bool {
+            self.min_count.is_none() && self.max_count.is_none()
+                && self.min_height.is_none() && self.max_height.is_none()
+                && self.min_weight.is_none() && self.max_weight.is_none()
+                && self.name_prefix.is_none()
+        }
+
+        pub fn matches(&self, item: &Item) -> bool {
+            fn within(value: usize, min: Option<usize>, max: Option<usize>) -> bool {
+                min.is_none_or(|min| value >= min) && max.is_none_or(|max| value <= max)
+            }
+
+            within(item.count, self.min_count, self.max_count)
+                && within(item.height, self.min_height, self.max_height)
+                && within(item.weight, self.min_weight, self.max_weight)
+                && self.name_prefix.as_ref().is_none_or(|prefix| item.name.to_lowercase().starts_with(&prefix.to_lowercase()))
+        }
+    }
+
+    /// A deleted item kept under `trash:item:{id}` until it is restored or purged
+    #[derive(Serialize, Deserialize, Debug, Clone, ToSchema, SimpleObject)]
+    pub struct TrashedItem {
+        #[serde(flatten)]
+        #[graphql(flatten)]
+        pub item: Item,
+        /// Unix timestamp of the delete
+        pub deleted_at: i64
+    }
+
+    /// How an item is stored under `item:{id}`, picked with `REDIS_LAYOUT=json|hash`
+    #[derive(Debug, Clone, Copy, PartialEq, Default)]
+    pub enum Layout {
+        /// The whole item serialized as one JSON string
+        #[default]
+        Json,
+        /// One hash field per item field, so updates can touch single fields
+        Hash
+    }
+
+    impl Layout {
+        /// Reads `REDIS_LAYOUT`, unset means `json`. Any other value is refused rather than read as JSON,
+        /// the server would otherwise fail to decode every hash it finds.
+        pub fn from_env() -> anyhow::Result<Self> {
+            let layout = match std::env::var("REDIS_LAYOUT") {
+                Ok(layout) => layout,
+                Err(std::env::VarError::NotPresent) => return Ok(Self::Json),
+                Err(e) => return Err(anyhow::anyhow!("REDIS_LAYOUT: {}", e))
+            };
+
+            match layout.as_str() {
+                "json" => Ok(Self::Json),
+                "hash" => Ok(Self::Hash),
+                other => Err(anyhow::anyhow!("Unknown REDIS_LAYOUT '{}', use json or hash", other))
+            }
+        }
+    }
+
+    /// One entry of the `items:events` stream
+    #[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
+    pub struct StreamEvent {
+        pub stream_id: String,
+        pub event: ChangeEvent<Item>
+    }
+
+    /// A page of stream entries, pass `next` back as `since` to continue
+    #[derive(Serialize, Deserialize, Debug, Clone, Default, ToSchema)]
+    pub struct EventPage {
+        pub events: Vec<StreamEvent>,
+        /// Entries that could not be decoded, group consumers should still acknowledge them
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        pub undecodable: Vec<String>,
+        pub next: Option<String>
+    }
+
+    #[derive(Deserialize, Debug, IntoParams)]
+    #[into_params(parameter_in = Query)]
+    pub struct HistoryQuery {
+        /// Only entries after this stream id, the whole stream when left out
+        pub since: Option<String>,
+        pub limit: Option<usize>
+    }
+
+    #[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
+    pub struct CreateGroupPayload {
+        pub name: String,
+        /// Where the group starts reading: `$` (default) for new events only, `0` for the whole history
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub start: Option<String>
+    }
+
+    #[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
+    pub struct ReadGroupPayload {
+        pub consumer: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub count: Option<usize>,
+        /// Wait up to this long for new events when there are none
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub block_ms: Option<usize>,
+        /// Take over entries another consumer left unacknowledged for at least this long
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub claim_idle_ms: Option<u64>
+    }
+
+    #[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
+    pub struct AckPayload {
+        pub ids: Vec<String>
+    }
+
+    #[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
+    pub struct AckReport {
+        pub acknowledged: usize
+    }
+
+    pub type RedisPool = bb8::Pool<bb8_redis::RedisConnectionManager>;
     
     #[derive(Clone)]
     pub struct AppState {
-        pub redis_pool: RedisPool
+        pub redis_pool: RedisPool,
+        pub layout: Layout,
+        pub items_feed: Feed<Item>
+    }
+
+    impl FromRef<AppState> for Feed<Item> {
+        fn from_ref(state: &AppState) -> Self {
+            state.items_feed.clone()
+        }
     }
     
     pub type Result<T> = std::result::Result<T, Error>;
@@ -33,10 +191,15 @@ pub mod redis {
 
 
 pub mod sqlx {
+    use axum::extract::FromRef;
     use serde::{Deserialize, Serialize};
-    use crate::error::sqlx::Error;
+    use chrono::{DateTime, Utc};
+    use utoipa::ToSchema;
+    use async_graphql::{InputObject, SimpleObject};
+    use crate::{cache::Cache, error::sqlx::Error, events::Feed, replica::Replicas};
 
-    #[derive(Serialize, Deserialize, Debug, Clone)]
+    #[derive(Serialize, Deserialize, Debug, Clone, ToSchema, SimpleObject)]
+    #[graphql(complex)]
     pub struct Datas {
         pub id: i32,
         pub name: String,
@@ -44,14 +207,50 @@ pub mod sqlx {
         pub sys: i16
     }
 
-    #[derive(Serialize, Deserialize, Debug, Clone)]
+    #[derive(Serialize, Deserialize, Debug, Clone, ToSchema, InputObject)]
     pub struct DatasPayload {
         pub name: String,
         pub flags: i64,
         pub sys: i16
     }
 
-    #[derive(Serialize, Deserialize, Debug, Clone)]
+    /// Body of `POST /api/datas`, the niceties are inserted in the same transaction as the row
+    #[derive(Serialize, Deserialize, Debug, Clone, ToSchema, InputObject)]
+    pub struct CreateDatasPayload {
+        pub name: String,
+        pub flags: i64,
+        pub sys: i16,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        #[graphql(default)]
+        pub niceties: Vec<NicetiesPaylod>
+    }
+
+    /// A new row along with the niceties created with it, ids included
+    #[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
+    pub struct DatasWithNiceties {
+        #[serde(flatten)]
+        pub datas: Datas,
+        pub niceties: Vec<Niceties>
+    }
+
+    /// Body of `PUT /api/datas/by-name/{name}`, the name comes from the path
+    #[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
+    pub struct UpsertDatasPayload {
+        pub flags: i64,
+        pub sys: i16
+    }
+
+    /// A deleted row waiting in the trash until it is restored or purged
+    #[derive(Serialize, Deserialize, Debug, Clone, ToSchema, SimpleObject)]
+    pub struct TrashedDatas {
+        pub id: i32,
+        pub name: String,
+        pub flags: i64,
+        pub sys: i16,
+        pub deleted_at: DateTime<Utc>
+    }
+
+    #[derive(Serialize, Deserialize, Debug, Clone, ToSchema, SimpleObject)]
     pub struct Niceties {
         pub id: i32,
         pub datas_id: i32,
@@ -60,8 +259,11 @@ pub mod sqlx {
         pub info: String
     }
 
-    #[derive(Serialize, Deserialize, Debug, Clone)]
+    #[derive(Serialize, Deserialize, Debug, Clone, ToSchema, InputObject)]
     pub struct NicetiesPaylod {
+        /// Ignored when nested in a new row, which the niceties then hang off
+        #[serde(default)]
+        #[graphql(default)]
         pub datas_id: i32,
         pub mem: i64,
         pub stack: i16,
@@ -70,7 +272,23 @@ pub mod sqlx {
 
     #[derive(Clone)]
     pub struct AppState {
-        pub pg_pool: sqlx::Pool<sqlx::Postgres>
+        pub pg_pool: sqlx::Pool<sqlx::Postgres>,
+        /// Where reads may go instead of `pg_pool`, which takes every write
+        pub replicas: Replicas<sqlx::Pool<sqlx::Postgres>>,
+        pub datas_feed: Feed<Datas>,
+        pub cache: Cache
+    }
+
+    impl FromRef<AppState> for Feed<Datas> {
+        fn from_ref(state: &AppState) -> Self {
+            state.datas_feed.clone()
+        }
+    }
+
+    impl FromRef<AppState> for Cache {
+        fn from_ref(state: &AppState) -> Self {
+            state.cache.clone()
+        }
     }
 
     pub type Result<T> = std::result::Result<T, Error>;
@@ -80,11 +298,14 @@ pub mod sqlx {
 pub mod tok_postgres {
     use std::{convert::Infallible, sync::Arc};
     use serde::{Deserialize, Serialize};
-    use tokio_postgres::{Client, Statement};
-    use axum::{extract::FromRequestParts, http::request::Parts};
-    use crate::error::tok_postgres::Error;
+    use tokio_postgres::{Client, Config, Statement};
+    use tokio_postgres_rustls::MakeRustlsConnect;
+    use axum::{extract::{FromRef, FromRequestParts}, http::request::Parts};
+    use chrono::{DateTime, Utc};
+    use utoipa::{IntoParams, ToSchema};
+    use crate::{cache::Cache, error::tok_postgres::Error, events::Feed, replica::Replicas};
 
-    #[derive(Serialize, Deserialize, Debug, Clone)]
+    #[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
     pub struct Datas {
         pub id: i32,
         pub name: String,
@@ -92,14 +313,49 @@ pub mod tok_postgres {
         pub sys: i16
     }
 
-    #[derive(Serialize, Deserialize, Debug, Clone)]
+    #[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
     pub struct DatasPayload {
         pub name: String,
         pub flags: i64,
         pub sys: i16
     }
 
-    #[derive(Serialize, Deserialize, Debug, Clone)]
+    /// Body of `POST /api/datas`, the niceties are inserted in the same transaction as the row
+    #[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
+    pub struct CreateDatasPayload {
+        pub name: String,
+        pub flags: i64,
+        pub sys: i16,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        pub niceties: Vec<NicetiesPaylod>
+    }
+
+    /// A new row along with the niceties created with it, ids included
+    #[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
+    pub struct DatasWithNiceties {
+        #[serde(flatten)]
+        pub datas: Datas,
+        pub niceties: Vec<Niceties>
+    }
+
+    /// Body of `PUT /api/datas/by-name/{name}`, the name comes from the path
+    #[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
+    pub struct UpsertDatasPayload {
+        pub flags: i64,
+        pub sys: i16
+    }
+
+    /// A deleted row waiting in the trash until it is restored or purged
+    #[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
+    pub struct TrashedDatas {
+        pub id: i32,
+        pub name: String,
+        pub flags: i64,
+        pub sys: i16,
+        pub deleted_at: DateTime<Utc>
+    }
+
+    #[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
     pub struct Niceties {
         pub id: i32,
         pub datas_id: i32,
@@ -108,22 +364,78 @@ pub mod tok_postgres {
         pub info: String
     }
 
-    #[derive(Serialize, Deserialize, Debug, Clone)]
+    #[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
     pub struct NicetiesPaylod {
+        /// Ignored when nested in a new row, which the niceties then hang off
+        #[serde(default)]
         pub datas_id: i32,
         pub mem: i64,
         pub stack: i16,
         pub info: String
     }
 
+    #[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, ToSchema)]
+    #[serde(rename_all = "lowercase")]
+    pub enum ImportFormat {
+        Csv,
+        Ndjson
+    }
+
+    impl ImportFormat {
+        pub fn from_content_type(content_type: &str) -> Option<Self> {
+            match content_type.split(';').next()?.trim() {
+                "text/csv" => Some(Self::Csv),
+                "application/x-ndjson" | "application/ndjson" => Some(Self::Ndjson),
+                _ => None
+            }
+        }
+    }
+
+    #[derive(Deserialize, Debug, IntoParams)]
+    #[into_params(parameter_in = Query)]
+    pub struct ImportQuery {
+        #[param(inline)]
+        pub format: Option<ImportFormat>
+    }
+
+    #[derive(Serialize, Deserialize, Debug, Clone, Default, ToSchema)]
+    pub struct ImportReport {
+        pub imported: u64,
+        pub rejected: u64,
+        pub rejected_lines: Vec<u64>
+    }
+
+    impl ImportReport {
+        pub fn reject(&mut self, line: u64, max_listed: usize) {
+            self.rejected += 1;
+            if self.rejected_lines.len() < max_listed {
+                self.rejected_lines.push(line);
+            }
+        }
+    }
+
     #[derive(Clone)]
     pub struct AppState {
         pub pg_pool: PgPool,
+        /// Where reads may go instead of `pg_pool`, which takes every write
+        pub replicas: Replicas<PgPool>,
+        /// Prepared on the primary, reads on a replica send the SQL itself
         pub get_datas: Statement,
         pub get_data: Statement,
-        pub create_datas: Statement,
-        pub edit_datas: Statement,
-        pub destroy_datas: Statement,
+        pub datas_feed: Feed<Datas>,
+        pub cache: Cache,
+    }
+
+    impl FromRef<AppState> for Feed<Datas> {
+        fn from_ref(state: &AppState) -> Self {
+            state.datas_feed.clone()
+        }
+    }
+
+    impl FromRef<AppState> for Cache {
+        fn from_ref(state: &AppState) -> Self {
+            state.cache.clone()
+        }
     }
 
     pub struct PgClient {
@@ -133,6 +445,25 @@ pub mod tok_postgres {
         pub create_datas: Statement,
         pub edit_datas: Statement,
         pub destroy_datas: Statement,
+        pub trash_datas: Statement,
+        pub restore_datas: Statement,
+        pub datas_feed: Feed<Datas>,
+        pub cache: Cache,
+        /// Imports open their own connection with these, a COPY would hold up every request on `client`
+        pub config: Config,
+        pub tls: MakeRustlsConnect,
+    }
+
+    impl FromRef<Arc<PgClient>> for Feed<Datas> {
+        fn from_ref(state: &Arc<PgClient>) -> Self {
+            state.datas_feed.clone()
+        }
+    }
+
+    impl FromRef<Arc<PgClient>> for Cache {
+        fn from_ref(state: &Arc<PgClient>) -> Self {
+            state.cache.clone()
+        }
     }
 
     pub struct PgConnection(pub Arc<PgClient>);
@@ -146,5 +477,5 @@ pub mod tok_postgres {
     }
 
     pub type Result<T> = std::result::Result<T, Error>;
-    pub type PgPool = bb8::Pool<bb8_postgres::PostgresConnectionManager<tokio_postgres::NoTls>>;
+    pub type PgPool = bb8::Pool<bb8_postgres::PostgresConnectionManager<tokio_postgres_rustls::MakeRustlsConnect>>;
 }
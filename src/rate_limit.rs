@@ -0,0 +1,386 @@
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::{Arc, LazyLock, Mutex},
+    time::{Duration, Instant},
+};
+use axum::{
+    extract::{ConnectInfo, Request, State},
+    http::{HeaderMap, HeaderValue, Method, StatusCode, header::RETRY_AFTER},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use bb8_redis::{bb8, RedisConnectionManager};
+use redis::Script;
+use crate::{auth::Principal, grpc, tls};
+
+/// Once the in-memory fallback tracks this many buckets, the ones that refilled completely are dropped
+const MAX_LOCAL_BUCKETS: usize = 10_000;
+
+type RedisPool = bb8::Pool<RedisConnectionManager>;
+
+/// Token bucket kept in a hash, refilled from the Redis clock so every instance agrees on time.
+///
+/// KEYS[1] is the bucket, ARGV is the capacity and the refill rate in tokens per millisecond.
+/// Returns whether the request was let through, the whole tokens left, the milliseconds until
+/// one token is available again and until the bucket is full.
+static TAKE_TOKEN: LazyLock<Script> = LazyLock::new(|| Script::new(r#"
+local capacity = tonumber(ARGV[1])
+local rate = tonumber(ARGV[2])
+local clock = redis.call('TIME')
+local now = tonumber(clock[1]) * 1000 + math.floor(tonumber(clock[2]) / 1000)
+
+local bucket = redis.call('HMGET', KEYS[1], 'tokens', 'ts')
+local tokens = tonumber(bucket[1]) or capacity
+local ts = tonumber(bucket[2]) or now
+tokens = math.min(capacity, tokens + math.max(0, now - ts) * rate)
+
+local allowed = 0
+local retry = 0
+if tokens >= 1 then
+    tokens = tokens - 1
+    allowed = 1
+else
+    retry = math.ceil((1 - tokens) / rate)
+end
+
+local reset = math.ceil((capacity - tokens) / rate)
+redis.call('HSET', KEYS[1], 'tokens', tostring(tokens), 'ts', now)
+redis.call('PEXPIRE', KEYS[1], reset + 1000)
+
+return {allowed, math.floor(tokens), retry, reset}
+"#));
+
+/// Endpoints sharing a budget, each client gets one bucket per group
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RouteGroup {
+    Read,
+    Write,
+    /// Imports, exports and search, which cost far more than a single row
+    Bulk
+}
+
+impl RouteGroup {
+    pub fn of(method: &Method, path: &str) -> Self {
+        if path.ends_with("/import") || path.ends_with("/export") || path.starts_with("/api/search") {
+            Self::Bulk
+        }
+        else if method == Method::GET || method == Method::HEAD || grpc::is_read(path) {
+            Self::Read
+        }
+        else {
+            Self::Write
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Read => "read",
+            Self::Write => "write",
+            Self::Bulk => "bulk"
+        }
+    }
+}
+
+/// `capacity` requests in a burst, refilled evenly over `window`
+#[derive(Debug, Clone, Copy)]
+pub struct Limit {
+    pub capacity: u32,
+    pub window: Duration
+}
+
+impl Limit {
+    /// Reads `<requests>/<seconds>` from `var`, e.g. `RATE_LIMIT_READ=300/60`
+    fn from_env(var: &str, default: Limit) -> Self {
+        let Ok(value) = std::env::var(var) else {
+            return default;
+        };
+
+        let parsed = value.split_once('/').and_then(|(capacity, secs)| {
+            let capacity = capacity.trim().parse().ok().filter(|c| *c > 0)?;
+            let secs: u64 = secs.trim().parse().ok().filter(|s| *s > 0)?;
+            Some(Limit { capacity, window: Duration::from_secs(secs) })
+        });
+
+        parsed.unwrap_or_else(|| {
+            tracing::warn!("Ignoring {}={:?}, expected <requests>/<seconds>", var, value);
+            default
+        })
+    }
+
+    /// Tokens added back per millisecond
+    fn rate(&self) -> f64 {
+        self.capacity as f64 / self.window.as_millis().max(1) as f64
+    }
+}
+
+/// What a bucket said about one request
+#[derive(Debug, Clone, Copy)]
+struct Decision {
+    allowed: bool,
+    remaining: u64,
+    retry_after_ms: u64,
+    reset_ms: u64
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+    full_at: Instant
+}
+
+/// Token bucket limiter for every route, enabled by default.
+///
+/// Buckets live in Redis when `RATE_LIMIT_REDIS_URL` is set, so all instances share them, and in
+/// process memory otherwise. A failing Redis falls back to the local buckets rather than rejecting.
+#[derive(Clone)]
+pub struct RateLimiter {
+    redis: Option<RedisPool>,
+    read: Limit,
+    write: Limit,
+    bulk: Limit,
+    local: Arc<Mutex<HashMap<String, Bucket>>>
+}
+
+impl RateLimiter {
+    /// Limits come from `RATE_LIMIT_READ`, `RATE_LIMIT_WRITE` and `RATE_LIMIT_BULK`
+    pub async fn from_env() -> anyhow::Result<Self> {
+        let redis = match std::env::var("RATE_LIMIT_REDIS_URL") {
+            Ok(url) => {
+                tracing::info!("Sharing rate limits through Redis");
+                Some(bb8::Pool::builder().build(RedisConnectionManager::new(tls::redis(&url)?)?).await?)
+            }
+            Err(_) => None
+        };
+
+        Ok(Self {
+            redis,
+            read: Limit::from_env("RATE_LIMIT_READ", Limit { capacity: 300, window: Duration::from_secs(60) }),
+            write: Limit::from_env("RATE_LIMIT_WRITE", Limit { capacity: 60, window: Duration::from_secs(60) }),
+            bulk: Limit::from_env("RATE_LIMIT_BULK", Limit { capacity: 10, window: Duration::from_secs(60) }),
+            local: Default::default()
+        })
+    }
+
+    pub fn limit(&self, group: RouteGroup) -> Limit {
+        match group {
+            RouteGroup::Read => self.read,
+            RouteGroup::Write => self.write,
+            RouteGroup::Bulk => self.bulk
+        }
+    }
+
+    async fn take(&self, bucket: &str, limit: Limit) -> Decision {
+        let Some(pool) = &self.redis else {
+            return self.take_local(bucket, limit, Instant::now());
+        };
+
+        let taken = match pool.get().await {
+            Ok(mut con) => TAKE_TOKEN
+                .key(bucket)
+                .arg(limit.capacity)
+                .arg(limit.rate())
+                .invoke_async::<(i64, i64, i64, i64)>(&mut *con)
+                .await
+                .map_err(|e| e.to_string()),
+            Err(e) => Err(e.to_string())
+        };
+
+        match taken {
+            Ok((allowed, remaining, retry, reset)) => Decision {
+                allowed: allowed == 1,
+                remaining: remaining.max(0) as u64,
+                retry_after_ms: retry.max(0) as u64,
+                reset_ms: reset.max(0) as u64
+            },
+            Err(e) => {
+                tracing::warn!("Rate limit lookup of {} failed, using the local bucket: {}", bucket, e);
+                self.take_local(bucket, limit, Instant::now())
+            }
+        }
+    }
+
+    /// Takes a token from the in-memory bucket as of `now`
+    fn take_local(&self, bucket: &str, limit: Limit, now: Instant) -> Decision {
+        let capacity = limit.capacity as f64;
+        let rate = limit.rate();
+        let mut buckets = self.local.lock().unwrap();
+
+        if buckets.len() >= MAX_LOCAL_BUCKETS {
+            buckets.retain(|_, b| b.full_at > now);
+        }
+
+        let entry = buckets.entry(bucket.to_string()).or_insert(Bucket { tokens: capacity, updated: now, full_at: now });
+        let elapsed = now.duration_since(entry.updated).as_millis() as f64;
+        entry.tokens = (entry.tokens + elapsed * rate).min(capacity);
+        entry.updated = now;
+
+        let allowed = entry.tokens >= 1.0;
+        if allowed {
+            entry.tokens -= 1.0;
+        }
+
+        let reset_ms = ((capacity - entry.tokens) / rate).ceil() as u64;
+        entry.full_at = now + Duration::from_millis(reset_ms);
+
+        Decision {
+            allowed,
+            remaining: entry.tokens.floor() as u64,
+            retry_after_ms: if allowed { 0 } else { ((1.0 - entry.tokens) / rate).ceil() as u64 },
+            reset_ms
+        }
+    }
+}
+
+/// The peer address, the only thing known about a caller before its credentials are checked
+fn peer_id(req: &Request) -> String {
+    match req.extensions().get::<ConnectInfo<SocketAddr>>() {
+        Some(ConnectInfo(addr)) => format!("ip:{}", addr.ip()),
+        None => "ip:unknown".to_string()
+    }
+}
+
+fn seconds(ms: u64) -> u64 {
+    ms.div_ceil(1000)
+}
+
+/// Reports `decision` unless a bucket closer to running out already did
+fn set_headers(headers: &mut HeaderMap, limit: Limit, decision: &Decision) {
+    let reported = headers.get("ratelimit-remaining").and_then(|v| v.to_str().ok()).and_then(|v| v.parse::<u64>().ok());
+    if reported.is_some_and(|remaining| remaining <= decision.remaining) {
+        return;
+    }
+
+    headers.insert("ratelimit-limit", HeaderValue::from(limit.capacity));
+    headers.insert("ratelimit-remaining", HeaderValue::from(decision.remaining));
+    headers.insert("ratelimit-reset", HeaderValue::from(seconds(decision.reset_ms)));
+
+    let policy = format!("{};w={}", limit.capacity, limit.window.as_secs());
+    if let Ok(policy) = HeaderValue::from_str(&policy) {
+        headers.insert("ratelimit-policy", policy);
+    }
+}
+
+/// Middleware taking one token from the peer address's bucket for the route group, 429 once it is empty.
+///
+/// It runs before [`crate::auth::require`], so every request pays here whatever token it carries.
+pub async fn limit(State(limiter): State<RateLimiter>, req: Request, next: Next) -> Response {
+    let client = peer_id(&req);
+    charge(&limiter, &client, req, next).await
+}
+
+/// Middleware taking one token from the authenticated caller's bucket as well, layered inside
+/// [`crate::auth::require`] so only verified credentials get a bucket. Lets everything through when auth is off.
+pub async fn limit_caller(State(limiter): State<RateLimiter>, req: Request, next: Next) -> Response {
+    let Some(principal) = req.extensions().get::<Principal>() else {
+        return next.run(req).await;
+    };
+
+    let client = format!("sub:{}", principal.subject);
+    charge(&limiter, &client, req, next).await
+}
+
+async fn charge(limiter: &RateLimiter, client: &str, req: Request, next: Next) -> Response {
+    let group = RouteGroup::of(req.method(), req.uri().path());
+    let limit = limiter.limit(group);
+    let bucket = format!("ratelimit:{}:{}", group.as_str(), client);
+
+    let decision = limiter.take(&bucket, limit).await;
+
+    let mut response = if decision.allowed {
+        next.run(req).await
+    }
+    else {
+        let retry_after = seconds(decision.retry_after_ms).max(1);
+        let mut response = (StatusCode::TOO_MANY_REQUESTS, "Too many requests").into_response();
+        response.headers_mut().insert(RETRY_AFTER, HeaderValue::from(retry_after));
+        response
+    };
+
+    set_headers(response.headers_mut(), limit, &decision);
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limiter() -> RateLimiter {
+        let limit = Limit { capacity: 3, window: Duration::from_secs(3) };
+        RateLimiter { redis: None, read: limit, write: limit, bulk: limit, local: Default::default() }
+    }
+
+    /// Whether each of `count` requests at `now` was let through
+    fn burst(limiter: &RateLimiter, count: usize, now: Instant) -> Vec<bool> {
+        (0..count).map(|_| limiter.take_local("ratelimit:read:ip:test", limiter.read, now).allowed).collect()
+    }
+
+    #[test]
+    fn a_full_bucket_allows_a_burst_of_its_capacity() {
+        let limiter = limiter();
+        let now = Instant::now();
+
+        assert_eq!(burst(&limiter, 4, now), [true, true, true, false]);
+
+        let refused = limiter.take_local("ratelimit:read:ip:test", limiter.read, now);
+        assert_eq!(refused.remaining, 0);
+        assert_eq!(refused.retry_after_ms, 1000);
+        assert_eq!(refused.reset_ms, 3000);
+    }
+
+    #[test]
+    fn tokens_come_back_over_the_window() {
+        let limiter = limiter();
+        let start = Instant::now();
+        burst(&limiter, 3, start);
+
+        // One token per second, a refused request keeps the fraction gathered so far
+        assert_eq!(burst(&limiter, 1, start + Duration::from_millis(999)), [false]);
+        assert_eq!(burst(&limiter, 2, start + Duration::from_millis(1000)), [true, false]);
+        assert_eq!(burst(&limiter, 3, start + Duration::from_millis(3000)), [true, true, false]);
+    }
+
+    #[test]
+    fn refills_stop_at_the_capacity() {
+        let limiter = limiter();
+        let start = Instant::now();
+        burst(&limiter, 3, start);
+
+        let later = start + Duration::from_secs(60);
+        assert_eq!(burst(&limiter, 4, later), [true, true, true, false]);
+    }
+
+    #[test]
+    fn buckets_are_separate_per_client() {
+        let limiter = limiter();
+        let now = Instant::now();
+        burst(&limiter, 3, now);
+
+        assert!(limiter.take_local("ratelimit:read:ip:other", limiter.read, now).allowed);
+    }
+
+    #[test]
+    fn routes_fall_into_their_group() {
+        let cases = [
+            (Method::GET, "/api/datas", RouteGroup::Read),
+            (Method::HEAD, "/api/items/7", RouteGroup::Read),
+            (Method::GET, "/api/datas/events", RouteGroup::Read),
+            (Method::POST, "/items.v1.Items/Get", RouteGroup::Read),
+            (Method::POST, "/datas.v1.Datas/Watch", RouteGroup::Read),
+            (Method::POST, "/api/datas", RouteGroup::Write),
+            (Method::PUT, "/api/items/7", RouteGroup::Write),
+            (Method::PATCH, "/api/items/7", RouteGroup::Write),
+            (Method::DELETE, "/api/datas/7", RouteGroup::Write),
+            (Method::POST, "/items.v1.Items/Create", RouteGroup::Write),
+            (Method::POST, "/graphql", RouteGroup::Write),
+            (Method::POST, "/api/datas/import", RouteGroup::Bulk),
+            (Method::GET, "/api/datas/export", RouteGroup::Bulk),
+            (Method::GET, "/api/items/export", RouteGroup::Bulk),
+            (Method::GET, "/api/search", RouteGroup::Bulk)
+        ];
+
+        for (method, path, group) in cases {
+            assert_eq!(RouteGroup::of(&method, path), group, "{} {}", method, path);
+        }
+    }
+}
@@ -0,0 +1,258 @@
+use std::{
+    convert::Infallible,
+    future::Future,
+    sync::{atomic::{AtomicBool, AtomicUsize, Ordering}, Arc},
+    time::Duration
+};
+use axum::{
+    extract::{FromRequestParts, Request, State},
+    http::{header::{COOKIE, SET_COOKIE}, request::Parts, HeaderMap, HeaderValue, Method},
+    middleware::Next,
+    response::Response
+};
+use bb8_postgres::PostgresConnectionManager;
+use chrono::Utc;
+use tokio_postgres_rustls::MakeRustlsConnect;
+use utoipa::{
+    openapi::{path::{Parameter, ParameterBuilder, ParameterIn}, ObjectBuilder, Type},
+    IntoParams
+};
+use crate::{cache::Cache, grpc};
+
+/// Cookie set after a write, reads carrying it go to the primary until the time it holds, in Unix milliseconds
+pub const READ_PRIMARY_COOKIE: &str = "read-primary-until";
+
+/// Same as [`READ_PRIMARY_COOKIE`] for clients that don't keep cookies, returned after writes to be sent back as is
+pub const READ_PRIMARY_HEADER: &str = "x-read-primary-until";
+
+const DEFAULT_CHECK_SECONDS: u64 = 5;
+const DEFAULT_READ_YOUR_WRITES_SECONDS: u64 = 5;
+
+/// How long a health check waits for a replica before counting it as down
+const PROBE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// How long a read waits for a replica connection, so one that died between checks fails fast
+pub const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Pools whose health can be checked with a trivial query
+pub trait Probe: Clone + Send + Sync + 'static {
+    fn probe(&self) -> impl Future<Output = Result<(), String>> + Send;
+}
+
+impl Probe for sqlx::PgPool {
+    async fn probe(&self) -> Result<(), String> {
+        sqlx::query("SELECT 1").execute(self).await.map(|_| ()).map_err(|e| e.to_string())
+    }
+}
+
+impl Probe for bb8::Pool<PostgresConnectionManager<MakeRustlsConnect>> {
+    async fn probe(&self) -> Result<(), String> {
+        let conn = self.get().await.map_err(|e| e.to_string())?;
+        conn.simple_query("SELECT 1").await.map(|_| ()).map_err(|e| e.to_string())
+    }
+}
+
+struct Replica<P> {
+    /// Position in `DATABASE_REPLICA_URLS`, logged instead of the URL and its credentials
+    index: usize,
+    pool: P,
+    healthy: AtomicBool
+}
+
+/// Read replicas of the Postgres primary, listed comma separated in `DATABASE_REPLICA_URLS`.
+///
+/// Reads take the healthy replicas in turn and fall back to the primary when none is left. Replicas are
+/// checked every `DATABASE_REPLICA_CHECK_SECONDS` and count as down until their first check passes.
+#[derive(Clone)]
+pub struct Replicas<P> {
+    replicas: Arc<[Replica<P>]>,
+    next: Arc<AtomicUsize>,
+    read_your_writes: ReadYourWrites
+}
+
+impl<P: Probe> Replicas<P> {
+    /// Opens a pool per replica with `connect`, which must not wait for the replica to be up
+    pub fn from_env(connect: impl Fn(&str) -> anyhow::Result<P>) -> anyhow::Result<Self> {
+        let urls = std::env::var("DATABASE_REPLICA_URLS").unwrap_or_default();
+        let replicas: Arc<[Replica<P>]> = urls
+            .split(',')
+            .map(str::trim)
+            .filter(|url| !url.is_empty())
+            .enumerate()
+            .map(|(index, url)| Ok(Replica { index, pool: connect(url)?, healthy: AtomicBool::new(false) }))
+            .collect::<anyhow::Result<_>>()?;
+
+        if replicas.is_empty() {
+            return Ok(Self { replicas, next: Default::default(), read_your_writes: ReadYourWrites { window: None } });
+        }
+
+        let every = Duration::from_secs(env_seconds("DATABASE_REPLICA_CHECK_SECONDS", DEFAULT_CHECK_SECONDS).max(1));
+        let window = Some(Duration::from_secs(env_seconds("READ_YOUR_WRITES_SECONDS", DEFAULT_READ_YOUR_WRITES_SECONDS)))
+            .filter(|window| !window.is_zero());
+
+        match window {
+            Some(window) => tracing::info!("Reading from {} replicas, callers read their writes from the primary for {}s", replicas.len(), window.as_secs()),
+            None => tracing::info!("Reading from {} replicas, without read-your-writes", replicas.len())
+        }
+
+        tokio::spawn(check_health(replicas.clone(), every));
+
+        Ok(Self { replicas, next: Default::default(), read_your_writes: ReadYourWrites { window } })
+    }
+}
+
+impl<P> Replicas<P> {
+    /// The replica to read from next, `None` when the read belongs on the primary
+    pub fn pick(&self, consistency: Consistency) -> Option<&P> {
+        if consistency == Consistency::ReadYourWrites || self.replicas.is_empty() {
+            return None;
+        }
+
+        let start = self.next.fetch_add(1, Ordering::Relaxed);
+        let count = self.replicas.len();
+
+        (0..count)
+            .map(|offset| &self.replicas[(start + offset) % count])
+            .find(|replica| replica.healthy.load(Ordering::Relaxed))
+            .map(|replica| &replica.pool)
+    }
+
+    /// Like [`Self::pick`] for loads that fill `cache`, which stay on the primary while caching is on:
+    /// an entry filled from a lagging replica would outlive the lag
+    pub fn pick_for(&self, cache: &Cache, consistency: Consistency) -> Option<&P> {
+        if cache.is_enabled() {
+            None
+        }
+        else {
+            self.pick(consistency)
+        }
+    }
+
+    /// State of [`mark_writes`]
+    pub fn read_your_writes(&self) -> ReadYourWrites {
+        self.read_your_writes
+    }
+}
+
+fn env_seconds(name: &str, default: u64) -> u64 {
+    std::env::var(name).ok().and_then(|v| v.parse().ok()).unwrap_or(default)
+}
+
+async fn check_health<P: Probe>(replicas: Arc<[Replica<P>]>, every: Duration) {
+    let mut interval = tokio::time::interval(every);
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+    loop {
+        interval.tick().await;
+
+        for replica in replicas.iter() {
+            let healthy = match tokio::time::timeout(PROBE_TIMEOUT, replica.pool.probe()).await {
+                Ok(Ok(())) => true,
+                Ok(Err(e)) => {
+                    tracing::debug!("Replica {} failed its health check: {}", replica.index, e);
+                    false
+                }
+                Err(_) => false
+            };
+
+            if replica.healthy.swap(healthy, Ordering::Relaxed) != healthy {
+                if healthy {
+                    tracing::info!("Replica {} is up, reads go to it again", replica.index);
+                }
+                else {
+                    tracing::warn!("Replica {} is down, reads skip it until it recovers", replica.index);
+                }
+            }
+        }
+    }
+}
+
+/// Whether a read may see a replica that hasn't caught up yet
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum Consistency {
+    /// Any healthy replica will do
+    #[default]
+    Eventual,
+    /// The caller wrote moments ago and reads from the primary so its change is there
+    ReadYourWrites
+}
+
+impl Consistency {
+    /// Reads [`READ_PRIMARY_HEADER`] or [`READ_PRIMARY_COOKIE`], an expired or unreadable marker is ignored
+    pub fn from_headers(headers: &HeaderMap) -> Self {
+        let header = headers.get(READ_PRIMARY_HEADER).and_then(|v| v.to_str().ok());
+        let cookie = headers
+            .get_all(COOKIE)
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .flat_map(|v| v.split(';'))
+            .filter_map(|pair| pair.trim().split_once('='))
+            .find(|(name, _)| *name == READ_PRIMARY_COOKIE)
+            .map(|(_, value)| value);
+
+        let until = header.into_iter().chain(cookie).filter_map(|v| v.trim().parse::<i64>().ok()).max();
+
+        match until {
+            Some(until) if until > Utc::now().timestamp_millis() => Self::ReadYourWrites,
+            _ => Self::Eventual
+        }
+    }
+}
+
+impl<S: Send + Sync> FromRequestParts<S> for Consistency {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _: &S) -> Result<Self, Self::Rejection> {
+        Ok(Self::from_headers(&parts.headers))
+    }
+}
+
+impl IntoParams for Consistency {
+    fn into_params(_: impl Fn() -> Option<ParameterIn>) -> Vec<Parameter> {
+        vec![
+            ParameterBuilder::new()
+                .name(READ_PRIMARY_HEADER)
+                .parameter_in(ParameterIn::Header)
+                .description(Some(
+                    "Value returned by a recent write, reads the primary instead of a replica until then. \
+                     The `read-primary-until` cookie does the same for clients that keep cookies"
+                ))
+                .schema(Some(ObjectBuilder::new().schema_type(Type::Integer)))
+                .build()
+        ]
+    }
+}
+
+/// How long after a write the caller keeps reading from the primary, `None` when off or without replicas
+#[derive(Debug, Clone, Copy)]
+pub struct ReadYourWrites {
+    window: Option<Duration>
+}
+
+/// Middleware marking the caller of a successful write so its next reads see it, see [`Consistency`].
+///
+/// GraphQL queries and mutations share `POST /graphql`, so both count as writes.
+pub async fn mark_writes(State(read_your_writes): State<ReadYourWrites>, req: Request, next: Next) -> Response {
+    let Some(window) = read_your_writes.window else {
+        return next.run(req).await;
+    };
+
+    let method = req.method();
+    let write = !(method == Method::GET || method == Method::HEAD || method == Method::OPTIONS || grpc::is_read(req.uri().path()));
+
+    let mut response = next.run(req).await;
+    if !write || !response.status().is_success() {
+        return response;
+    }
+
+    let until = Utc::now().timestamp_millis() + window.as_millis() as i64;
+    let cookie = format!("{}={}; Max-Age={}; Path=/; HttpOnly; SameSite=Lax", READ_PRIMARY_COOKIE, until, window.as_secs().max(1));
+
+    let headers = response.headers_mut();
+    headers.insert(READ_PRIMARY_HEADER, HeaderValue::from(until));
+    if let Ok(cookie) = HeaderValue::from_str(&cookie) {
+        headers.append(SET_COOKIE, cookie);
+    }
+
+    response
+}
@@ -0,0 +1,47 @@
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+
+const DEFAULT_PER_PAGE: u32 = 20;
+const MAX_PER_PAGE: u32 = 100;
+
+#[derive(Deserialize, Debug, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct SearchQuery {
+    /// Web-search style query: words, `"quoted phrases"`, `or` and `-excluded`
+    pub q: String,
+    pub page: Option<u32>,
+    pub per_page: Option<u32>
+}
+
+impl SearchQuery {
+    /// The 1-based page and its size, clamped to sane bounds
+    pub fn page(&self) -> (u32, u32) {
+        (self.page.unwrap_or(1).max(1), self.per_page.unwrap_or(DEFAULT_PER_PAGE).clamp(1, MAX_PER_PAGE))
+    }
+
+    /// `LIMIT` and `OFFSET` for the requested page
+    pub fn limit_offset(&self) -> (i64, i64) {
+        let (page, per_page) = self.page();
+        (per_page as i64, (page as i64 - 1) * per_page as i64)
+    }
+}
+
+/// One match, either a datas row (`kind = "datas"`) or a niceties row (`kind = "niceties"`)
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
+pub struct SearchHit {
+    pub kind: String,
+    pub id: i32,
+    /// The datas row the hit belongs to, its own id for datas hits
+    pub datas_id: i32,
+    pub rank: f32,
+    /// The matched text with the matching words wrapped in `<mark>`
+    pub headline: String
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
+pub struct SearchResults {
+    pub total: i64,
+    pub page: u32,
+    pub per_page: u32,
+    pub hits: Vec<SearchHit>
+}
@@ -0,0 +1,121 @@
+use axum::{extract::FromRequestParts, http::{StatusCode, request::Parts}};
+use crate::auth::Principal;
+
+/// Header picking the tenant when the credentials don't name one
+pub const TENANT_HEADER: &str = "x-tenant-id";
+
+/// Tenant of requests that name none, and of every row that existed before tenants
+pub const DEFAULT_TENANT: &str = "default";
+
+const MAX_TENANT_LEN: usize = 64;
+
+/// Tenant ids end up in Redis keys and Postgres settings, so they are kept to `[A-Za-z0-9_-]`
+pub fn validate(tenant: &str) -> Result<(), String> {
+    let valid = !tenant.is_empty()
+        && tenant.len() <= MAX_TENANT_LEN
+        && tenant.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'_' || b == b'-');
+
+    if valid {
+        Ok(())
+    }
+    else {
+        Err(format!("Invalid tenant id '{}', use up to {} letters, digits, '_' or '-'", tenant, MAX_TENANT_LEN))
+    }
+}
+
+/// The tenant a request works in.
+///
+/// Taken from the API key or the `tenant` claim of the JWT when they carry one, else from `X-Tenant-Id`,
+/// else [`DEFAULT_TENANT`]. A header naming another tenant than the credentials is refused, and so is any
+/// tenant but the default one for unbound credentials that aren't admin. The header is free while auth is off.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Tenant(pub String);
+
+impl Tenant {
+    /// Whether an event tagged with `tenant` belongs to this one, untagged events belong to the default tenant
+    pub fn owns(&self, tenant: Option<&str>) -> bool {
+        tenant.unwrap_or(DEFAULT_TENANT) == self.0
+    }
+}
+
+impl<S: Send + Sync> FromRequestParts<S> for Tenant {
+    type Rejection = (StatusCode, String);
+
+    async fn from_request_parts(parts: &mut Parts, _: &S) -> Result<Self, Self::Rejection> {
+        let principal = parts.extensions.get::<Principal>();
+
+        let requested = match parts.headers.get(TENANT_HEADER) {
+            Some(value) => {
+                let value = value.to_str().map_err(|_| (StatusCode::BAD_REQUEST, "Invalid X-Tenant-Id header".to_string()))?;
+                Some(value.trim().to_string())
+            }
+            None => None
+        };
+
+        let tenant = match (principal, requested) {
+            (Some(Principal { tenant: Some(bound), .. }), Some(requested)) if *bound != requested => {
+                return Err((StatusCode::FORBIDDEN, format!("These credentials belong to tenant '{}'", bound)));
+            }
+            (Some(Principal { tenant: Some(bound), .. }), _) => bound.clone(),
+            (Some(principal), Some(requested)) if !principal.is_admin() && requested != DEFAULT_TENANT => {
+                return Err((StatusCode::FORBIDDEN, format!("Only admin credentials may pick a tenant, these work in '{}'", DEFAULT_TENANT)));
+            }
+            (_, Some(requested)) => requested,
+            (_, None) => DEFAULT_TENANT.to_string()
+        };
+
+        validate(&tenant).map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+
+        Ok(Self(tenant))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::Request;
+    use crate::auth::Scope;
+    use super::*;
+
+    async fn tenant(principal: Option<Principal>, header: Option<&str>) -> Result<Tenant, StatusCode> {
+        let mut req = Request::builder();
+        if let Some(header) = header {
+            req = req.header(TENANT_HEADER, header);
+        }
+        let (mut parts, _) = req.body(()).unwrap().into_parts();
+        if let Some(principal) = principal {
+            parts.extensions.insert(principal);
+        }
+
+        Tenant::from_request_parts(&mut parts, &()).await.map_err(|(status, _)| status)
+    }
+
+    fn principal(scope: Scope, tenant: Option<&str>) -> Principal {
+        Principal { subject: "key:test".to_string(), scopes: vec![scope], tenant: tenant.map(str::to_string) }
+    }
+
+    #[tokio::test]
+    async fn bound_credentials_stay_in_their_tenant() {
+        let bound = || Some(principal(Scope::ItemsWrite, Some("acme")));
+
+        assert_eq!(tenant(bound(), None).await, Ok(Tenant("acme".to_string())));
+        assert_eq!(tenant(bound(), Some("acme")).await, Ok(Tenant("acme".to_string())));
+        assert_eq!(tenant(bound(), Some("globex")).await, Err(StatusCode::FORBIDDEN));
+    }
+
+    #[tokio::test]
+    async fn only_unbound_admins_pick_a_tenant() {
+        let user = || Some(principal(Scope::ItemsWrite, None));
+        let admin = || Some(principal(Scope::Admin, None));
+
+        assert_eq!(tenant(user(), None).await, Ok(Tenant(DEFAULT_TENANT.to_string())));
+        assert_eq!(tenant(user(), Some(DEFAULT_TENANT)).await, Ok(Tenant(DEFAULT_TENANT.to_string())));
+        assert_eq!(tenant(user(), Some("globex")).await, Err(StatusCode::FORBIDDEN));
+        assert_eq!(tenant(admin(), Some("globex")).await, Ok(Tenant("globex".to_string())));
+    }
+
+    #[tokio::test]
+    async fn the_header_picks_the_tenant_without_auth() {
+        assert_eq!(tenant(None, Some("globex")).await, Ok(Tenant("globex".to_string())));
+        assert_eq!(tenant(None, Some("not/valid")).await, Err(StatusCode::BAD_REQUEST));
+    }
+}
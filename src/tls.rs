@@ -0,0 +1,437 @@
+use std::{path::PathBuf, str::FromStr, sync::Arc};
+use anyhow::{anyhow, bail, Context};
+use redis::{ClientTlsConfig, ConnectionAddr, ConnectionInfo, IntoConnectionInfo, TlsCertificates};
+use rustls::{
+    client::{
+        danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier},
+        WebPkiServerVerifier
+    },
+    crypto::ring,
+    pki_types::{pem::PemObject, CertificateDer, PrivateKeyDer, ServerName, UnixTime},
+    CertificateError, ClientConfig, DigitallySignedStruct, RootCertStore, SignatureScheme
+};
+use tokio_postgres_rustls::MakeRustlsConnect;
+
+/// How much of the server certificate is checked
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Verify {
+    /// Encrypt only, any certificate is accepted
+    None,
+    /// The certificate must chain up to a trusted CA, whatever host it names
+    Ca,
+    /// The certificate must chain up to a trusted CA and name the host connected to
+    Full
+}
+
+impl FromStr for Verify {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(Self::None),
+            "ca" => Ok(Self::Ca),
+            "full" => Ok(Self::Full),
+            other => Err(anyhow!("Unknown TLS verify mode '{}', use none, ca or full", other))
+        }
+    }
+}
+
+/// `sslmode` as libpq and sqlx read it
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SslMode {
+    Disable,
+    Allow,
+    Prefer,
+    Require,
+    VerifyCa,
+    VerifyFull
+}
+
+impl FromStr for SslMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "disable" => Ok(Self::Disable),
+            "allow" => Ok(Self::Allow),
+            "prefer" => Ok(Self::Prefer),
+            "require" => Ok(Self::Require),
+            "verify-ca" => Ok(Self::VerifyCa),
+            "verify-full" => Ok(Self::VerifyFull),
+            other => Err(anyhow!("Unknown sslmode '{}', use disable, allow, prefer, require, verify-ca or verify-full", other))
+        }
+    }
+}
+
+impl SslMode {
+    fn verify(self) -> Verify {
+        match self {
+            Self::VerifyFull => Verify::Full,
+            Self::VerifyCa => Verify::Ca,
+            // Like sqlx, `require` encrypts without checking the certificate
+            _ => Verify::None
+        }
+    }
+}
+
+/// Where certificates come from, PEM files that may hold a chain
+#[derive(Debug, Clone, Default)]
+struct Certificates {
+    ca_file: Option<PathBuf>,
+    cert_file: Option<PathBuf>,
+    key_file: Option<PathBuf>
+}
+
+impl Certificates {
+    /// The CA file, else the Mozilla roots
+    fn roots(&self) -> anyhow::Result<RootCertStore> {
+        let Some(ca_file) = &self.ca_file else {
+            return Ok(RootCertStore { roots: webpki_roots::TLS_SERVER_ROOTS.to_vec() });
+        };
+
+        let mut roots = RootCertStore::empty();
+        for cert in CertificateDer::pem_file_iter(ca_file).with_context(|| format!("Failed to read CA bundle {}", ca_file.display()))? {
+            roots.add(cert?)?;
+        }
+
+        if roots.is_empty() {
+            bail!("CA bundle {} holds no certificate", ca_file.display());
+        }
+
+        Ok(roots)
+    }
+
+    /// The client certificate chain and its key for servers that ask for one
+    fn client_auth(&self) -> anyhow::Result<Option<(Vec<CertificateDer<'static>>, PrivateKeyDer<'static>)>> {
+        match (&self.cert_file, &self.key_file) {
+            (Some(cert_file), Some(key_file)) => {
+                let chain = CertificateDer::pem_file_iter(cert_file)
+                    .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+                    .with_context(|| format!("Failed to read client certificate {}", cert_file.display()))?;
+                let key = PrivateKeyDer::from_pem_file(key_file)
+                    .with_context(|| format!("Failed to read client key {}", key_file.display()))?;
+
+                Ok(Some((chain, key)))
+            }
+            (None, None) => Ok(None),
+            _ => bail!("A client certificate needs both its certificate and its key")
+        }
+    }
+
+    /// rustls settings for `verify`, used by the `tokio_postgres` connections
+    fn client_config(&self, verify: Verify) -> anyhow::Result<ClientConfig> {
+        let provider = Arc::new(ring::default_provider());
+        let webpki = WebPkiServerVerifier::builder_with_provider(Arc::new(self.roots()?), provider.clone()).build()?;
+
+        let builder = ClientConfig::builder_with_provider(provider)
+            .with_safe_default_protocol_versions()?
+            .dangerous()
+            .with_custom_certificate_verifier(Arc::new(Verifier { verify, webpki }));
+
+        Ok(match self.client_auth()? {
+            Some((chain, key)) => builder.with_client_auth_cert(chain, key)?,
+            None => builder.with_no_client_auth()
+        })
+    }
+}
+
+/// Checks the certificate as far as `verify` asks, handshake signatures are always checked
+#[derive(Debug)]
+struct Verifier {
+    verify: Verify,
+    webpki: Arc<WebPkiServerVerifier>
+}
+
+impl ServerCertVerifier for Verifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        server_name: &ServerName<'_>,
+        ocsp_response: &[u8],
+        now: UnixTime
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        if self.verify == Verify::None {
+            return Ok(ServerCertVerified::assertion());
+        }
+
+        match self.webpki.verify_server_cert(end_entity, intermediates, server_name, ocsp_response, now) {
+            Err(rustls::Error::InvalidCertificate(CertificateError::NotValidForName | CertificateError::NotValidForNameContext { .. }))
+                if self.verify == Verify::Ca =>
+            {
+                Ok(ServerCertVerified::assertion())
+            }
+            result => result
+        }
+    }
+
+    fn verify_tls12_signature(&self, message: &[u8], cert: &CertificateDer<'_>, dss: &DigitallySignedStruct) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.webpki.verify_tls12_signature(message, cert, dss)
+    }
+
+    fn verify_tls13_signature(&self, message: &[u8], cert: &CertificateDer<'_>, dss: &DigitallySignedStruct) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.webpki.verify_tls13_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.webpki.supported_verify_schemes()
+    }
+}
+
+fn env_path(name: &str) -> Option<PathBuf> {
+    std::env::var(name).ok().filter(|v| !v.is_empty()).map(PathBuf::from)
+}
+
+/// Config and rustls connector for a `tokio_postgres` connection to `database_url`.
+///
+/// Reads the same settings sqlx does, so one `DATABASE_URL` works for every mode: `sslmode`, `sslrootcert`,
+/// `sslcert` and `sslkey` from the URL, else `PGSSLMODE`, `PGSSLROOTCERT`, `PGSSLCERT` and `PGSSLKEY`,
+/// `prefer` when neither names a mode.
+pub fn postgres(database_url: &str) -> anyhow::Result<(tokio_postgres::Config, MakeRustlsConnect)> {
+    let mut mode = match std::env::var("PGSSLMODE") {
+        Ok(mode) => mode.parse()?,
+        Err(_) => SslMode::Prefer
+    };
+    let mut certificates = Certificates {
+        ca_file: env_path("PGSSLROOTCERT"),
+        cert_file: env_path("PGSSLCERT"),
+        key_file: env_path("PGSSLKEY")
+    };
+
+    // `tokio_postgres` doesn't know the verify modes nor the certificate options, so they are taken out of the URL
+    let (base, params) = database_url.split_once('?').unwrap_or((database_url, ""));
+    let mut kept = Vec::new();
+    for param in params.split('&').filter(|p| !p.is_empty()) {
+        match param.split_once('=') {
+            Some(("sslmode", value)) => mode = value.parse()?,
+            Some(("sslrootcert", value)) => certificates.ca_file = Some(value.into()),
+            Some(("sslcert", value)) => certificates.cert_file = Some(value.into()),
+            Some(("sslkey", value)) => certificates.key_file = Some(value.into()),
+            _ => kept.push(param)
+        }
+    }
+
+    let url = if kept.is_empty() { base.to_string() } else { format!("{}?{}", base, kept.join("&")) };
+    let mut config: tokio_postgres::Config = url.parse()?;
+    config.ssl_mode(match mode {
+        SslMode::Disable => tokio_postgres::config::SslMode::Disable,
+        SslMode::Allow | SslMode::Prefer => tokio_postgres::config::SslMode::Prefer,
+        SslMode::Require | SslMode::VerifyCa | SslMode::VerifyFull => tokio_postgres::config::SslMode::Require
+    });
+
+    Ok((config, MakeRustlsConnect::new(certificates.client_config(mode.verify())?)))
+}
+
+/// Connection info for `redis_url`, with the TLS settings applied when it is a `rediss://` URL.
+///
+/// `REDIS_TLS_CA_FILE` replaces the system roots, `REDIS_TLS_CERT_FILE` and `REDIS_TLS_KEY_FILE` are presented
+/// to servers asking for a client certificate and `REDIS_TLS_VERIFY` is `none`, `ca` or `full` (the default).
+/// They apply to every Redis URL of the process.
+pub fn redis(redis_url: &str) -> anyhow::Result<ConnectionInfo> {
+    let info = redis_url.into_connection_info()?;
+    if !matches!(info.addr, ConnectionAddr::TcpTls { .. }) {
+        return Ok(info);
+    }
+
+    let verify = match std::env::var("REDIS_TLS_VERIFY") {
+        Ok(verify) => verify.parse()?,
+        Err(_) => Verify::Full
+    };
+    let certificates = Certificates {
+        ca_file: env_path("REDIS_TLS_CA_FILE"),
+        cert_file: env_path("REDIS_TLS_CERT_FILE"),
+        key_file: env_path("REDIS_TLS_KEY_FILE")
+    };
+
+    let read = |path: &PathBuf| std::fs::read(path).with_context(|| format!("Failed to read {}", path.display()));
+    let client_tls = match (&certificates.cert_file, &certificates.key_file) {
+        (Some(cert_file), Some(key_file)) => Some(ClientTlsConfig { client_cert: read(cert_file)?, client_key: read(key_file)? }),
+        (None, None) => None,
+        _ => bail!("REDIS_TLS_CERT_FILE and REDIS_TLS_KEY_FILE have to be set together")
+    };
+    let root_cert = certificates.ca_file.as_ref().map(read).transpose()?;
+
+    let mut info = redis::Client::build_with_tls(info, TlsCertificates { client_tls, root_cert })?
+        .get_connection_info()
+        .clone();
+
+    match verify {
+        Verify::Full => {}
+        Verify::Ca => info.addr.set_danger_accept_invalid_hostnames(true),
+        Verify::None => {
+            if let ConnectionAddr::TcpTls { insecure, .. } = &mut info.addr {
+                *insecure = true;
+            }
+        }
+    }
+
+    Ok(info)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::SocketAddr, path::Path};
+    use rcgen::{BasicConstraints, CertificateParams, CertifiedKey, ExtendedKeyUsagePurpose, IsCa, KeyPair};
+    use rustls::{server::WebPkiClientVerifier, ServerConfig};
+    use tokio::{io::{AsyncReadExt, AsyncWriteExt}, net::{TcpListener, TcpStream}};
+    use tokio_rustls::{TlsAcceptor, TlsConnector};
+    use super::*;
+
+    fn ca(name: &str) -> CertifiedKey {
+        let mut params = CertificateParams::new(Vec::new()).unwrap();
+        params.distinguished_name.push(rcgen::DnType::CommonName, name);
+        params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        let key_pair = KeyPair::generate().unwrap();
+        CertifiedKey { cert: params.self_signed(&key_pair).unwrap(), key_pair }
+    }
+
+    fn leaf(names: &[&str], usage: ExtendedKeyUsagePurpose, issuer: &CertifiedKey) -> CertifiedKey {
+        let mut params = CertificateParams::new(names.iter().map(|n| n.to_string()).collect::<Vec<_>>()).unwrap();
+        params.extended_key_usages = vec![usage];
+        let key_pair = KeyPair::generate().unwrap();
+        CertifiedKey { cert: params.signed_by(&key_pair, &issuer.cert, &issuer.key_pair).unwrap(), key_pair }
+    }
+
+    /// A scratch directory holding the PEM files the settings point at
+    struct Dir(PathBuf);
+
+    impl Dir {
+        fn new(test: &str) -> Self {
+            let dir = std::env::temp_dir().join(format!("hello-axum-tls-{}-{}", test, std::process::id()));
+            std::fs::create_dir_all(&dir).unwrap();
+            Self(dir)
+        }
+
+        fn write(&self, name: &str, pem: &str) -> PathBuf {
+            let path = self.0.join(name);
+            std::fs::write(&path, pem).unwrap();
+            path
+        }
+    }
+
+    impl Drop for Dir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn der(certified: &CertifiedKey) -> (Vec<CertificateDer<'static>>, PrivateKeyDer<'static>) {
+        let key = PrivateKeyDer::try_from(certified.key_pair.serialize_der()).unwrap();
+        (vec![certified.cert.der().clone()], key)
+    }
+
+    /// Serves one TLS connection with `server`, answering `ok`, asking for a client certificate from `client_ca`
+    async fn listen(server: &CertifiedKey, client_ca: Option<&CertifiedKey>) -> SocketAddr {
+        let provider = Arc::new(ring::default_provider());
+        let builder = ServerConfig::builder_with_provider(provider.clone()).with_safe_default_protocol_versions().unwrap();
+        let builder = match client_ca {
+            Some(client_ca) => {
+                let mut roots = RootCertStore::empty();
+                roots.add(client_ca.cert.der().clone()).unwrap();
+                builder.with_client_cert_verifier(WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider).build().unwrap())
+            }
+            None => builder.with_no_client_auth()
+        };
+        let (chain, key) = der(server);
+        let acceptor = TlsAcceptor::from(Arc::new(builder.with_single_cert(chain, key).unwrap()));
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            if let Ok(mut tls) = acceptor.accept(stream).await {
+                let _ = tls.write_all(b"ok").await;
+                let _ = tls.shutdown().await;
+            }
+        });
+
+        addr
+    }
+
+    /// Connects as `host` and reads the server's answer, so a certificate refused on either side fails here
+    async fn connect(addr: SocketAddr, host: &str, certificates: &Certificates, verify: Verify) -> Result<(), String> {
+        let config = certificates.client_config(verify).map_err(|e| e.to_string())?;
+        let stream = TcpStream::connect(addr).await.map_err(|e| e.to_string())?;
+        let name = ServerName::try_from(host.to_string()).map_err(|e| e.to_string())?;
+
+        let mut tls = TlsConnector::from(Arc::new(config)).connect(name, stream).await.map_err(|e| e.to_string())?;
+        let mut answer = Vec::new();
+        tls.read_to_end(&mut answer).await.map_err(|e| e.to_string())?;
+
+        if answer == b"ok" { Ok(()) } else { Err(format!("unexpected answer {:?}", answer)) }
+    }
+
+    fn trusting(dir: &Dir, ca: &CertifiedKey) -> Certificates {
+        Certificates { ca_file: Some(dir.write("ca.pem", &ca.cert.pem())), ..Default::default() }
+    }
+
+    #[tokio::test]
+    async fn verify_full_accepts_a_trusted_certificate_for_the_host() {
+        let dir = Dir::new("full");
+        let ca = ca("test ca");
+        let server = leaf(&["localhost"], ExtendedKeyUsagePurpose::ServerAuth, &ca);
+
+        let addr = listen(&server, None).await;
+        connect(addr, "localhost", &trusting(&dir, &ca), Verify::Full).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn verify_full_rejects_another_host_which_verify_ca_accepts() {
+        let dir = Dir::new("mismatch");
+        let ca = ca("test ca");
+        let server = leaf(&["db.internal"], ExtendedKeyUsagePurpose::ServerAuth, &ca);
+        let certificates = trusting(&dir, &ca);
+
+        let err = connect(listen(&server, None).await, "localhost", &certificates, Verify::Full).await.unwrap_err();
+        assert!(err.contains("not valid for name"), "{}", err);
+
+        connect(listen(&server, None).await, "localhost", &certificates, Verify::Ca).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn rejects_an_untrusted_ca_unless_verification_is_off() {
+        let dir = Dir::new("untrusted");
+        let server = leaf(&["localhost"], ExtendedKeyUsagePurpose::ServerAuth, &ca("rogue ca"));
+        let certificates = trusting(&dir, &ca("test ca"));
+
+        for verify in [Verify::Full, Verify::Ca] {
+            let err = connect(listen(&server, None).await, "localhost", &certificates, verify).await.unwrap_err();
+            assert!(err.contains("UnknownIssuer"), "{:?}: {}", verify, err);
+        }
+
+        connect(listen(&server, None).await, "localhost", &certificates, Verify::None).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn presents_the_client_certificate() {
+        let dir = Dir::new("client");
+        let ca = ca("test ca");
+        let server = leaf(&["localhost"], ExtendedKeyUsagePurpose::ServerAuth, &ca);
+        let clients = self::ca("client ca");
+        let client = leaf(&["app"], ExtendedKeyUsagePurpose::ClientAuth, &clients);
+
+        let anonymous = trusting(&dir, &ca);
+        assert!(connect(listen(&server, Some(&clients)).await, "localhost", &anonymous, Verify::Full).await.is_err());
+
+        let authenticated = Certificates {
+            cert_file: Some(dir.write("client.pem", &client.cert.pem())),
+            key_file: Some(dir.write("client.key", &client.key_pair.serialize_pem())),
+            ..anonymous
+        };
+        connect(listen(&server, Some(&clients)).await, "localhost", &authenticated, Verify::Full).await.unwrap();
+    }
+
+    #[test]
+    fn postgres_takes_the_tls_settings_out_of_the_url() {
+        let dir = Dir::new("postgres");
+        let ca_file = dir.write("ca.pem", &ca("test ca").cert.pem());
+
+        let url = format!("postgres://app@localhost/hello?sslmode=verify-full&sslrootcert={}&application_name=api", ca_file.display());
+        let (config, _) = postgres(&url).unwrap();
+        assert_eq!(config.get_ssl_mode(), tokio_postgres::config::SslMode::Require);
+        assert_eq!(config.get_application_name(), Some("api"));
+
+        assert!(postgres("postgres://app@localhost/hello?sslmode=verify-everything").is_err());
+        assert!(postgres(&format!("postgres://app@localhost/hello?sslrootcert={}", Path::new("/nonexistent/ca.pem").display())).is_err());
+    }
+}
@@ -0,0 +1,37 @@
+use std::{fmt::Debug, future::Future, time::Duration};
+
+/// How often the purge job looks for rows that stayed in the trash past the retention window
+const PURGE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+const DEFAULT_RETENTION_HOURS: u64 = 30 * 24;
+
+/// How long deleted entries stay restorable, set with `TRASH_RETENTION_HOURS` (30 days by default)
+pub fn retention_from_env() -> Duration {
+    let hours = std::env::var("TRASH_RETENTION_HOURS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(DEFAULT_RETENTION_HOURS);
+
+    Duration::from_secs(hours * 60 * 60)
+}
+
+/// Runs `purge` every [`PURGE_INTERVAL`], handing it the retention window.
+/// Failures are logged and retried on the next tick.
+pub async fn purge_job<F, Fut, E>(retention: Duration, mut purge: F)
+where
+    F: FnMut(Duration) -> Fut,
+    Fut: Future<Output = Result<u64, E>>,
+    E: Debug
+{
+    let mut interval = tokio::time::interval(PURGE_INTERVAL);
+
+    loop {
+        interval.tick().await;
+
+        match purge(retention).await {
+            Ok(0) => {}
+            Ok(purged) => tracing::info!("Purged {} entries from the trash", purged),
+            Err(e) => tracing::error!("Trash purge failed: {:?}", e)
+        }
+    }
+}
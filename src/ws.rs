@@ -0,0 +1,432 @@
+use std::{collections::{HashMap, HashSet}, time::Duration};
+use axum::{
+    extract::{ws::{Message, WebSocket, WebSocketUpgrade}, State},
+    response::Response
+};
+use futures::{SinkExt, StreamExt};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::{sync::{broadcast::error::RecvError, mpsc}, time::Instant};
+use crate::{events::{ChangeEvent, ChangeKind, Feed}, tenant::Tenant};
+
+/// Outgoing messages a client may have queued before it is considered too slow and dropped
+const QUEUE_CAPACITY: usize = 256;
+
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(20);
+
+/// A client that hasn't answered a ping for this long is disconnected
+const HEARTBEAT_TIMEOUT: Duration = Duration::from_secs(60);
+
+#[derive(Deserialize, Debug)]
+#[serde(tag = "action", rename_all = "lowercase")]
+enum ClientMessage {
+    /// Subscribe to changes of specific ids, or of entities matching a filter like `sys = 3 and flags > 10`
+    Subscribe {
+        #[serde(default)]
+        ids: Vec<i64>,
+        filter: Option<String>
+    },
+    /// Drop one subscription, or all of them when `subscription` is left out
+    Unsubscribe {
+        subscription: Option<u64>
+    }
+}
+
+#[derive(Serialize, Debug)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum ServerMessage<'a, T> {
+    Subscribed { subscription: u64 },
+    Unsubscribed { subscription: Option<u64> },
+    Change { subscriptions: Vec<u64>, event: &'a ChangeEvent<T> },
+    Error { message: String }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Op {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge
+}
+
+#[derive(Debug, Clone)]
+struct Condition {
+    field: String,
+    op: Op,
+    value: Value
+}
+
+/// A conjunction of `field op value` conditions, e.g. `sys = 3 and name != "x"`
+#[derive(Debug, Clone)]
+pub struct Filter(Vec<Condition>);
+
+impl Filter {
+    pub fn parse(expr: &str) -> Result<Self, String> {
+        let mut conditions = Vec::new();
+
+        for part in split_and(expr) {
+            let part = part.trim();
+            let pos = part.find(['!', '<', '>', '=']).ok_or_else(|| format!("Missing operator in '{}'", part))?;
+
+            let rest = &part[pos..];
+            let (op, len) = if rest.starts_with("!=") { (Op::Ne, 2) }
+                else if rest.starts_with("<=") { (Op::Le, 2) }
+                else if rest.starts_with(">=") { (Op::Ge, 2) }
+                else if rest.starts_with('=') { (Op::Eq, 1) }
+                else if rest.starts_with('<') { (Op::Lt, 1) }
+                else if rest.starts_with('>') { (Op::Gt, 1) }
+                else { return Err(format!("Unknown operator in '{}'", part)) };
+
+            let field = part[..pos].trim();
+            let raw = part[pos + len..].trim();
+            if field.is_empty() || raw.is_empty() {
+                return Err(format!("Expected 'field op value', got '{}'", part));
+            }
+
+            let value = match raw.strip_prefix('"').and_then(|v| v.strip_suffix('"')) {
+                Some(text) => Value::String(text.to_string()),
+                None => serde_json::from_str(raw).unwrap_or_else(|_| Value::String(raw.to_string()))
+            };
+
+            conditions.push(Condition { field: field.to_string(), op, value });
+        }
+
+        if conditions.is_empty() {
+            return Err("Empty filter".to_string());
+        }
+
+        Ok(Self(conditions))
+    }
+
+    pub fn matches(&self, entity: &Value) -> bool {
+        self.0.iter().all(|cond| {
+            let Some(actual) = entity.get(&cond.field) else { return false };
+
+            let ordering = match (actual, &cond.value) {
+                (Value::Number(a), Value::Number(b)) => a.as_f64().zip(b.as_f64()).and_then(|(a, b)| a.partial_cmp(&b)),
+                (Value::String(a), Value::String(b)) => Some(a.cmp(b)),
+                (a, b) => (a == b).then_some(std::cmp::Ordering::Equal)
+            };
+
+            match (cond.op, ordering) {
+                (Op::Ne, ordering) => ordering != Some(std::cmp::Ordering::Equal),
+                (_, None) => false,
+                (Op::Eq, Some(o)) => o.is_eq(),
+                (Op::Lt, Some(o)) => o.is_lt(),
+                (Op::Le, Some(o)) => o.is_le(),
+                (Op::Gt, Some(o)) => o.is_gt(),
+                (Op::Ge, Some(o)) => o.is_ge()
+            }
+        })
+    }
+}
+
+/// Splits on the `and` keyword outside of quoted strings.
+///
+/// Works on bytes, `i` may sit inside a multi-byte character where slicing the string would panic.
+/// A match starts with a space, so the split points are always character boundaries.
+fn split_and(expr: &str) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut start = 0;
+    let mut in_quotes = false;
+    let bytes = expr.as_bytes();
+
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'"' {
+            in_quotes = !in_quotes;
+        }
+        else if !in_quotes && bytes.get(i..i + 5).is_some_and(|word| word.eq_ignore_ascii_case(b" and ")) {
+            parts.push(&expr[start..i]);
+            i += 5;
+            start = i;
+            continue;
+        }
+        i += 1;
+    }
+    parts.push(&expr[start..]);
+
+    parts
+}
+
+enum Subscription {
+    Ids(HashSet<i64>),
+    Filter(Filter)
+}
+
+/// GET /ws - Live change subscriptions over a WebSocket, limited to the caller's tenant
+pub async fn handler<T>(ws: WebSocketUpgrade, State(feed): State<Feed<T>>, tenant: Tenant) -> Response
+where
+    T: Serialize + Clone + Send + Sync + 'static
+{
+    ws.on_upgrade(move |socket| session(socket, feed, tenant))
+}
+
+async fn session<T>(socket: WebSocket, feed: Feed<T>, tenant: Tenant)
+where
+    T: Serialize + Clone + Send + Sync + 'static
+{
+    let (mut sink, mut incoming) = socket.split();
+    let (queue, mut outgoing) = mpsc::channel::<Message>(QUEUE_CAPACITY);
+
+    let writer = tokio::spawn(async move {
+        while let Some(msg) = outgoing.recv().await {
+            if sink.send(msg).await.is_err() {
+                break;
+            }
+        }
+        let _ = sink.close().await;
+    });
+
+    let mut changes = feed.subscribe();
+    let mut heartbeat = tokio::time::interval(HEARTBEAT_INTERVAL);
+    let mut last_pong = Instant::now();
+    let mut subscriptions: HashMap<u64, Subscription> = HashMap::new();
+    let mut next_subscription = 1u64;
+
+    // Ids whose last state matched a filter, so their deletion (which carries no data to filter on) still reaches the client
+    let mut filtered_ids: HashSet<i64> = HashSet::new();
+
+    loop {
+        let reply = tokio::select! {
+            msg = incoming.next() => {
+                match msg {
+                    Some(Ok(Message::Text(text))) => {
+                        handle_client_message::<T>(text.as_str(), &mut subscriptions, &mut next_subscription)
+                    }
+                    Some(Ok(Message::Pong(_))) => {
+                        last_pong = Instant::now();
+                        None
+                    }
+                    Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                    Some(Ok(_)) => None
+                }
+            }
+            change = changes.recv() => {
+                match change {
+                    Ok(event) if !tenant.owns(event.tenant.as_deref()) => None,
+                    Ok(event) => {
+                        let matched = matching_subscriptions(&event, &subscriptions, &mut filtered_ids);
+
+                        if matched.is_empty() {
+                            None
+                        }
+                        else {
+                            serde_json::to_string(&ServerMessage::Change { subscriptions: matched, event: &event }).ok()
+                        }
+                    }
+                    Err(RecvError::Lagged(missed)) => {
+                        tracing::warn!("Disconnecting WebSocket client that fell {} events behind", missed);
+                        break;
+                    }
+                    Err(RecvError::Closed) => break
+                }
+            }
+            _ = heartbeat.tick() => {
+                if last_pong.elapsed() > HEARTBEAT_TIMEOUT {
+                    tracing::info!("Disconnecting unresponsive WebSocket client");
+                    break;
+                }
+
+                if queue.try_send(Message::Ping(Default::default())).is_err() {
+                    tracing::warn!("Disconnecting slow WebSocket consumer");
+                    break;
+                }
+                None
+            }
+        };
+
+        if let Some(text) = reply
+            && queue.try_send(Message::Text(text.into())).is_err()
+        {
+            tracing::warn!("Disconnecting slow WebSocket consumer");
+            break;
+        }
+    }
+
+    // A slow consumer isn't drained, its queue is dropped along with the connection
+    writer.abort();
+}
+
+fn handle_client_message<T: Serialize>(
+    text: &str,
+    subscriptions: &mut HashMap<u64, Subscription>,
+    next_subscription: &mut u64
+) -> Option<String> {
+    let reply: ServerMessage<'_, T> = match serde_json::from_str::<ClientMessage>(text) {
+        Ok(ClientMessage::Subscribe { ids, filter }) => {
+            let subscription = match filter {
+                Some(expr) => Filter::parse(&expr).map(Subscription::Filter),
+                None if !ids.is_empty() => Ok(Subscription::Ids(ids.into_iter().collect())),
+                None => Err("Subscribe needs 'ids' or a 'filter'".to_string())
+            };
+
+            match subscription {
+                Ok(subscription) => {
+                    let id = *next_subscription;
+                    *next_subscription += 1;
+                    subscriptions.insert(id, subscription);
+
+                    ServerMessage::Subscribed { subscription: id }
+                }
+                Err(message) => ServerMessage::Error { message }
+            }
+        }
+        Ok(ClientMessage::Unsubscribe { subscription }) => {
+            match subscription {
+                Some(id) => {
+                    subscriptions.remove(&id);
+                }
+                None => subscriptions.clear()
+            }
+
+            ServerMessage::Unsubscribed { subscription }
+        }
+        Err(e) => ServerMessage::Error { message: format!("Invalid message: {}", e) }
+    };
+
+    serde_json::to_string(&reply).ok()
+}
+
+fn matching_subscriptions<T: Serialize>(
+    event: &ChangeEvent<T>,
+    subscriptions: &HashMap<u64, Subscription>,
+    filtered_ids: &mut HashSet<i64>
+) -> Vec<u64> {
+    let entity = event.data.as_ref().and_then(|data| serde_json::to_value(data).ok());
+    let mut matched = Vec::new();
+    let mut filter_hit = false;
+
+    for (&id, subscription) in subscriptions {
+        let hit = match subscription {
+            Subscription::Ids(ids) => ids.contains(&event.id),
+            Subscription::Filter(filter) => {
+                let hit = match &entity {
+                    Some(entity) => filter.matches(entity),
+                    None => filtered_ids.contains(&event.id)
+                };
+                filter_hit |= hit;
+                hit
+            }
+        };
+
+        if hit {
+            matched.push(id);
+        }
+    }
+
+    // An entity that no longer matches any filter is forgotten, its later changes only reach `Ids` subscriptions
+    if event.kind == ChangeKind::Deleted || !filter_hit {
+        filtered_ids.remove(&event.id);
+    }
+    else {
+        filtered_ids.insert(event.id);
+    }
+
+    matched.sort_unstable();
+    matched
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use super::*;
+
+    fn event(kind: ChangeKind, id: i64, data: Option<Value>) -> ChangeEvent<Value> {
+        ChangeEvent { kind, id, data, tenant: None }
+    }
+
+    #[test]
+    fn and_splits_outside_quotes_only() {
+        assert_eq!(split_and("sys = 3 and flags > 10"), ["sys = 3", "flags > 10"]);
+        assert_eq!(split_and("sys = 3 AND flags > 10"), ["sys = 3", "flags > 10"]);
+        assert_eq!(split_and(r#"name = "salt and pepper" and sys = 1"#), [r#"name = "salt and pepper""#, "sys = 1"]);
+        assert_eq!(split_and("name = brand"), ["name = brand"]);
+    }
+
+    #[test]
+    fn non_ascii_filters_split_without_panicking() {
+        assert_eq!(split_and("name = café and sys = 3"), ["name = café", "sys = 3"]);
+        assert_eq!(split_and("name = 日本語 and sys = 3"), ["name = 日本語", "sys = 3"]);
+        assert_eq!(split_and("name = é"), ["name = é"]);
+
+        let filter = Filter::parse("name = café and sys = 3").unwrap();
+        assert!(filter.matches(&json!({ "name": "café", "sys": 3 })));
+    }
+
+    #[test]
+    fn parse_reads_operators_and_values() {
+        let filter = Filter::parse(r#"sys >= 3 and flags != 1 and name = "a and b" and kind < z"#).unwrap();
+        let ops: Vec<Op> = filter.0.iter().map(|c| c.op).collect();
+
+        assert_eq!(ops, [Op::Ge, Op::Ne, Op::Eq, Op::Lt]);
+        assert_eq!(filter.0[0].value, json!(3));
+        assert_eq!(filter.0[2].value, json!("a and b"));
+        assert_eq!(filter.0[3].value, json!("z"));
+
+        assert!(Filter::parse("").is_err());
+        assert!(Filter::parse("sys 3").is_err());
+        assert!(Filter::parse("= 3").is_err());
+        assert!(Filter::parse("sys =").is_err());
+    }
+
+    #[test]
+    fn matches_compares_numbers_and_strings() {
+        let entity = json!({ "name": "beta", "sys": 3, "flags": 12 });
+
+        assert!(Filter::parse("sys = 3 and flags > 10").unwrap().matches(&entity));
+        assert!(Filter::parse("sys <= 3.5").unwrap().matches(&entity));
+        assert!(Filter::parse("name > alpha").unwrap().matches(&entity));
+        assert!(Filter::parse("name != gamma").unwrap().matches(&entity));
+        assert!(!Filter::parse("sys = 3 and flags < 10").unwrap().matches(&entity));
+        assert!(!Filter::parse("missing = 1").unwrap().matches(&entity));
+        assert!(!Filter::parse("name > 1").unwrap().matches(&entity));
+    }
+
+    #[test]
+    fn entities_leaving_a_filter_are_forgotten() {
+        let subscriptions = HashMap::from([(1, Subscription::Filter(Filter::parse("sys = 3").unwrap()))]);
+        let mut filtered = HashSet::new();
+
+        let matched = matching_subscriptions(&event(ChangeKind::Created, 7, Some(json!({ "sys": 3 }))), &subscriptions, &mut filtered);
+        assert_eq!(matched, [1]);
+        assert!(filtered.contains(&7));
+
+        let matched = matching_subscriptions(&event(ChangeKind::Updated, 7, Some(json!({ "sys": 4 }))), &subscriptions, &mut filtered);
+        assert!(matched.is_empty());
+        assert!(filtered.is_empty());
+
+        let matched = matching_subscriptions(&event(ChangeKind::Deleted, 7, None), &subscriptions, &mut filtered);
+        assert!(matched.is_empty());
+    }
+
+    #[test]
+    fn deletes_reach_filters_the_entity_matched() {
+        let subscriptions = HashMap::from([(1, Subscription::Filter(Filter::parse("sys = 3").unwrap()))]);
+        let mut filtered = HashSet::new();
+
+        matching_subscriptions(&event(ChangeKind::Updated, 7, Some(json!({ "sys": 3 }))), &subscriptions, &mut filtered);
+        let matched = matching_subscriptions(&event(ChangeKind::Deleted, 7, None), &subscriptions, &mut filtered);
+
+        assert_eq!(matched, [1]);
+        assert!(filtered.is_empty());
+    }
+
+    #[test]
+    fn ids_subscriptions_are_not_tracked_as_filtered() {
+        let subscriptions = HashMap::from([
+            (1, Subscription::Ids(HashSet::from([7]))),
+            (2, Subscription::Filter(Filter::parse("sys = 3").unwrap()))
+        ]);
+        let mut filtered = HashSet::new();
+
+        let matched = matching_subscriptions(&event(ChangeKind::Updated, 7, Some(json!({ "sys": 4 }))), &subscriptions, &mut filtered);
+        assert_eq!(matched, [1]);
+        assert!(filtered.is_empty());
+
+        let matched = matching_subscriptions(&event(ChangeKind::Deleted, 7, None), &subscriptions, &mut filtered);
+        assert_eq!(matched, [1]);
+    }
+}